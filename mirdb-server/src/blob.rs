@@ -0,0 +1,278 @@
+use std::fs::remove_file;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use bincode::deserialize;
+use bincode::serialize;
+use crc::crc32;
+use crc::crc32::Hasher32;
+use integer_encoding::FixedInt;
+use log::error;
+use memmap::Mmap;
+use serde::{Deserialize, Serialize};
+
+use crate::error::err;
+use crate::error::MyResult;
+use crate::error::StatusCode;
+use crate::options::Options;
+use crate::slice::Slice;
+use crate::store::StorePayload;
+use crate::utils::make_file_name;
+
+const CHECKSUM_SIZE: usize = 4;
+
+fn checksum(data: &[u8]) -> u32 {
+    let mut digest = crc32::Digest::new(crc32::CASTAGNOLI);
+    digest.write(data);
+    digest.sum32()
+}
+
+pub fn blob_file_name(number: usize) -> String {
+    make_file_name(number, "blob")
+}
+
+/// Where a value separated from its key is, an sstable holds it in place
+/// of the value.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BlobIndex {
+    pub file_number: usize,
+    pub offset: u64,
+    pub size: u64,
+    /// When the value expires, 0 if it never does, so that compactions drop
+    /// the expired values without reading them.
+    pub expires_at: u64,
+}
+
+impl BlobIndex {
+    pub fn encode(&self) -> MyResult<Slice> {
+        Ok(Slice::from(serialize(self)?))
+    }
+
+    pub fn decode(v: &[u8]) -> MyResult<Self> {
+        Ok(deserialize(v)?)
+    }
+
+    /// The bytes the value takes in its blob file.
+    pub fn record_size(&self) -> u64 {
+        self.size + CHECKSUM_SIZE as u64
+    }
+
+    pub fn is_expired(&self) -> bool {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        self.expires_at != 0 && self.expires_at <= now
+    }
+}
+
+/// Appends the values to a new blob file, each record is laid out as
+/// `value | crc32 (4 bytes)`. The file is never written again once it is
+/// finished.
+pub struct BlobWriter {
+    number_: usize,
+    path_: PathBuf,
+    writer_: BufWriter<File>,
+    size_: u64,
+}
+
+impl BlobWriter {
+    pub fn new(opt: &Options, number: usize) -> MyResult<Self> {
+        let path = Path::new(&opt.work_dir).join(blob_file_name(number));
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(&path)?;
+        Ok(BlobWriter {
+            number_: number,
+            path_: path,
+            writer_: BufWriter::new(file),
+            size_: 0,
+        })
+    }
+
+    pub fn number(&self) -> usize {
+        self.number_
+    }
+
+    pub fn add(&mut self, v: &[u8], expires_at: u64) -> MyResult<BlobIndex> {
+        let mut crc = [0; CHECKSUM_SIZE];
+        checksum(v).encode_fixed(&mut crc);
+        self.writer_.write_all(v)?;
+        self.writer_.write_all(&crc)?;
+        let index = BlobIndex {
+            file_number: self.number_,
+            offset: self.size_,
+            size: v.len() as u64,
+            expires_at,
+        };
+        self.size_ += index.record_size();
+        Ok(index)
+    }
+
+    /// Adds an encoded `StorePayload`, returns the encoded index which
+    /// stands for it in the sstable.
+    pub fn add_value(&mut self, v: &[u8]) -> MyResult<Slice> {
+        let payload: StorePayload = deserialize(v)?;
+        self.add(v, payload.expires_at())?.encode()
+    }
+
+    pub fn finish(mut self) -> MyResult<BlobFile> {
+        self.writer_.flush()?;
+        self.writer_.get_ref().sync_all()?;
+        BlobFile::open_path(self.number_, &self.path_, 0)
+    }
+}
+
+/// A blob file referenced by one or more versions. Like an sstable, it is
+/// removed from the disk once it is marked obsolete and the last version
+/// using it is gone.
+pub struct BlobFile {
+    number_: usize,
+    path_: PathBuf,
+    // `None` for an empty file, which can't be mapped
+    mmap_: Option<Mmap>,
+    size_: u64,
+    // the bytes of the values no sstable of the current version points to
+    stale_: AtomicU64,
+    obsolete_: AtomicBool,
+}
+
+impl BlobFile {
+    pub fn open(opt: &Options, number: usize, stale: u64) -> MyResult<Self> {
+        let path = Path::new(&opt.work_dir).join(blob_file_name(number));
+        BlobFile::open_path(number, &path, stale)
+    }
+
+    fn open_path(number: usize, path: &Path, stale: u64) -> MyResult<Self> {
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        let mmap = if size > 0 {
+            Some(unsafe { Mmap::map(&file)? })
+        } else {
+            None
+        };
+        Ok(BlobFile {
+            number_: number,
+            path_: path.to_path_buf(),
+            mmap_: mmap,
+            size_: size,
+            stale_: AtomicU64::new(stale),
+            obsolete_: AtomicBool::new(false),
+        })
+    }
+
+    pub fn number(&self) -> usize {
+        self.number_
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size_
+    }
+
+    pub fn stale(&self) -> u64 {
+        self.stale_.load(Relaxed)
+    }
+
+    pub fn add_stale(&self, bytes: u64) {
+        self.stale_.fetch_add(bytes, Relaxed);
+    }
+
+    /// The share of the file a garbage collection would reclaim.
+    pub fn stale_ratio(&self) -> f64 {
+        if self.size_ == 0 {
+            return 1.;
+        }
+        self.stale() as f64 / self.size_ as f64
+    }
+
+    pub fn read(&self, index: &BlobIndex) -> MyResult<Slice> {
+        assert_eq!(self.number_, index.file_number);
+        let start = index.offset as usize;
+        let end = start + index.record_size() as usize;
+        let data = match &self.mmap_ {
+            Some(mmap) if end <= mmap.len() => &mmap[start..end],
+            _ => return err(StatusCode::BlobError, "blob index out of the file"),
+        };
+        let (v, crc) = data.split_at(index.size as usize);
+        if checksum(v) != u32::decode_fixed(crc) {
+            return err(StatusCode::ChecksumError, "blob checksum mismatch");
+        }
+        Ok(Slice::from(v))
+    }
+
+    pub fn mark_obsolete(&self) {
+        self.obsolete_.store(true, Relaxed);
+    }
+}
+
+impl Drop for BlobFile {
+    fn drop(&mut self) {
+        if self.obsolete_.load(Relaxed) {
+            if let Err(e) = remove_file(&self.path_) {
+                error!("remove obsolete blob file {:?} error: {}", self.path_, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::test_utils::get_test_opt;
+
+    use super::*;
+
+    #[test]
+    fn test_read() -> MyResult<()> {
+        let opt = get_test_opt();
+        let mut writer = BlobWriter::new(&opt, 3)?;
+        let a = writer.add(b"hello", 0)?;
+        let b = writer.add(&[7; 1000], 42)?;
+        let blob = writer.finish()?;
+
+        assert_eq!(3, blob.number());
+        assert_eq!(a.record_size() + b.record_size(), blob.size());
+        assert_eq!(Slice::from("hello"), blob.read(&a)?);
+        assert_eq!(Slice::from(vec![7; 1000]), blob.read(&b)?);
+        assert_eq!(b, BlobIndex::decode(&b.encode()?[..])?);
+        assert!(b.is_expired());
+        assert!(!a.is_expired());
+
+        let mut bad = b;
+        bad.offset += 1;
+        assert!(blob.read(&bad).is_err());
+        bad.offset = blob.size();
+        assert!(blob.read(&bad).is_err());
+
+        blob.add_stale(a.record_size());
+        assert_eq!(a.record_size(), blob.stale());
+        assert!(blob.stale_ratio() < 0.1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_obsolete() -> MyResult<()> {
+        let opt = get_test_opt();
+        let mut writer = BlobWriter::new(&opt, 1)?;
+        writer.add(b"v", 0)?;
+        drop(writer.finish()?);
+        let path = Path::new(&opt.work_dir).join(blob_file_name(1));
+        assert!(path.exists());
+
+        let blob = BlobFile::open(&opt, 1, 0)?;
+        blob.mark_obsolete();
+        drop(blob);
+        assert!(!path.exists());
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+use bincode::serialize;
+
+use crate::error::MyResult;
+use crate::internal_key::ValueType;
+use crate::slice::Slice;
+use crate::store::StorePayload;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterDecision {
+    Keep,
+    Remove,
+    ChangeValue(StorePayload),
+}
+
+/// Decides what becomes of the values written out by the memtable flushes
+/// and the compactions. It only sees the values no snapshot can read, so
+/// the snapshots keep a consistent view.
+pub trait CompactionFilter: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// `level` is the level the value is written to, 0 for a flush.
+    fn filter(&self, level: usize, key: &[u8], value: &StorePayload) -> FilterDecision;
+}
+
+/// Runs `filter` on a value entry and returns the entry to write in its
+/// place, if any. A removed value becomes a tombstone, so that it keeps
+/// hiding the older entries of its user key.
+pub fn apply_filter(
+    filter: &dyn CompactionFilter,
+    level: usize,
+    key: &[u8],
+    value: &StorePayload,
+) -> MyResult<Option<(ValueType, Slice)>> {
+    match filter.filter(level, key, value) {
+        FilterDecision::Keep => Ok(None),
+        FilterDecision::Remove => Ok(Some((ValueType::Deletion, Slice::new()))),
+        FilterDecision::ChangeValue(value) => {
+            Ok(Some((ValueType::Value, Slice::from(serialize(&value)?))))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    use crate::data_manager::DataManager;
+    use crate::test_utils::get_test_opt;
+
+    use super::*;
+
+    /// Purges the keys of the tenant `t1` and migrates the `old` values.
+    #[derive(Default)]
+    struct TenantFilter {
+        levels_: Mutex<Vec<usize>>,
+    }
+
+    impl CompactionFilter for TenantFilter {
+        fn name(&self) -> &str {
+            "tenant"
+        }
+
+        fn filter(&self, level: usize, key: &[u8], value: &StorePayload) -> FilterDecision {
+            self.levels_.lock().unwrap().push(level);
+            if key.starts_with(b"t1:") {
+                FilterDecision::Remove
+            } else if &value.data[..] == b"old" {
+                FilterDecision::ChangeValue(make_payload(b"new"))
+            } else {
+                FilterDecision::Keep
+            }
+        }
+    }
+
+    fn make_payload(v: &[u8]) -> StorePayload {
+        StorePayload::new(Slice::from(v), 0, 0, 0, 0)
+    }
+
+    #[test]
+    fn test_filter() -> MyResult<()> {
+        let filter = Arc::new(TenantFilter::default());
+        let mut opt = get_test_opt();
+        opt.mem_table_max_size = 20;
+        opt.l0_compaction_trigger = 1;
+        opt.compaction_filter = Some(filter.clone());
+
+        let a = Slice::from("t1:a");
+        let b = Slice::from("t1:b");
+        let c = Slice::from("t2:c");
+
+        let dm = DataManager::new(opt)?;
+        dm.insert(a.clone(), make_payload(b"old"))?;
+        dm.insert(c.clone(), make_payload(b"old"))?;
+        let snapshot = dm.snapshot();
+        dm.insert(b.clone(), make_payload(b"old"))?;
+
+        // the flush leaves alone the values the snapshot sees
+        dm.minor_compaction()?;
+        assert_eq!(vec![0], *filter.levels_.lock().unwrap());
+        assert_eq!(None, dm.get(&b)?);
+        assert_eq!(Some(make_payload(b"old")), dm.get(&a)?);
+        assert_eq!(Some(make_payload(b"old")), dm.get_at(&snapshot, &c)?);
+
+        drop(snapshot);
+        dm.major_compaction()?;
+        assert_eq!(vec![0, 1, 1], *filter.levels_.lock().unwrap());
+        assert_eq!(None, dm.get(&a)?);
+        assert_eq!(None, dm.get(&b)?);
+        assert_eq!(Some(make_payload(b"new")), dm.get(&c)?);
+        Ok(())
+    }
+}
@@ -20,13 +20,34 @@ pub struct Config {
     pub sst_max_size: String,
     pub mem_table_max_size: String,
     pub mem_table_max_height: usize,
+    #[serde(default)]
+    pub mem_table_height_probability: Option<f64>,
+    #[serde(default)]
+    pub mem_table_height_seed: Option<u64>,
     pub imm_mem_table_max_count: usize,
+    #[serde(default)]
+    pub row_cache_size: Option<String>,
+    #[serde(default)]
+    pub blob_value_threshold: Option<String>,
+    #[serde(default)]
+    pub blob_gc_ratio: Option<f64>,
     pub block_size: String,
     pub block_restart_interval: usize,
 
     pub l0_compaction_trigger: usize,
 
     pub thread_sleep_ms: usize,
+    #[serde(default)]
+    pub flush_threads: Option<usize>,
+    #[serde(default)]
+    pub compaction_threads: Option<usize>,
+
+    #[serde(default)]
+    pub manifest_max_size: Option<String>,
+
+    /// How long a shutdown may take before the process exits anyway.
+    #[serde(default)]
+    pub shutdown_timeout_ms: Option<u64>,
 }
 
 impl Config {
@@ -37,11 +58,42 @@ impl Config {
         opt.sst_max_size = parse_size(self.sst_max_size.as_bytes())?;
         opt.mem_table_max_size = parse_size(self.mem_table_max_size.as_bytes())?;
         opt.mem_table_max_height = self.mem_table_max_height;
+        if let Some(x) = self.mem_table_height_probability {
+            if x <= 0.0 || x >= 1.0 {
+                return err(
+                    StatusCode::ConfigError,
+                    "mem_table_height_probability must be in (0, 1)",
+                );
+            }
+            opt.mem_table_height_probability = x;
+        }
+        opt.mem_table_height_seed = self.mem_table_height_seed;
         opt.imm_mem_table_max_count = self.imm_mem_table_max_count;
+        if let Some(ref x) = self.row_cache_size {
+            opt.row_cache_size = parse_size(x.as_bytes())?;
+        }
+        if let Some(ref x) = self.blob_value_threshold {
+            opt.blob_value_threshold = parse_size(x.as_bytes())?;
+        }
+        if let Some(x) = self.blob_gc_ratio {
+            if x <= 0.0 || x > 1.0 {
+                return err(StatusCode::ConfigError, "blob_gc_ratio must be in (0, 1]");
+            }
+            opt.blob_gc_ratio = x;
+        }
         opt.table_opt.block_size = parse_size(self.block_size.as_bytes())?;
         opt.table_opt.block_restart_interval = self.block_restart_interval;
         opt.l0_compaction_trigger = self.l0_compaction_trigger;
         opt.thread_sleep_ms = self.thread_sleep_ms;
+        if let Some(x) = self.flush_threads {
+            opt.flush_threads = x;
+        }
+        if let Some(x) = self.compaction_threads {
+            opt.compaction_threads = x;
+        }
+        if let Some(ref x) = self.manifest_max_size {
+            opt.manifest_max_size = parse_size(x.as_bytes())?;
+        }
         Ok(opt)
     }
 }
@@ -108,8 +160,13 @@ work_dir = "/tmp/mirdbs"
 sst_max_size = "100M"
 mem_table_max_size = "4M"
 mem_table_max_height = 32
+mem_table_height_probability = 0.25
+mem_table_height_seed = 42
 
 imm_mem_table_max_count = 16
+row_cache_size = "8M"
+blob_value_threshold = "4K"
+blob_gc_ratio = 0.25
 
 block_size = "4K"
 block_restart_interval = 16
@@ -117,6 +174,11 @@ block_restart_interval = 16
 l0_compaction_trigger = 4
 
 thread_sleep_ms = 500
+compaction_threads = 2
+
+manifest_max_size = "2M"
+
+shutdown_timeout_ms = 10000
 "#;
 
         let config: Config = toml::from_str(toml_str).unwrap();
@@ -127,11 +189,20 @@ thread_sleep_ms = 500
         assert_eq!(100 * MB, opt.sst_max_size);
         assert_eq!(4 * MB, opt.mem_table_max_size);
         assert_eq!(32, opt.mem_table_max_height);
+        assert_eq!(0.25, opt.mem_table_height_probability);
+        assert_eq!(Some(42), opt.mem_table_height_seed);
         assert_eq!(16, opt.imm_mem_table_max_count);
+        assert_eq!(8 * MB, opt.row_cache_size);
+        assert_eq!(4 * KB, opt.blob_value_threshold);
+        assert_eq!(0.25, opt.blob_gc_ratio);
         assert_eq!(4 * KB, opt.table_opt.block_size);
         assert_eq!(16, opt.table_opt.block_restart_interval);
         assert_eq!(4, opt.l0_compaction_trigger);
         assert_eq!(500, opt.thread_sleep_ms);
+        assert_eq!(1, opt.flush_threads);
+        assert_eq!(2, opt.compaction_threads);
+        assert_eq!(2 * MB, opt.manifest_max_size);
+        assert_eq!(Some(10000), config.shutdown_timeout_ms);
 
         Ok(())
     }
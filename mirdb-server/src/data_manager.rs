@@ -1,10 +1,18 @@
+use log::error;
 use log::info;
 use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::fmt::Debug;
+use std::mem;
 use std::path::Path;
+use std::sync::atomic::AtomicBool;
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering::Relaxed;
 use std::sync::Arc;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
 use std::sync::RwLock;
 use std::sync::RwLockWriteGuard;
 use std::thread;
@@ -16,34 +24,144 @@ use bincode::serialize;
 use serde::Deserialize;
 use serde::Serialize;
 
+use sstable::Comparator;
 use sstable::SsIterator;
-use sstable::TableBuilder;
 use sstable::TableReader;
 
+use crate::blob::BlobIndex;
+use crate::blob::BlobWriter;
+use crate::compaction_filter::apply_filter;
+use crate::db_iterator::DbIterator;
+use crate::db_iterator::ReadOptions;
+use crate::error::err;
 use crate::error::MyResult;
+use crate::error::StatusCode;
+use crate::internal_key::make_internal_key;
+use crate::internal_key::make_lookup_key;
+use crate::internal_key::parse_internal_key;
+use crate::internal_key::user_key;
+use crate::internal_key::InternalKeyComparator;
+use crate::internal_key::SeqNum;
+use crate::internal_key::ValueType;
+use crate::internal_key::MAX_SEQ_NUM;
+use crate::manifest::VersionEdit;
+use crate::manifest::FORMAT_VERSION;
 use crate::memtable::Memtable;
+use crate::memtable::MemtableIter;
 use crate::memtable_list::MemtableList;
+use crate::merge_operator::MergeContext;
+use crate::merge_operator::MergeOperator;
 use crate::merger::Merger;
+use crate::migration::upgrade_format;
 use crate::options::Options;
+use crate::range_del::RangeTombstone;
+use crate::range_del::RangeTombstones;
+use crate::row_cache::RowCache;
+use crate::scheduler::Scheduler;
 use crate::slice::Slice;
-use crate::sstable_reader::SstableReader;
+use crate::snapshot::Snapshot;
+use crate::snapshot::SnapshotList;
+use crate::sstable_builder::SstableBuilder;
+use crate::stats::Stats;
 use crate::store::StoreKey;
 use crate::store::StorePayload;
 use crate::types::Table;
+use crate::utils::key_stripe;
 use crate::utils::make_file_name;
 use crate::utils::read_lock;
 use crate::utils::to_str;
 use crate::utils::write_lock;
+use crate::version::TableFile;
+use crate::version::Version;
+use crate::version_set::VersionSet;
 use crate::wal::WAL;
 
+/// The number of locks the writes of the user keys are serialized on.
+const KEY_LOCK_STRIPES: usize = 64;
+
+/// How long a write is delayed by a slowdown.
+const SLOWDOWN_DELAY: Duration = Duration::from_millis(1);
+
+/// How long a stopped writer sleeps before it checks again, should it miss
+/// the wake up of the background work.
+const STOP_WAIT: Duration = Duration::from_millis(100);
+
+fn add_stale(stale: &mut HashMap<usize, u64>, index: &BlobIndex) {
+    *stale.entry(index.file_number).or_insert(0) += index.record_size();
+}
+
+/// Whether an entry of `file` points into the blob file `number`.
+fn points_into(file: &TableFile, number: usize) -> MyResult<bool> {
+    let mut iter = file.iter();
+    while let Some((k, v)) = iter.next() {
+        if let Some(parsed) = parse_internal_key(&k) {
+            if parsed.value_type == ValueType::BlobIndex
+                && BlobIndex::decode(&v)?.file_number == number
+            {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// The oldest of `snapshots` which sees an entry of the sequence number, a
+/// range tombstone not newer than it hides the entry from every snapshot
+/// which sees the entry.
+fn visible_bound(snapshots: &[SeqNum], seq: SeqNum) -> SeqNum {
+    match snapshots.binary_search(&seq) {
+        Ok(i) => snapshots[i],
+        Err(i) => snapshots.get(i).cloned().unwrap_or(MAX_SEQ_NUM),
+    }
+}
+
+/// The newest range tombstone visible at `seq` which covers `key`, in the
+/// memtables or in `version`.
+fn covering_seq(
+    muttable: &Memtable<Slice, Slice, InternalKeyComparator>,
+    immuttable: &MemtableList<Slice, Slice, InternalKeyComparator>,
+    version: &Version,
+    key: &[u8],
+    seq: SeqNum,
+) -> Option<SeqNum> {
+    let seqs = [
+        muttable.covering_seq(key, seq),
+        immuttable.covering_seq(key, seq),
+        version.covering_seq(key, seq),
+    ];
+    seqs.iter().filter_map(|x| *x).max()
+}
+
+/// The entries a compaction gathered for a merge.
+enum MergeOutcome {
+    /// Merged into one entry, which stands for the entries down to the
+    /// sequence number.
+    Merged(ValueType, Slice, SeqNum),
+    /// Kept as they are.
+    Unmerged(Vec<(Vec<u8>, Vec<u8>)>),
+}
+
 pub struct DataManager {
-    mut_: Arc<RwLock<Memtable<Slice, Slice>>>,
-    imm_: Arc<RwLock<MemtableList<Slice, Slice>>>,
-    readers_: Arc<RwLock<SstableReader>>,
+    // swapped for a new one when full, the sealed one goes to `imm_` as is
+    mut_: RwLock<Arc<Memtable<Slice, Slice, InternalKeyComparator>>>,
+    imm_: Arc<RwLock<MemtableList<Slice, Slice, InternalKeyComparator>>>,
+    versions_: Arc<VersionSet>,
     wal_: Arc<RwLock<WAL>>,
     opt_: Options,
-    next_file_number_: AtomicUsize,
+    next_file_number_: Arc<AtomicUsize>,
     last_compact_keys_: Vec<Vec<u8>>,
+    compaction_lock_: Mutex<()>,
+    flush_lock_: Mutex<()>,
+    snapshots_: Arc<SnapshotList>,
+    stats_: Stats,
+    row_cache_: Option<RowCache>,
+    key_locks_: Vec<Mutex<()>>,
+    // writers only stall when the background threads are there to unblock
+    // them
+    stall_writes_: AtomicBool,
+    background_lock_: Mutex<()>,
+    background_cv_: Condvar,
+    scheduler_: RwLock<Option<Scheduler>>,
 }
 
 unsafe impl Sync for DataManager {}
@@ -51,49 +169,100 @@ unsafe impl Send for DataManager {}
 
 impl DataManager {
     pub fn new(opt: Options) -> MyResult<Arc<Self>> {
-        let readers_ = Arc::new(RwLock::new(SstableReader::new(opt.clone())?));
-        let next_file_number = {
-            let readers = read_lock(&readers_);
-            readers.manifest_builder().next_file_number()
-        };
+        let versions_ = Arc::new(VersionSet::new(opt.clone())?);
+        if versions_.format_version() < FORMAT_VERSION {
+            upgrade_format(&opt, &versions_)?;
+        }
+        let next_file_number = versions_.file_number_allocator();
+        let log_number = versions_.log_number();
+        let wal = WAL::new(opt.clone(), log_number, next_file_number.clone())?;
         let mut dm = DataManager {
-            mut_: Arc::new(RwLock::new(Memtable::new(
-                opt.mem_table_max_size,
-                opt.mem_table_max_height,
-            ))),
+            mut_: RwLock::new(Arc::new(Memtable::with_options(&opt))),
             imm_: Arc::new(RwLock::new(MemtableList::new(
                 opt.clone(),
                 opt.imm_mem_table_max_count,
-                opt.mem_table_max_size,
-                opt.mem_table_max_height,
             ))),
-            readers_,
-            next_file_number_: AtomicUsize::new(next_file_number),
-            wal_: Arc::new(RwLock::new(WAL::new(opt.clone())?)),
+            versions_,
+            next_file_number_: next_file_number,
+            wal_: Arc::new(RwLock::new(wal)),
             opt_: opt.clone(),
             last_compact_keys_: Vec::with_capacity(opt.max_level),
+            compaction_lock_: Mutex::new(()),
+            flush_lock_: Mutex::new(()),
+            snapshots_: Arc::new(SnapshotList::new()),
+            stats_: Stats::new(),
+            row_cache_: if opt.row_cache_size > 0 {
+                Some(RowCache::new(opt.row_cache_size, KEY_LOCK_STRIPES))
+            } else {
+                None
+            },
+            key_locks_: (0..KEY_LOCK_STRIPES).map(|_| Mutex::new(())).collect(),
+            stall_writes_: AtomicBool::new(false),
+            background_lock_: Mutex::new(()),
+            background_cv_: Condvar::new(),
+            scheduler_: RwLock::new(None),
         };
         dm.redo()?;
         Ok(Arc::new(dm))
     }
 
-    pub fn background_thread(dma: Arc<Self>) {
-        let dm = dma.clone();
-        let _ = thread::spawn(move || {
-            let d = Duration::from_millis(dm.opt().thread_sleep_ms as u64);
-            loop {
-                dm.major_compaction().unwrap();
-                thread::sleep(d);
+    /// Runs the flushes and the compactions in the background from now on,
+    /// starting with the work left over.
+    pub fn start_background_jobs(dm: &Arc<Self>) {
+        *write_lock(&dm.scheduler_) = Some(Scheduler::new(dm));
+        dm.stall_writes_.store(true, Relaxed);
+        dm.schedule_flush();
+        dm.schedule_compaction();
+    }
+
+    fn schedule_flush(&self) {
+        if let Some(scheduler) = read_lock(&self.scheduler_).as_ref() {
+            scheduler.schedule_flush();
+        }
+    }
+
+    fn schedule_compaction(&self) {
+        if let Some(scheduler) = read_lock(&self.scheduler_).as_ref() {
+            scheduler.schedule_compaction();
+        }
+    }
+
+    /// Flushes the immutable memtables, then looks for a compaction.
+    pub fn flush_job(&self) {
+        match self.minor_compaction() {
+            Ok(()) => self.schedule_compaction(),
+            Err(e) => {
+                error!("flush error: {:?}, retrying", e);
+                self.wait_for_retry();
+                self.schedule_flush();
             }
-        });
-        let dm = dma.clone();
-        let _ = thread::spawn(move || {
-            let d = Duration::from_millis(dm.opt().thread_sleep_ms as u64);
-            loop {
-                dm.minor_compaction().unwrap();
-                thread::sleep(d);
+        }
+    }
+
+    /// Compacts a level, and carries on while another one needs it.
+    pub fn compaction_job(&self) {
+        match self.major_compaction() {
+            Ok(()) => {
+                let version = self.versions_.current();
+                let levels = version.compute_compaction_levels();
+                // the last level is never compacted
+                if levels.iter().any(|x| *x < self.opt_.max_level - 1)
+                    || version.pick_blob_file_for_gc().is_some()
+                {
+                    self.schedule_compaction();
+                }
             }
-        });
+            Err(e) => {
+                error!("compaction error: {:?}, retrying", e);
+                self.wait_for_retry();
+                self.schedule_compaction();
+            }
+        }
+    }
+
+    fn wait_for_retry(&self) {
+        self.stats_.add_background_error();
+        thread::sleep(Duration::from_millis(self.opt_.thread_sleep_ms as u64));
     }
 
     fn new_file_number(&self) -> usize {
@@ -105,8 +274,40 @@ impl DataManager {
     }
 
     pub fn info(&self) -> String {
-        let readers = read_lock(&self.readers_);
-        readers.manifest_builder().to_string()
+        self.versions_.to_string()
+    }
+
+    pub fn stats(&self) -> Vec<(String, String)> {
+        let mut items = self.stats_.items();
+        items.push((
+            "mem_table_bytes".to_owned(),
+            read_lock(&self.mut_).size().to_string(),
+        ));
+        items.push((
+            "imm_mem_table_bytes".to_owned(),
+            read_lock(&self.imm_).size().to_string(),
+        ));
+        if let Some(cache) = &self.row_cache_ {
+            items.push(("row_cache_bytes".to_owned(), cache.size().to_string()));
+        }
+        let version = self.versions_.current();
+        items.push((
+            "blob_file_bytes".to_owned(),
+            version
+                .blob_files()
+                .map(|x| x.size())
+                .sum::<u64>()
+                .to_string(),
+        ));
+        items.push((
+            "blob_stale_bytes".to_owned(),
+            version
+                .blob_files()
+                .map(|x| x.stale())
+                .sum::<u64>()
+                .to_string(),
+        ));
+        items
     }
 
     pub fn redo(&mut self) -> MyResult<()> {
@@ -130,37 +331,55 @@ impl DataManager {
                 threads.push(thread::spawn(move || {
                     info!("building sstable {:?}...", path);
                     let st = time::SystemTime::now();
-                    let t = seg.build_sstable(&opt, &path).unwrap();
+                    let (t, last_sequence) = seg.build_sstable(&opt, &path).unwrap();
                     info!(
                         "build sstable {:?} cost: {}ms",
                         path,
                         st.elapsed().unwrap().as_millis()
                     );
-                    t.map(|_| path)
+                    (t.map(|(_, _, blob)| (path, blob)), last_sequence)
                 }));
             }
 
             let table_opt = self.opt_.get_table_opt();
 
-            let readers = threads
-                .into_iter()
-                .map(|handle| handle.join().unwrap())
-                .filter(Option::is_some)
-                .map(Option::unwrap)
-                .map(|path| TableReader::new(&path, table_opt.clone()).unwrap())
-                .collect();
+            let mut files = vec![];
+            let mut blob_files = vec![];
+            for handle in threads {
+                let (built, last_sequence) = handle.join().unwrap();
+                self.versions_.recover_last_sequence(last_sequence);
+                if let Some((path, blob)) = built {
+                    let reader = TableReader::new(&path, table_opt.clone())?;
+                    files.push(TableFile::new(path, reader));
+                    blob_files.extend(blob);
+                }
+            }
 
-            {
-                let mut readers_group = write_lock(&self.readers_);
-                readers_group.add_readers(0, readers)?;
+            // every seg replayed so far is in an sstable now
+            let mut edit = VersionEdit {
+                log_number: Some(self.new_file_number()),
+                ..VersionEdit::default()
+            };
+            for file in &files {
+                edit.add_file(0, file);
             }
+            for blob in &blob_files {
+                edit.add_blob_file(blob);
+            }
+            self.versions_
+                .log_and_apply_with_blobs(edit, files, blob_files)?;
 
             for seg in &mut wal.segs {
                 seg.delete()?;
             }
         }
 
-        self.wal_ = Arc::new(RwLock::new(WAL::new(self.opt_.clone())?));
+        let log_number = self.versions_.log_number();
+        self.wal_ = Arc::new(RwLock::new(WAL::new(
+            self.opt_.clone(),
+            log_number,
+            self.next_file_number_.clone(),
+        )?));
 
         assert_eq!(0, read_lock(&self.wal_).seg_count());
 
@@ -169,116 +388,479 @@ impl DataManager {
         Ok(())
     }
 
-    pub fn insert(&self, k: StoreKey, v: StorePayload) -> MyResult<Option<StorePayload>> {
-        self.insert_with_option(k, Some(v))
+    /// Every write of a user key holds the lock of its stripe, so that the
+    /// writes depending on the current value see no concurrent change.
+    fn lock_key(&self, k: &[u8]) -> MutexGuard<()> {
+        self.key_locks_[key_stripe(k, self.key_locks_.len())]
+            .lock()
+            .unwrap()
+    }
+
+    /// A range write holds every stripe, taken in their order, so that it
+    /// runs apart from the writes depending on the current value of a key.
+    fn lock_all_keys(&self) -> Vec<MutexGuard<()>> {
+        self.key_locks_.iter().map(|x| x.lock().unwrap()).collect()
+    }
+
+    pub fn insert(&self, k: StoreKey, v: StorePayload) -> MyResult<()> {
+        let _guard = self.lock_key(k.as_ref());
+        let encoded_v = serialize(&v)?;
+        self.write(k.as_ref(), Slice::from(encoded_v), ValueType::Value)
+    }
+
+    /// Inserts the value if `cond` holds for the current value of the key,
+    /// atomically. Returns whether it was inserted.
+    pub fn insert_if<F>(&self, k: StoreKey, v: StorePayload, cond: F) -> MyResult<bool>
+    where
+        F: FnOnce(Option<&StorePayload>) -> bool,
+    {
+        let _guard = self.lock_key(k.as_ref());
+        if !cond(self.get(&k)?.as_ref()) {
+            return Ok(false);
+        }
+        let encoded_v = serialize(&v)?;
+        self.write(k.as_ref(), Slice::from(encoded_v), ValueType::Value)?;
+        Ok(true)
+    }
+
+    /// Writes a merge operand, the merge operator applies it to the value of
+    /// the key when it is read or compacted.
+    pub fn merge(&self, k: StoreKey, operand: Slice) -> MyResult<()> {
+        let _guard = self.lock_key(k.as_ref());
+        self.write_operand(&k, operand)
+    }
+
+    /// Writes a merge operand if `cond` holds for the current value of the
+    /// key, atomically. Returns whether it was written.
+    pub fn merge_if<F>(&self, k: StoreKey, operand: Slice, cond: F) -> MyResult<bool>
+    where
+        F: FnOnce(Option<&StorePayload>) -> bool,
+    {
+        let _guard = self.lock_key(k.as_ref());
+        self.write_operand_if(&k, operand, cond)
     }
 
-    fn insert_with_option(
+    /// Like `merge_if`, and reads the value the operand gives, `None` if it
+    /// was not written. No other write of the key comes in between.
+    pub fn merge_and_get_if<F>(
         &self,
         k: StoreKey,
-        v: Option<StorePayload>,
-    ) -> MyResult<Option<StorePayload>> {
-        let encoded_v = serialize(&v)?;
-        let r = self.insert_(k, Slice::from(encoded_v))?;
-        Ok(r.and_then(|_| v))
+        operand: Slice,
+        cond: F,
+    ) -> MyResult<Option<StorePayload>>
+    where
+        F: FnOnce(Option<&StorePayload>) -> bool,
+    {
+        let _guard = self.lock_key(k.as_ref());
+        if !self.write_operand_if(&k, operand, cond)? {
+            return Ok(None);
+        }
+        self.get(&k)
+    }
+
+    fn write_operand_if<F>(&self, k: &StoreKey, operand: Slice, cond: F) -> MyResult<bool>
+    where
+        F: FnOnce(Option<&StorePayload>) -> bool,
+    {
+        if !cond(self.get(k)?.as_ref()) {
+            return Ok(false);
+        }
+        self.write_operand(k, operand)?;
+        Ok(true)
+    }
+
+    fn write_operand(&self, k: &StoreKey, operand: Slice) -> MyResult<()> {
+        if self.opt_.merge_operator.is_none() {
+            return err(StatusCode::NotSupport, "no merge operator");
+        }
+        self.write(k.as_ref(), operand, ValueType::Merge)
     }
 
-    fn insert_(&self, k: Slice, v: Slice) -> MyResult<Option<Slice>> {
+    /// Deletes the keys in `[start, end)`, with a range tombstone rather than
+    /// key by key. The keys are not locked, a write of one of them racing
+    /// with it lands before or after it.
+    pub fn delete_range(&self, start: &[u8], end: &[u8]) -> MyResult<()> {
+        match self.opt_.table_opt.comparator.compare(start, end) {
+            Ordering::Greater => return err(StatusCode::Other, "range start is after its end"),
+            Ordering::Equal => return Ok(()),
+            Ordering::Less => {}
+        }
+        let _guards = self.lock_all_keys();
+        self.write(start, Slice::from(end), ValueType::RangeDeletion)
+    }
+
+    /// Logs and applies one entry under the next sequence number, readers see
+    /// it once the sequence number is published. A range tombstone is kept
+    /// apart from the entries of the memtable.
+    fn write(&self, k: &[u8], v: Slice, value_type: ValueType) -> MyResult<()> {
+        self.make_room_for_write();
         let mut wal = write_lock(&self.wal_);
-        wal.append(&k, &v)?;
+        let seq = self.versions_.last_sequence() + 1;
+        let internal_key = make_internal_key(k, seq, value_type);
+        wal.append(&internal_key, &v)?;
+
+        // the log serializes the writers, the readers share the memtable
+        // with them
+        let is_full = {
+            let muttable = read_lock(&self.mut_);
+            if value_type == ValueType::RangeDeletion {
+                muttable.add_range_deletion(RangeTombstone::new(k, &v[..], seq));
+            } else {
+                assert!(muttable.add(internal_key, v), "duplicate internal key");
+            }
+            self.versions_.set_last_sequence(seq);
+            muttable.is_full()
+        };
 
-        let mut muttable = write_lock(&self.mut_);
-        let r = muttable.insert(k, v);
+        // only once the write is visible, see `RowCache`
+        if let Some(cache) = &self.row_cache_ {
+            if value_type == ValueType::RangeDeletion {
+                cache.clear();
+            } else {
+                cache.invalidate(k);
+            }
+        }
 
-        if wal.current_seg_size()? >= self.opt_.mem_table_max_size {
-            let copied = muttable.clone();
-            {
-                let mut immuttable = write_lock(&self.imm_);
-                immuttable.add(copied);
+        if is_full {
+            self.rotate_memtable(&mut wal)?;
+            self.schedule_flush();
+        }
+
+        Ok(())
+    }
+
+    /// Seals the mutable memtable and moves it to the immutable ones, its
+    /// log segment is done with. The caller holds the log.
+    fn rotate_memtable(&self, wal: &mut WAL) -> MyResult<()> {
+        {
+            // both are held, so readers see the sealed table in either
+            let mut muttable = write_lock(&self.mut_);
+            let mut immuttable = write_lock(&self.imm_);
+            let new_table = Memtable::with_options(&self.opt_);
+            immuttable.add(mem::replace(&mut *muttable, Arc::new(new_table)));
+        }
+        wal.new_seg()?;
+        Ok(())
+    }
+
+    /// Stops the background jobs, after the running ones are done, and
+    /// flushes every memtable, so the next start has no log to replay.
+    /// Writes are not expected meanwhile.
+    pub fn shutdown(&self) -> MyResult<()> {
+        info!("shutting down...");
+        // no job is scheduled from now on, dropping the pools waits for the
+        // queued ones
+        let scheduler = write_lock(&self.scheduler_).take();
+        drop(scheduler);
+        self.stall_writes_.store(false, Relaxed);
+
+        {
+            let mut wal = write_lock(&self.wal_);
+            let is_empty = {
+                let muttable = read_lock(&self.mut_);
+                muttable.length() == 0 && muttable.range_deletions().is_empty()
+            };
+            if !is_empty {
+                self.rotate_memtable(&mut wal)?;
             }
-            muttable.clear();
-            wal.new_seg()?;
         }
+        self.minor_compaction()?;
+        info!("shutdown done!");
+        Ok(())
+    }
 
-        Ok(r)
+    /// Delays the write while level 0 fills up and blocks it while the
+    /// flushes or the compactions are too far behind, so that neither the
+    /// memtables nor level 0 grow without bound.
+    fn make_room_for_write(&self) {
+        if !self.stall_writes_.load(Relaxed) {
+            return;
+        }
+
+        if self.level_file_count(0) >= self.opt_.l0_slowdown_writes_trigger {
+            let start = time::Instant::now();
+            thread::sleep(SLOWDOWN_DELAY);
+            self.stats_
+                .add_slowdown_micros(start.elapsed().as_micros() as u64);
+        }
+
+        let start = time::Instant::now();
+        let mut stopped = false;
+        let mut guard = self.background_lock_.lock().unwrap();
+        while self.level_file_count(0) >= self.opt_.l0_stop_writes_trigger
+            || read_lock(&self.imm_).is_full()
+        {
+            stopped = true;
+            guard = self
+                .background_cv_
+                .wait_timeout(guard, STOP_WAIT)
+                .unwrap()
+                .0;
+        }
+        if stopped {
+            self.stats_
+                .add_stop_micros(start.elapsed().as_micros() as u64);
+        }
+    }
+
+    pub fn level_file_count(&self, level: usize) -> usize {
+        self.versions_.current().get_files(level).len()
+    }
+
+    pub fn imm_table_count(&self) -> usize {
+        read_lock(&self.imm_).table_count()
+    }
+
+    /// Wakes up the writers waiting for a flush or a compaction.
+    fn notify_background_work(&self) {
+        let _guard = self.background_lock_.lock().unwrap();
+        self.background_cv_.notify_all();
+    }
+
+    /// Takes a snapshot of the writes made so far, reads through it ignore
+    /// the later ones until it is dropped.
+    pub fn snapshot(&self) -> Snapshot {
+        SnapshotList::acquire(&self.snapshots_, || self.versions_.last_sequence())
+    }
+
+    /// The sequence number compaction must keep the visible entries of, the
+    /// oldest live snapshot or else the last write.
+    pub fn oldest_snapshot(&self) -> SeqNum {
+        match self.snapshots_.oldest() {
+            Some(seq) => seq,
+            None => self.versions_.last_sequence(),
+        }
+    }
+
+    /// The values newer than it are seen by no snapshot, the compaction
+    /// filter only gets these.
+    fn newest_snapshot(&self) -> SeqNum {
+        self.snapshots_.newest().unwrap_or(0)
     }
 
+    /// Reads the latest value of the key, through the row cache if there is
+    /// one. A value read from the sstables is cached.
     pub fn get<K: ?Sized>(&self, k: &K) -> MyResult<Option<StorePayload>>
     where
         K: Borrow<StoreKey>,
     {
-        let k = k.borrow();
+        let k: &StoreKey = k.borrow();
+        let cache = match &self.row_cache_ {
+            Some(cache) => cache,
+            None => return Ok(self.get_by_seq(k, self.versions_.last_sequence())?.0),
+        };
 
-        let muttable = read_lock(&self.mut_);
-        let immuttable = read_lock(&self.imm_);
+        if let Some(v) = cache.get(k.as_ref()) {
+            self.stats_.add_row_cache_hit();
+            return Ok(Some(v));
+        }
+        self.stats_.add_row_cache_miss();
 
-        let mut r = muttable.get(k);
-        if r.is_none() {
-            r = immuttable.get(k);
+        // taken before the read, a write meanwhile keeps it from the cache
+        let epoch = cache.epoch(k.as_ref());
+        let (v, from_tables) = self.get_by_seq(k, self.versions_.last_sequence())?;
+        if let (Some(v), true) = (&v, from_tables) {
+            cache.insert(k.clone(), v.clone(), epoch);
         }
+        Ok(v)
+    }
 
-        if let Some(r) = r {
-            Ok(deserialize(r.borrow())?)
-        } else {
-            let readers = read_lock(&self.readers_);
-            let x: Option<Slice> = readers.get(k)?;
-            Ok(x.and_then(|x| deserialize(x.borrow()).unwrap()))
+    pub fn get_at<K: ?Sized>(&self, snapshot: &Snapshot, k: &K) -> MyResult<Option<StorePayload>>
+    where
+        K: Borrow<StoreKey>,
+    {
+        Ok(self.get_by_seq(k.borrow(), snapshot.seq())?.0)
+    }
+
+    /// Reads the value of the key as of `seq`, and whether the sstables were
+    /// read for it.
+    fn get_by_seq(&self, k: &StoreKey, seq: SeqNum) -> MyResult<(Option<StorePayload>, bool)> {
+        let lookup_key = make_lookup_key(k.as_ref(), seq);
+        let mut ctx = MergeContext::new();
+
+        let version = {
+            let muttable = read_lock(&self.mut_);
+            let immuttable = read_lock(&self.imm_);
+            // pinned before the memtables are released, a table flushed
+            // meanwhile is either in the memtables or in this version
+            let version = self.versions_.current();
+
+            if let Some(seq) = covering_seq(&muttable, &immuttable, &version, k.as_ref(), seq) {
+                ctx.set_range_deletion(seq);
+            }
+            muttable.collect(&lookup_key[..], &mut ctx);
+            immuttable.collect(&lookup_key[..], &mut ctx);
+            version
+        };
+
+        let from_tables = !ctx.is_done();
+        if from_tables {
+            version.collect(&lookup_key[..], &mut ctx)?;
+        }
+
+        match ctx.finish(k.as_ref(), self.merge_operator())? {
+            Some(v) => Ok((Some(deserialize(&v[..])?), from_tables)),
+            None => Ok((None, from_tables)),
+        }
+    }
+
+    /// Reads the values of the keys as of the snapshot, or as of now, in
+    /// the order of the keys. The locks are taken once for all of them, and
+    /// they are looked up in order, so every level of the sstables is walked
+    /// once. The row cache is not used, its values are not all of one point.
+    pub fn multi_get(
+        &self,
+        snapshot: Option<&Snapshot>,
+        keys: &[StoreKey],
+    ) -> MyResult<Vec<Option<StorePayload>>> {
+        let seq = match snapshot {
+            Some(snapshot) => snapshot.seq(),
+            None => self.versions_.last_sequence(),
+        };
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        let cmp = &self.opt_.table_opt.comparator;
+        order.sort_by(|a, b| cmp.compare(keys[*a].as_ref(), keys[*b].as_ref()));
+        let lookup_keys: Vec<Slice> = order
+            .iter()
+            .map(|i| make_lookup_key(keys[*i].as_ref(), seq))
+            .collect();
+        let mut ctxs: Vec<MergeContext> = order.iter().map(|_| MergeContext::new()).collect();
+
+        let version = {
+            let muttable = read_lock(&self.mut_);
+            let immuttable = read_lock(&self.imm_);
+            let version = self.versions_.current();
+
+            for (i, (lookup_key, ctx)) in lookup_keys.iter().zip(ctxs.iter_mut()).enumerate() {
+                let key = keys[order[i]].as_ref();
+                if let Some(seq) = covering_seq(&muttable, &immuttable, &version, key, seq) {
+                    ctx.set_range_deletion(seq);
+                }
+                muttable.collect(&lookup_key[..], ctx);
+                immuttable.collect(&lookup_key[..], ctx);
+            }
+            version
+        };
+
+        version.multi_collect(&lookup_keys, &mut ctxs)?;
+
+        let mut values = vec![None; keys.len()];
+        for (i, ctx) in order.into_iter().zip(ctxs) {
+            if let Some(v) = ctx.finish(keys[i].as_ref(), self.merge_operator())? {
+                values[i] = Some(deserialize(&v[..])?);
+            }
         }
+        Ok(values)
+    }
+
+    fn merge_operator(&self) -> Option<&dyn MergeOperator> {
+        self.opt_.merge_operator.as_ref().map(|x| x.as_ref())
+    }
+
+    /// Iterates the keys in `[lower_bound, upper_bound)` as of the snapshot,
+    /// or as of now. The iterator keeps the memtables alive and pins the
+    /// current version, later writes and compactions don't affect it.
+    pub fn iter(
+        &self,
+        snapshot: Option<&Snapshot>,
+        lower_bound: Option<StoreKey>,
+        upper_bound: Option<StoreKey>,
+    ) -> DbIterator {
+        let seq = match snapshot {
+            Some(snapshot) => snapshot.seq(),
+            None => self.versions_.last_sequence(),
+        };
+        let (memtables, version, range_dels) = {
+            let muttable = read_lock(&self.mut_);
+            let immuttable = read_lock(&self.imm_);
+            let mut memtables = vec![MemtableIter::new(Arc::clone(&muttable))];
+            for table in immuttable.tables_iter() {
+                memtables.push(MemtableIter::new(table.clone()));
+            }
+            let version = self.versions_.current();
+            let mut range_dels = muttable.range_deletions();
+            range_dels.extend(immuttable.range_deletions().iter());
+            range_dels.extend(version.range_deletions().iter());
+            (memtables, version, range_dels)
+        };
+        DbIterator::new(
+            memtables,
+            version,
+            range_dels,
+            self.opt_.max_level,
+            ReadOptions {
+                comparator: self.opt_.table_opt.comparator.clone(),
+                merge_operator: self.opt_.merge_operator.clone(),
+                seq,
+                lower_bound,
+                upper_bound,
+            },
+        )
     }
 
     pub fn remove<K>(&self, k: &K) -> MyResult<Option<StorePayload>>
     where
         K: Borrow<StoreKey>,
     {
-        let r = self.get(k.borrow())?;
+        let k: &StoreKey = k.borrow();
+        let _guard = self.lock_key(k.as_ref());
+        let r = self.get(k)?;
         if r.is_some() {
-            self.insert_with_option(k.borrow().clone(), None)?;
+            self.write(k.as_ref(), Slice::new(), ValueType::Deletion)?;
         }
         Ok(r)
     }
 
-    fn minor_compaction(&self) -> MyResult<()> {
-        let imm = read_lock(&self.imm_);
-        let c = imm.table_count();
-        if c == 0 {
+    pub fn minor_compaction(&self) -> MyResult<()> {
+        if self.imm_table_count() == 0 {
             return Ok(());
         }
-        drop(imm);
 
-        let mut wal = write_lock(&self.wal_);
-        let imm = read_lock(&self.imm_);
+        // the memtables are built without the wal lock, so the writes go on
+        // meanwhile, a concurrent flush waits for this one
+        let _flush = self.flush_lock_.lock().unwrap();
+        // the oldest first, the wal segs are consumed in the same order
+        let tables: Vec<_> = read_lock(&self.imm_).tables_iter().rev().cloned().collect();
 
-        let mut iter = imm.tables_iter().rev();
         let work_dir = Path::new(&self.opt_.work_dir);
-        for _ in 0..c {
-            let memtable = iter.next().unwrap();
+        for memtable in tables {
             let path = work_dir.join(make_file_name(self.new_file_number(), "sst"));
-            if let Some((_, reader)) = memtable.build_sstable(&self.opt_, &path)? {
-                let mut readers = write_lock(&self.readers_);
-                readers.add(0, reader)?;
+            let built = memtable.build_sstable(&self.opt_, &path, self.newest_snapshot())?;
+            let mut files = vec![];
+            let mut blob_files = vec![];
+            if let Some((_, reader, blob)) = built {
+                files.push(TableFile::new(&path, reader));
+                blob_files.extend(blob);
             }
+
+            let mut wal = write_lock(&self.wal_);
+            let mut edit = VersionEdit {
+                log_number: Some(wal.next_log_number()),
+                ..VersionEdit::default()
+            };
+            files.iter().for_each(|x| edit.add_file(0, x));
+            blob_files.iter().for_each(|x| edit.add_blob_file(x));
+            self.versions_
+                .log_and_apply_with_blobs(edit, files, blob_files)?;
             wal.consume_seg()?;
+            drop(wal);
+            write_lock(&self.imm_).consume();
         }
-        drop(imm);
-        drop(wal);
-        let mut imm = write_lock(&self.imm_);
-        for _ in 0..c {
-            imm.consume();
-        }
+        self.clear_filtered_rows();
+        self.notify_background_work();
         Ok(())
     }
 
     pub fn major_compaction(&self) -> MyResult<()> {
-        let levels = {
-            let readers = read_lock(&self.readers_);
-            readers.compute_compaction_levels()
-        };
+        let _compaction = self.compaction_lock_.lock().unwrap();
+        let levels = self.versions_.current().compute_compaction_levels();
         if !levels.is_empty() {
             info!("size compaction: {:?}", levels);
             self.size_compaction(levels)?;
-        } else {
+        } else if !self.blob_gc()? {
             self.seek_compaction()?;
         }
+        self.notify_background_work();
         Ok(())
     }
 
@@ -290,33 +872,40 @@ impl DataManager {
             return Ok(());
         }
 
-        let readers_group = read_lock(&self.readers_);
-        let readers = readers_group.get_readers(level);
+        let version = self.versions_.current();
+        let files = version.get_files(level);
+        let cmp = self.opt_.internal_comparator();
 
-        let mut inputs0: Vec<&TableReader>;
+        let mut inputs0: Vec<&Arc<TableFile>>;
 
         if level == 0 {
-            inputs0 = readers.iter().rev().collect();
+            inputs0 = files.iter().rev().collect();
         } else {
             let last_compact_key = self.last_compact_keys_.get(level);
 
-            inputs0 = readers
+            inputs0 = files
                 .iter()
                 .filter(|reader| {
-                    last_compact_key.is_none() || reader.max_key() > last_compact_key.unwrap()
+                    last_compact_key.is_none()
+                        || cmp.compare(reader.max_key(), last_compact_key.unwrap())
+                            == Ordering::Greater
                 })
                 .collect();
 
             if inputs0.is_empty() {
-                inputs0.push(&readers[0]);
+                inputs0.push(&files[0]);
             }
         }
 
         let (max, min) = inputs0.iter().fold((None, None), |a, b| {
             if let (Some(max), Some(min)) = a {
                 (
-                    Some(::std::cmp::max(max, b.max_key())),
-                    Some(::std::cmp::min(min, b.min_key())),
+                    Some(::std::cmp::max_by(max, b.max_key(), |x, y| {
+                        cmp.compare(x, y)
+                    })),
+                    Some(::std::cmp::min_by(min, b.min_key(), |x, y| {
+                        cmp.compare(x, y)
+                    })),
                 )
             } else {
                 (Some(b.max_key()), Some(b.min_key()))
@@ -325,88 +914,483 @@ impl DataManager {
 
         let min_key = min.unwrap();
         let max_key = max.unwrap();
-        let readers = readers_group.get_readers(level + 1);
-        let inputs1 = self.get_other_readers(&min_key, &max_key, readers);
+        let files = version.get_files(level + 1);
+        let inputs1 = self.get_other_files(&min_key, &max_key, files);
 
-        let mut iters = vec![];
+        let mut iters: Vec<Box<dyn SsIterator>> = vec![];
 
         for reader in &inputs0 {
-            iters.push(reader.iter());
+            iters.push(Box::new(reader.iter()));
         }
 
         for reader in &inputs1 {
-            iters.push(reader.iter());
+            iters.push(Box::new(reader.iter()));
         }
 
-        let mut merger = Merger::new(iters);
+        let mut merger = Merger::new(iters, Arc::new(self.opt_.internal_comparator()));
+
+        let mut table: Option<SstableBuilder> = None;
+        let mut new_files = vec![];
+        let mut blob: Option<BlobWriter> = None;
+        // the bytes of the values of the dropped blob indexes, by blob file
+        let mut stale = HashMap::new();
+
+        // entries a snapshot may still see are kept, see `oldest_snapshot`
+        let smallest_snapshot = self.oldest_snapshot();
+        let newest_snapshot = self.newest_snapshot();
+        let snapshots = self.snapshots_.all();
+        let range_dels = version.range_deletions();
+        let mut current_user_key: Option<Vec<u8>> = None;
+        let mut last_seq_for_key: Option<SeqNum> = None;
+        let mut dropped = 0;
+        let mut expired = 0;
+        // an entry read ahead by a merge
+        let mut pending = None;
+
+        loop {
+            let (k, v) = match pending.take().or_else(|| merger.next()) {
+                Some(kv) => kv,
+                None => break,
+            };
+            let parsed = match parse_internal_key(&k) {
+                Some(parsed) => parsed,
+                None => return err(StatusCode::Other, "bad internal key"),
+            };
 
-        let work_dir = Path::new(&self.opt_.work_dir);
+            let is_new_user_key = match &current_user_key {
+                Some(current_user_key) => current_user_key[..] != *parsed.user_key,
+                None => true,
+            };
+            if is_new_user_key {
+                // a full table is only cut between user keys, so that a
+                // file of a level holds all the entries of its user keys
+                let is_full = match &table {
+                    Some(table_) => table_.total_size_estimate() >= self.opt_.sst_max_size,
+                    None => false,
+                };
+                if is_full {
+                    new_files.push(self.finish_table(table.take().unwrap())?);
+                }
+                current_user_key = Some(parsed.user_key.to_vec());
+                last_seq_for_key = None;
+            }
 
-        let table_opt = self.opt_.get_table_opt();
-        let mut table = None;
-        let mut new_readers = vec![];
+            // hidden by a newer entry which every snapshot sees
+            let shadowed = match last_seq_for_key {
+                Some(seq) => seq <= smallest_snapshot,
+                None => false,
+            };
+            last_seq_for_key = Some(parsed.seq);
+            // deleted by a newer range tombstone
+            let covered = match range_dels
+                .covering_seq(parsed.user_key, visible_bound(&snapshots, parsed.seq))
+            {
+                Some(seq) => seq > parsed.seq,
+                None => false,
+            };
+            if shadowed || covered {
+                if parsed.value_type == ValueType::BlobIndex {
+                    add_stale(&mut stale, &BlobIndex::decode(&v)?);
+                }
+                dropped += 1;
+                continue;
+            }
 
-        while let Some((k, v)) = merger.next() {
-            if table.is_none() {
-                let path = work_dir.join(make_file_name(self.new_file_number(), "sst"));
-                table = Some(TableBuilder::new(&path, table_opt.clone())?);
+            let mut value_type = parsed.value_type;
+            let mut new_value = None;
+            if value_type == ValueType::Merge {
+                let (merged, next) = self.merge_entries(
+                    &mut merger,
+                    (k.clone(), v.clone()),
+                    &snapshots,
+                    &version,
+                    level + 1,
+                    &mut stale,
+                )?;
+                pending = next;
+                match merged {
+                    MergeOutcome::Merged(t, merged_v, oldest_seq) => {
+                        value_type = t;
+                        new_value = Some(merged_v);
+                        last_seq_for_key = Some(oldest_seq);
+                    }
+                    MergeOutcome::Unmerged(entries) => {
+                        for (k, v) in &entries {
+                            self.add_to_table(&mut table, k, v)?;
+                        }
+                        if let Some((k, _)) = entries.last() {
+                            last_seq_for_key = parse_internal_key(k).map(|x| x.seq);
+                        }
+                        continue;
+                    }
+                }
             }
 
-            let is_full = {
-                let table_ = table.as_mut().unwrap();
+            // an expired value is hidden from every reader, now and later,
+            // it becomes a tombstone so that it keeps hiding the older
+            // entries of its user key
+            if value_type == ValueType::Value {
+                let payload: StorePayload = match &new_value {
+                    Some(v) => deserialize(&v[..])?,
+                    None => deserialize(&v)?,
+                };
+                if payload.is_expired() {
+                    value_type = ValueType::Deletion;
+                    new_value = Some(Slice::new());
+                    expired += 1;
+                } else if let Some(filter) = &self.opt_.compaction_filter {
+                    if parsed.seq > newest_snapshot {
+                        if let Some((t, v)) =
+                            apply_filter(filter.as_ref(), level + 1, parsed.user_key, &payload)?
+                        {
+                            value_type = t;
+                            new_value = Some(v);
+                        }
+                    }
+                }
+            } else if value_type == ValueType::BlobIndex {
+                // the value is only read for the compaction filter
+                let index = BlobIndex::decode(&v)?;
+                if index.is_expired() {
+                    value_type = ValueType::Deletion;
+                    new_value = Some(Slice::new());
+                    expired += 1;
+                    add_stale(&mut stale, &index);
+                } else if let Some(filter) = &self.opt_.compaction_filter {
+                    if parsed.seq > newest_snapshot {
+                        let payload: StorePayload = deserialize(&version.read_blob(&index)?[..])?;
+                        if let Some((t, v)) =
+                            apply_filter(filter.as_ref(), level + 1, parsed.user_key, &payload)?
+                        {
+                            value_type = t;
+                            new_value = Some(v);
+                            add_stale(&mut stale, &index);
+                        }
+                    }
+                }
+            }
 
-                table_.add(&k, &v)?;
+            if value_type == ValueType::Deletion
+                && parsed.seq <= smallest_snapshot
+                && version.is_base_level_for_key(level + 1, parsed.user_key)
+            {
+                dropped += 1;
+                continue;
+            }
 
-                table_.total_size_estimate() >= self.opt_.sst_max_size
+            let v = match &new_value {
+                Some(v) => &v[..],
+                None => &v[..],
             };
+            if value_type == ValueType::Value && self.opt_.is_blob_value(v.len()) {
+                let index = self.blob_writer(&mut blob)?.add_value(v)?;
+                let k = make_internal_key(parsed.user_key, parsed.seq, ValueType::BlobIndex);
+                self.add_to_table(&mut table, &k[..], &index[..])?;
+            } else if new_value.is_some() {
+                let k = make_internal_key(parsed.user_key, parsed.seq, value_type);
+                self.add_to_table(&mut table, &k[..], v)?;
+            } else {
+                self.add_to_table(&mut table, &k, v)?;
+            }
+        }
 
-            if !is_full {
-                continue;
+        // the range tombstones of the inputs go along, but the ones every
+        // snapshot sees with no entry left under them
+        let inputs: Vec<&Arc<TableFile>> = inputs0.iter().chain(&inputs1).cloned().collect();
+        let mut range_tombstones: Vec<&RangeTombstone> =
+            inputs.iter().flat_map(|x| x.range_tombstones()).collect();
+        let range_tombstone_count = range_tombstones.len();
+        range_tombstones
+            .retain(|x| x.seq > smallest_snapshot || !version.can_drop_range_deletion(x, &inputs));
+        range_tombstones.sort_by(|a, b| cmp.compare(&a.internal_key()[..], &b.internal_key()[..]));
+        let has_entries = table.is_some();
+        for tombstone in &range_tombstones {
+            self.add_range_deletion(&mut table, tombstone)?;
+        }
+        // a table of range tombstones only is bounded by where the first
+        // one starts, which may fall in another file of the level, so it
+        // is kept in the key range of the inputs which no other file has
+        if !has_entries && !range_tombstones.is_empty() {
+            let order = |x: &&[u8], y: &&[u8]| cmp.compare(x, y);
+            let lower = inputs
+                .iter()
+                .map(|x| &x.min_key()[..])
+                .min_by(order)
+                .unwrap();
+            let upper = inputs
+                .iter()
+                .map(|x| &x.max_key()[..])
+                .max_by(order)
+                .unwrap();
+            let first = range_tombstones[0].internal_key();
+            let k = ::std::cmp::min_by(::std::cmp::max_by(&first[..], lower, order), upper, order);
+            self.table_builder(&mut table)?
+                .set_range_deletion_bounds(k, k);
+        }
+
+        if let Some(table_) = table.take() {
+            new_files.push(self.finish_table(table_)?);
+        }
+        let mut blob_files = vec![];
+        if let Some(blob) = blob.take() {
+            blob_files.push(blob.finish()?);
+        }
+        info!(
+            "size compaction dropped {} entries, {} expired, {} range tombstones",
+            dropped,
+            expired,
+            range_tombstone_count - range_tombstones.len()
+        );
+        self.stats_.add_expired_reclaimed(expired);
+
+        let mut edit = VersionEdit::default();
+        for file in &inputs0 {
+            edit.delete_file(level, file);
+        }
+        for file in &inputs1 {
+            edit.delete_file(level + 1, file);
+        }
+        for file in &new_files {
+            edit.add_file(level + 1, file);
+        }
+        for blob in &blob_files {
+            edit.add_blob_file(blob);
+        }
+        for (number, bytes) in stale {
+            edit.add_blob_stale(number, bytes);
+        }
+
+        self.versions_
+            .log_and_apply_with_blobs(edit, new_files, blob_files)?;
+        self.clear_filtered_rows();
+
+        Ok(())
+    }
+
+    /// Collects the garbage of the blob file with the most stale bytes: the
+    /// sstables pointing into it are rewritten on their level, its live
+    /// values are moved to a new blob file, then it is dropped. Returns
+    /// false if no blob file needs it.
+    fn blob_gc(&self) -> MyResult<bool> {
+        let version = self.versions_.current();
+        let victim = match version.pick_blob_file_for_gc() {
+            Some(victim) => victim.clone(),
+            None => return Ok(false),
+        };
+        info!(
+            "blob gc: {:08}.blob, {} of {} bytes stale",
+            victim.number(),
+            victim.stale(),
+            victim.size()
+        );
+
+        let mut edit = VersionEdit::default();
+        let mut new_files = vec![];
+        let mut blob: Option<BlobWriter> = None;
+        let mut moved = 0;
+        for level in 0..self.opt_.max_level {
+            for file in version.get_files(level) {
+                let points = match file.blob_files() {
+                    Some(blob_files) => blob_files.contains(&victim.number()),
+                    // from before the blob files were recorded
+                    None => points_into(file, victim.number())?,
+                };
+                if !points {
+                    continue;
+                }
+                let mut table = None;
+                let mut iter = file.iter();
+                while let Some((k, v)) = iter.next() {
+                    let is_blob_index = match parse_internal_key(&k) {
+                        Some(parsed) => parsed.value_type == ValueType::BlobIndex,
+                        None => return err(StatusCode::Other, "bad internal key"),
+                    };
+                    if is_blob_index {
+                        let index = BlobIndex::decode(&v)?;
+                        if index.file_number == victim.number() {
+                            let value = victim.read(&index)?;
+                            let new_index = self
+                                .blob_writer(&mut blob)?
+                                .add(&value[..], index.expires_at)?;
+                            self.add_to_table(&mut table, &k, &new_index.encode()?[..])?;
+                            moved += 1;
+                            continue;
+                        }
+                    }
+                    self.add_to_table(&mut table, &k, &v)?;
+                }
+                for tombstone in file.range_tombstones() {
+                    self.add_range_deletion(&mut table, tombstone)?;
+                }
+                edit.delete_file(level, file);
+                if let Some(table) = table {
+                    let file = self.finish_table(table)?;
+                    edit.add_file(level, &file);
+                    new_files.push(file);
+                }
             }
+        }
+        info!("blob gc moved {} values", moved);
 
-            let mut table_ = table.take().unwrap();
-            let path = &table_.path().clone();
-            table_.flush()?;
-            let reader = TableReader::new(path, table_opt.clone())?;
-            new_readers.push(reader);
+        let mut blob_files = vec![];
+        if let Some(blob) = blob {
+            let blob = blob.finish()?;
+            edit.add_blob_file(&blob);
+            blob_files.push(blob);
         }
+        edit.delete_blob_file(&victim);
+        self.versions_
+            .log_and_apply_with_blobs(edit, new_files, blob_files)?;
+        self.stats_.add_blob_gc();
+        Ok(true)
+    }
 
-        if let Some(mut table_) = table.take() {
-            let path = &table_.path().clone();
-            table_.flush()?;
-            let reader = TableReader::new(&path, table_opt.clone())?;
-            new_readers.push(reader);
+    /// A compaction filter may have changed any value the flush or the
+    /// compaction wrote, the row cache can't tell which.
+    fn clear_filtered_rows(&self) {
+        if self.opt_.compaction_filter.is_none() {
+            return;
+        }
+        if let Some(cache) = &self.row_cache_ {
+            cache.clear();
         }
+    }
 
-        let file_names0 = inputs0.iter().map(|x| x.file_name().clone()).collect();
-        let file_names1 = inputs1.iter().map(|x| x.file_name().clone()).collect();
+    /// Merges the merge operand `first` with the older entries of its user
+    /// key which are in the same snapshot stripe, no snapshot sees them apart.
+    /// The merge needs a value or a deletion to apply the operands to, or to
+    /// know that the key has no older entry. The entry read past them is
+    /// returned too. The entries under a range tombstone of the stripe are
+    /// deleted. A value merged out of a blob file goes to `stale`.
+    fn merge_entries(
+        &self,
+        merger: &mut Merger,
+        first: (Vec<u8>, Vec<u8>),
+        snapshots: &[SeqNum],
+        version: &Version,
+        output_level: usize,
+        stale: &mut HashMap<usize, u64>,
+    ) -> MyResult<(MergeOutcome, Option<(Vec<u8>, Vec<u8>)>)> {
+        let stripe = |seq: SeqNum| match snapshots.binary_search(&seq) {
+            Ok(i) | Err(i) => i,
+        };
+        let (user_key, seq) = match parse_internal_key(&first.0) {
+            Some(parsed) => (parsed.user_key.to_vec(), parsed.seq),
+            None => return err(StatusCode::Other, "bad internal key"),
+        };
 
-        drop(readers_group);
+        let mut entries = vec![];
+        let mut next = None;
+        let mut key_ended = true;
+        let mut ctx = MergeContext::new();
+        if let Some(seq) = version.covering_seq(&user_key, visible_bound(snapshots, seq)) {
+            ctx.set_range_deletion(seq);
+        }
+        let mut entry = Some(first);
+        while let Some((k, v)) = entry.take() {
+            let (same_key, same_stripe, entry_seq, value_type) = match parse_internal_key(&k) {
+                Some(parsed) => (
+                    parsed.user_key == &user_key[..],
+                    stripe(parsed.seq) == stripe(seq),
+                    parsed.seq,
+                    parsed.value_type,
+                ),
+                None => return err(StatusCode::Other, "bad internal key"),
+            };
+            if !same_key || !same_stripe {
+                key_ended = !same_key;
+                next = Some((k, v));
+                break;
+            }
+            ctx.add(entry_seq, value_type, Slice::from(&v[..]));
+            entries.push((k, v));
+            if ctx.is_done() {
+                break;
+            }
+            entry = merger.next();
+        }
 
-        let mut readers_group = write_lock(&self.readers_);
+        let can_merge = self.merge_operator().is_some()
+            && (ctx.is_done()
+                || key_ended && version.is_base_level_for_key(output_level, &user_key));
+        if !can_merge {
+            return Ok((MergeOutcome::Unmerged(entries), next));
+        }
+        let oldest_seq = parse_internal_key(&entries[entries.len() - 1].0)
+            .unwrap()
+            .seq;
+        if let Some(index) = version.resolve_blob(&mut ctx)? {
+            add_stale(stale, &index);
+        }
+        let outcome = match ctx.finish(&user_key, self.merge_operator())? {
+            Some(v) => MergeOutcome::Merged(ValueType::Value, v, oldest_seq),
+            None => MergeOutcome::Merged(ValueType::Deletion, Slice::new(), oldest_seq),
+        };
+        Ok((outcome, next))
+    }
 
-        readers_group.remove_by_file_names(level, &file_names0)?;
-        readers_group.remove_by_file_names(level + 1, &file_names1)?;
+    /// The table builder of a compaction, created on first use.
+    fn table_builder<'a>(
+        &self,
+        table: &'a mut Option<SstableBuilder>,
+    ) -> MyResult<&'a mut SstableBuilder> {
+        if table.is_none() {
+            let path =
+                Path::new(&self.opt_.work_dir).join(make_file_name(self.new_file_number(), "sst"));
+            *table = Some(SstableBuilder::new(&path, &self.opt_)?);
+        }
+        Ok(table.as_mut().unwrap())
+    }
 
-        readers_group.add_readers(level + 1, new_readers)?;
+    fn add_to_table(&self, table: &mut Option<SstableBuilder>, k: &[u8], v: &[u8]) -> MyResult<()> {
+        self.table_builder(table)?.add(k, v)?;
+        Ok(())
+    }
 
+    /// The range tombstones of a table are added in the order of their keys.
+    fn add_range_deletion(
+        &self,
+        table: &mut Option<SstableBuilder>,
+        tombstone: &RangeTombstone,
+    ) -> MyResult<()> {
+        self.table_builder(table)?
+            .add_range_deletion(&tombstone.internal_key()[..], &tombstone.end[..])?;
         Ok(())
     }
 
+    /// The blob writer of a compaction, created on first use.
+    fn blob_writer<'a>(&self, blob: &'a mut Option<BlobWriter>) -> MyResult<&'a mut BlobWriter> {
+        if blob.is_none() {
+            *blob = Some(BlobWriter::new(&self.opt_, self.new_file_number())?);
+        }
+        Ok(blob.as_mut().unwrap())
+    }
+
+    fn finish_table(&self, table: SstableBuilder) -> MyResult<TableFile> {
+        let path = table.path().clone();
+        table.finish()?;
+        let reader = TableReader::new(&path, self.opt_.get_table_opt())?;
+        Ok(TableFile::new(&path, reader))
+    }
+
     fn seek_compaction(&self) -> MyResult<()> {
         Ok(())
     }
 
-    fn get_other_readers<'a>(
+    fn get_other_files<'a>(
         &'a self,
         min_key: &[u8],
         max_key: &[u8],
-        readers: &'a [TableReader],
-    ) -> Vec<&'a TableReader> {
-        readers
+        files: &'a [Arc<TableFile>],
+    ) -> Vec<&'a Arc<TableFile>> {
+        // by user keys, the entries of a user key may span several files
+        let cmp = &self.opt_.table_opt.comparator;
+        files
             .iter()
-            .take_while(|x| x.min_key().as_slice() <= max_key)
-            .filter(|x| x.max_key().as_slice() >= min_key)
+            .take_while(|x| {
+                cmp.compare(user_key(x.min_key()), user_key(max_key)) != Ordering::Greater
+            })
+            .filter(|x| cmp.compare(user_key(x.max_key()), user_key(min_key)) != Ordering::Less)
             .collect()
     }
 
@@ -414,7 +1398,7 @@ impl DataManager {
     fn clear_memtables(&self) {
         let mut muttable = write_lock(&self.mut_);
         let mut immuttable = write_lock(&self.imm_);
-        muttable.clear();
+        *muttable = Arc::new(Memtable::with_options(&self.opt_));
         immuttable.clear();
     }
 }
@@ -422,8 +1406,12 @@ impl DataManager {
 #[cfg(test)]
 mod test {
     use std::collections::HashMap;
+    use std::fs;
     use std::time;
 
+    use crate::options::KB;
+    use crate::options::MB;
+    use crate::store::StoreOperand;
     use crate::test_utils::get_test_opt;
 
     use super::*;
@@ -436,6 +1424,19 @@ mod test {
         StorePayload::new(Slice::from(v), 0, 0, 0, 0)
     }
 
+    /// Orders the keys backward.
+    struct ReverseComparator;
+
+    impl Comparator for ReverseComparator {
+        fn name(&self) -> &str {
+            "test.ReverseComparator"
+        }
+
+        fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+            b.cmp(a)
+        }
+    }
+
     fn get_data() -> HashMap<StoreKey, StorePayload> {
         (b'a'..=b'f')
             .into_iter()
@@ -444,33 +1445,97 @@ mod test {
     }
 
     #[test]
-    fn test_fault_tolerance() -> MyResult<()> {
+    fn test_comparator() -> MyResult<()> {
         let mut opt = get_test_opt();
-        opt.imm_mem_table_max_count = 3;
         opt.mem_table_max_size = 20;
         opt.sst_max_size = 60;
         opt.l0_compaction_trigger = 1;
+        opt.table_opt.block_size = 20;
+        opt.table_opt.comparator = Arc::new(ReverseComparator);
 
         let dm = DataManager::new(opt.clone())?;
-
-        let mut data = get_data();
-
-        let mut meet_b = false;
-        let mut updated_b = false;
-
-        let st = time::SystemTime::now();
-        for (k, v) in &data {
-            println!("{}: {}", to_str(k), to_str(&v.data));
-            dm.insert(k.clone(), v.clone())?;
-            if meet_b {
-                dm.insert(make_key(b"b".to_vec()), make_payload(b"none".to_vec()))?;
-                updated_b = true;
+        let key = |k: &str| make_key(k.as_bytes().to_vec());
+        let keys: Vec<StoreKey> = (0..20).map(|x| key(&format!("k{:02}", x))).collect();
+        // the entries spread over level 1, level 0 and the memtables
+        for (i, k) in keys.iter().enumerate() {
+            dm.insert(k.clone(), make_payload(k[..].to_vec()))?;
+            if i % 5 == 4 {
+                dm.minor_compaction()?;
             }
-            if k == &b"b".to_vec() {
-                meet_b = true;
+            if i == 9 {
+                dm.major_compaction()?;
             }
         }
-        println!("insert cost: {}ms", st.elapsed().unwrap().as_millis());
+        dm.remove(&keys[3])?;
+        // from the greater key to the lesser one
+        dm.delete_range(b"k12", b"k10")?;
+        assert!(dm.delete_range(b"k10", b"k12").is_err());
+
+        let check = |dm: &DataManager| -> MyResult<()> {
+            let live = |i: &usize| *i != 3 && *i != 11 && *i != 12;
+            for (i, k) in keys.iter().enumerate() {
+                let expected = Some(make_payload(k[..].to_vec())).filter(|_| live(&i));
+                assert_eq!(expected, dm.get(k)?);
+            }
+            let found: Vec<StoreKey> = dm.iter(None, None, None).map(|(k, _)| k).collect();
+            let expected: Vec<StoreKey> = (0..20)
+                .rev()
+                .filter(live)
+                .map(|i| keys[i].clone())
+                .collect();
+            assert_eq!(expected, found);
+            let found: Vec<StoreKey> = dm
+                .iter(None, Some(key("k15")), Some(key("k09")))
+                .map(|(k, _)| k)
+                .collect();
+            assert_eq!(vec![key("k15"), key("k14"), key("k13"), key("k10")], found);
+            Ok(())
+        };
+        check(&dm)?;
+        dm.minor_compaction()?;
+        dm.major_compaction()?;
+        check(&dm)?;
+        for pair in dm.versions_.current().get_files(1).windows(2) {
+            assert_eq!(
+                Ordering::Less,
+                ReverseComparator.compare(user_key(pair[0].max_key()), user_key(pair[1].min_key()))
+            );
+        }
+
+        // the tables are opened again with the comparator
+        drop(dm);
+        let dm = DataManager::new(opt)?;
+        check(&dm)
+    }
+
+    #[test]
+    fn test_fault_tolerance() -> MyResult<()> {
+        let mut opt = get_test_opt();
+        opt.imm_mem_table_max_count = 3;
+        opt.mem_table_max_size = 20;
+        opt.sst_max_size = 60;
+        opt.l0_compaction_trigger = 1;
+
+        let dm = DataManager::new(opt.clone())?;
+
+        let mut data = get_data();
+
+        let mut meet_b = false;
+        let mut updated_b = false;
+
+        let st = time::SystemTime::now();
+        for (k, v) in &data {
+            println!("{}: {}", to_str(k), to_str(&v.data));
+            dm.insert(k.clone(), v.clone())?;
+            if meet_b {
+                dm.insert(make_key(b"b".to_vec()), make_payload(b"none".to_vec()))?;
+                updated_b = true;
+            }
+            if k == &b"b".to_vec() {
+                meet_b = true;
+            }
+        }
+        println!("insert cost: {}ms", st.elapsed().unwrap().as_millis());
 
         if updated_b {
             data.insert(make_key(b"b".to_vec()), make_payload(b"none".to_vec()));
@@ -574,4 +1639,786 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_sequence() -> MyResult<()> {
+        let mut opt = get_test_opt();
+        opt.imm_mem_table_max_count = 3;
+        opt.mem_table_max_size = 20;
+
+        let k = make_key(b"a".to_vec());
+
+        let dm = DataManager::new(opt.clone())?;
+        for i in 0..10 {
+            dm.insert(k.clone(), make_payload(vec![i; 20]))?;
+        }
+        assert_eq!(10, dm.versions_.last_sequence());
+        dm.minor_compaction()?;
+        dm.insert(k.clone(), make_payload(vec![10; 20]))?;
+        drop(dm);
+
+        // the versions replayed from the wal land in several level 0 files
+        let dm = DataManager::new(opt.clone())?;
+        assert_eq!(11, dm.versions_.last_sequence());
+        assert_eq!(Some(make_payload(vec![10; 20])), dm.get(&k)?);
+
+        // new writes are newer than the recovered ones
+        dm.insert(k.clone(), make_payload(b"new".to_vec()))?;
+        dm.minor_compaction()?;
+        assert_eq!(Some(make_payload(b"new".to_vec())), dm.get(&k)?);
+        dm.remove(&k)?;
+        assert_eq!(None, dm.get(&k)?);
+        drop(dm);
+
+        let dm = DataManager::new(opt.clone())?;
+        assert_eq!(13, dm.versions_.last_sequence());
+        assert_eq!(None, dm.get(&k)?);
+        dm.major_compaction()?;
+        assert_eq!(None, dm.get(&k)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot() -> MyResult<()> {
+        let mut opt = get_test_opt();
+        opt.imm_mem_table_max_count = 3;
+        opt.mem_table_max_size = 20;
+        opt.l0_compaction_trigger = 1;
+
+        let a = make_key(b"a".to_vec());
+        let b = make_key(b"b".to_vec());
+
+        let dm = DataManager::new(opt.clone())?;
+        dm.insert(a.clone(), make_payload(b"a1".to_vec()))?;
+        dm.insert(b.clone(), make_payload(b"b1".to_vec()))?;
+
+        let snapshot = dm.snapshot();
+        assert_eq!(2, dm.oldest_snapshot());
+
+        dm.insert(a.clone(), make_payload(b"a2".to_vec()))?;
+        dm.remove(&b)?;
+        dm.insert(make_key(b"c".to_vec()), make_payload(b"c1".to_vec()))?;
+        assert_eq!(2, dm.oldest_snapshot());
+
+        let check = |dm: &DataManager| -> MyResult<()> {
+            assert_eq!(
+                Some(make_payload(b"a1".to_vec())),
+                dm.get_at(&snapshot, &a)?
+            );
+            assert_eq!(
+                Some(make_payload(b"b1".to_vec())),
+                dm.get_at(&snapshot, &b)?
+            );
+            assert_eq!(None, dm.get_at(&snapshot, &make_key(b"c".to_vec()))?);
+            assert_eq!(Some(make_payload(b"a2".to_vec())), dm.get(&a)?);
+            assert_eq!(None, dm.get(&b)?);
+            Ok(())
+        };
+
+        check(&dm)?;
+        dm.minor_compaction()?;
+        check(&dm)?;
+        dm.major_compaction()?;
+        check(&dm)?;
+
+        drop(snapshot);
+        assert_eq!(5, dm.oldest_snapshot());
+        Ok(())
+    }
+
+    fn sst_disk_usage(opt: &Options) -> MyResult<u64> {
+        let mut size = 0;
+        for entry in fs::read_dir(&opt.work_dir)? {
+            let path = entry?.path();
+            if path.extension().map_or(false, |x| x == "sst") {
+                size += fs::metadata(&path)?.len();
+            }
+        }
+        Ok(size)
+    }
+
+    #[test]
+    fn test_drop_tombstones() -> MyResult<()> {
+        let mut opt = get_test_opt();
+        opt.mem_table_max_size = 20;
+        opt.sst_max_size = 4096;
+        opt.l0_compaction_trigger = 1;
+
+        let dm = DataManager::new(opt.clone())?;
+        let keys: Vec<_> = (0..20u32)
+            .map(|i| make_key(format!("key{:02}", i).into_bytes()))
+            .collect();
+        for k in &keys {
+            dm.insert(k.clone(), make_payload(vec![b'v'; 1000]))?;
+        }
+        dm.minor_compaction()?;
+        dm.major_compaction()?;
+        let before = sst_disk_usage(&opt)?;
+        assert!(before > 0);
+
+        for k in keys.iter().skip(4) {
+            dm.remove(k)?;
+        }
+        dm.minor_compaction()?;
+        dm.major_compaction()?;
+
+        // the output level is the bottommost one holding the keys, the
+        // tombstones go along with the values they delete
+        let after = sst_disk_usage(&opt)?;
+        assert!(after * 4 < before, "{} -> {}", before, after);
+        for (i, k) in keys.iter().enumerate() {
+            assert_eq!(i < 4, dm.get(k)?.is_some());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_drop_shadowed() -> MyResult<()> {
+        let mut opt = get_test_opt();
+        opt.mem_table_max_size = 20;
+        opt.sst_max_size = 4096;
+        opt.l0_compaction_trigger = 1;
+
+        let dm = DataManager::new(opt.clone())?;
+        let k = make_key(b"a".to_vec());
+        for i in 0..20 {
+            dm.insert(k.clone(), make_payload(vec![i; 1000]))?;
+        }
+        dm.minor_compaction()?;
+        let before = sst_disk_usage(&opt)?;
+
+        // an older version a snapshot sees is kept
+        let snapshot = dm.snapshot();
+        dm.insert(k.clone(), make_payload(b"new".to_vec()))?;
+        dm.minor_compaction()?;
+        dm.major_compaction()?;
+        let after = sst_disk_usage(&opt)?;
+        assert!(after * 5 < before, "{} -> {}", before, after);
+        assert_eq!(
+            Some(make_payload(vec![19; 1000])),
+            dm.get_at(&snapshot, &k)?
+        );
+        assert_eq!(Some(make_payload(b"new".to_vec())), dm.get(&k)?);
+        Ok(())
+    }
+
+    fn sst_entry_count(dm: &DataManager) -> usize {
+        let version = dm.versions_.current();
+        (0..dm.opt_.max_level)
+            .flat_map(|level| version.get_files(level))
+            .map(|file| file.iter().count())
+            .sum()
+    }
+
+    #[test]
+    fn test_purge_expired() -> MyResult<()> {
+        let mut opt = get_test_opt();
+        opt.mem_table_max_size = 20;
+        opt.l0_compaction_trigger = 1;
+
+        let dm = DataManager::new(opt.clone())?;
+        let a = make_key(b"a".to_vec());
+        let b = make_key(b"b".to_vec());
+        let c = make_key(b"c".to_vec());
+        let expired = |data: &[u8]| StorePayload::new(Slice::from(data), 0, 1, 0, 0);
+
+        dm.insert(a.clone(), make_payload(vec![b'a'; 1000]))?;
+        dm.insert(b.clone(), make_payload(vec![b'b'; 1000]))?;
+        dm.insert(c.clone(), expired(&[b'c'; 1000]))?;
+        dm.minor_compaction()?;
+        dm.major_compaction()?;
+        assert_eq!(1, dm.stats_.expired_reclaimed());
+        assert_eq!(None, dm.get(&c)?);
+
+        // the expired newer versions hide the older ones, a snapshot keeps
+        // the older version of `b` it sees
+        let snapshot = dm.snapshot();
+        dm.insert(a.clone(), expired(&[b'x'; 1000]))?;
+        dm.insert(b.clone(), expired(&[b'y'; 1000]))?;
+        dm.minor_compaction()?;
+        dm.major_compaction()?;
+        assert_eq!(3, dm.stats_.expired_reclaimed());
+        assert_eq!(None, dm.get(&a)?);
+        assert_eq!(None, dm.get(&b)?);
+        assert_eq!(
+            Some(make_payload(vec![b'b'; 1000])),
+            dm.get_at(&snapshot, &b)?
+        );
+        // the tombstones and the older versions
+        assert_eq!(4, sst_entry_count(&dm));
+
+        // once the snapshot is gone, a compaction overlapping the keys drops
+        // them altogether
+        drop(snapshot);
+        dm.insert(make_key(b"aa".to_vec()), make_payload(b"aa".to_vec()))?;
+        dm.minor_compaction()?;
+        dm.major_compaction()?;
+        assert_eq!(None, dm.get(&a)?);
+        assert_eq!(None, dm.get(&b)?);
+        assert_eq!(1, sst_entry_count(&dm));
+        assert!(dm
+            .stats()
+            .contains(&("expired_reclaimed".to_owned(), "3".to_owned())));
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge() -> MyResult<()> {
+        let mut opt = get_test_opt();
+        opt.mem_table_max_size = 20;
+        opt.l0_compaction_trigger = 1;
+
+        let dm = DataManager::new(opt.clone())?;
+        let k = make_key(b"a".to_vec());
+        let append = |data: &[u8]| {
+            let operand = StoreOperand::Append(make_payload(data.to_vec()));
+            Slice::from(serialize(&operand).unwrap())
+        };
+        let data = |p: Option<StorePayload>| p.map(|x| x.data);
+
+        // the operands spread over level 1, level 0 and the memtables
+        dm.insert(k.clone(), make_payload(b"a".to_vec()))?;
+        dm.minor_compaction()?;
+        dm.major_compaction()?;
+        dm.merge(k.clone(), append(b"b"))?;
+        dm.minor_compaction()?;
+        dm.merge(k.clone(), append(b"c"))?;
+        assert_eq!(Some(Slice::from("abc")), data(dm.get(&k)?));
+
+        let snapshot = dm.snapshot();
+        dm.merge(k.clone(), append(b"d"))?;
+        dm.minor_compaction()?;
+        dm.major_compaction()?;
+        assert_eq!(Some(Slice::from("abcd")), data(dm.get(&k)?));
+        assert_eq!(Some(Slice::from("abc")), data(dm.get_at(&snapshot, &k)?));
+        // the operands the snapshot sees are merged into a value
+        assert_eq!(2, sst_entry_count(&dm));
+
+        drop(snapshot);
+        dm.merge(k.clone(), append(b"e"))?;
+        dm.minor_compaction()?;
+        dm.major_compaction()?;
+        assert_eq!(Some(Slice::from("abcde")), data(dm.get(&k)?));
+        assert_eq!(1, sst_entry_count(&dm));
+
+        // operands of a key without value are dropped on the base level
+        let missing = make_key(b"b".to_vec());
+        dm.merge(missing.clone(), append(b"x"))?;
+        assert_eq!(None, dm.get(&missing)?);
+        dm.minor_compaction()?;
+        dm.major_compaction()?;
+        assert_eq!(None, dm.get(&missing)?);
+        assert_eq!(1, sst_entry_count(&dm));
+        Ok(())
+    }
+
+    #[test]
+    fn test_multi_get() -> MyResult<()> {
+        let mut opt = get_test_opt();
+        opt.mem_table_max_size = 20;
+        opt.sst_max_size = 60;
+        opt.l0_compaction_trigger = 1;
+        opt.table_opt.block_size = 20;
+
+        let dm = DataManager::new(opt.clone())?;
+        let append = |data: &[u8]| {
+            let operand = StoreOperand::Append(make_payload(data.to_vec()));
+            Slice::from(serialize(&operand).unwrap())
+        };
+
+        // the entries spread over level 1, level 0 and the memtables
+        let keys: Vec<StoreKey> = (0..40)
+            .map(|x| make_key(format!("k{:02}", x).into_bytes()))
+            .collect();
+        for (i, k) in keys.iter().enumerate() {
+            dm.insert(k.clone(), make_payload(k[..].to_vec()))?;
+            if i % 10 == 9 {
+                dm.minor_compaction()?;
+            }
+            if i == 19 {
+                dm.major_compaction()?;
+            }
+        }
+        for k in keys.iter().step_by(3) {
+            dm.merge(k.clone(), append(b"m"))?;
+        }
+        for k in keys.iter().step_by(7) {
+            dm.remove(k)?;
+        }
+        dm.minor_compaction()?;
+        dm.merge(keys[1].clone(), append(b"n"))?;
+
+        let snapshot = dm.snapshot();
+        dm.insert(keys[2].clone(), make_payload(b"new".to_vec()))?;
+
+        // out of order, with a missing key and a repeated one
+        let mut wanted: Vec<StoreKey> = keys.iter().rev().cloned().collect();
+        wanted.push(make_key(b"missing".to_vec()));
+        wanted.push(keys[2].clone());
+
+        let values = dm.multi_get(None, &wanted)?;
+        assert_eq!(wanted.len(), values.len());
+        for (k, v) in wanted.iter().zip(&values) {
+            assert_eq!(&dm.get(k)?, v);
+        }
+        assert_eq!(Some(make_payload(b"new".to_vec())), values[41]);
+        let data = |p: &Option<StorePayload>| p.clone().map(|x| x.data);
+        assert_eq!(Some(Slice::from("k01n")), data(&values[38]));
+        assert_eq!(Some(Slice::from("k03m")), data(&values[36]));
+        assert_eq!(None, values[40]);
+        assert_eq!(None, values[39]);
+
+        let values = dm.multi_get(Some(&snapshot), &wanted)?;
+        for (k, v) in wanted.iter().zip(&values) {
+            assert_eq!(&dm.get_at(&snapshot, k)?, v);
+        }
+        assert_eq!(Some(make_payload(b"k02".to_vec())), values[41]);
+
+        assert!(dm.multi_get(None, &[])?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_row_cache() -> MyResult<()> {
+        let mut opt = get_test_opt();
+        opt.row_cache_size = 4 * KB;
+
+        let a = make_key(b"a".to_vec());
+        let b = make_key(b"b".to_vec());
+
+        let dm = DataManager::new(opt.clone())?;
+        dm.insert(a.clone(), make_payload(b"a1".to_vec()))?;
+        dm.insert(b.clone(), make_payload(b"b1".to_vec()))?;
+        dm.minor_compaction()?;
+
+        let stats = |dm: &DataManager| (dm.stats_.row_cache_hits(), dm.stats_.row_cache_misses());
+        assert_eq!(Some(make_payload(b"a1".to_vec())), dm.get(&a)?);
+        assert_eq!((0, 1), stats(&dm));
+        assert_eq!(Some(make_payload(b"a1".to_vec())), dm.get(&a)?);
+        assert_eq!((1, 1), stats(&dm));
+
+        // a write invalidates the key, its value in the memtable isn't cached
+        dm.insert(a.clone(), make_payload(b"a2".to_vec()))?;
+        assert_eq!(Some(make_payload(b"a2".to_vec())), dm.get(&a)?);
+        assert_eq!(Some(make_payload(b"a2".to_vec())), dm.get(&a)?);
+        assert_eq!((1, 3), stats(&dm));
+
+        assert_eq!(Some(make_payload(b"b1".to_vec())), dm.get(&b)?);
+        let snapshot = dm.snapshot();
+        assert_eq!(Some(make_payload(b"b1".to_vec())), dm.remove(&b)?);
+        assert_eq!((2, 4), stats(&dm));
+        assert_eq!(None, dm.get(&b)?);
+        assert_eq!(
+            Some(make_payload(b"b1".to_vec())),
+            dm.get_at(&snapshot, &b)?
+        );
+        assert_eq!((2, 5), stats(&dm));
+
+        dm.minor_compaction()?;
+        dm.major_compaction()?;
+        assert_eq!(Some(make_payload(b"a2".to_vec())), dm.get(&a)?);
+        assert_eq!(Some(make_payload(b"a2".to_vec())), dm.get(&a)?);
+        assert_eq!(None, dm.get(&b)?);
+        assert_eq!((3, 7), stats(&dm));
+        Ok(())
+    }
+
+    #[test]
+    fn test_blob_values() -> MyResult<()> {
+        let mut opt = get_test_opt();
+        opt.mem_table_max_size = MB;
+        opt.l0_compaction_trigger = 1;
+        opt.blob_value_threshold = 100;
+        opt.blob_gc_ratio = 0.5;
+
+        let big = |c: u8| make_payload(vec![c; 200]);
+        let keys: Vec<StoreKey> = (b'a'..=b'e').map(|x| make_key(vec![x])).collect();
+        // one memtable to one sstable and one blob file
+        let flush = |dm: &DataManager| -> MyResult<()> {
+            dm.rotate_memtable(&mut write_lock(&dm.wal_))?;
+            dm.minor_compaction()
+        };
+        let blob_numbers = |dm: &DataManager| -> Vec<usize> {
+            dm.versions_
+                .current()
+                .blob_files()
+                .map(|x| x.number())
+                .collect()
+        };
+
+        let dm = DataManager::new(opt.clone())?;
+        dm.insert(keys[0].clone(), big(b'a'))?;
+        dm.insert(keys[1].clone(), big(b'b'))?;
+        dm.insert(keys[2].clone(), big(b'c'))?;
+        dm.insert(keys[3].clone(), make_payload(b"d".to_vec()))?;
+        // expired already
+        dm.insert(
+            keys[4].clone(),
+            StorePayload::new(Slice::from(vec![b'e'; 200]), 0, 1, 0, 0),
+        )?;
+        flush(&dm)?;
+
+        let first = blob_numbers(&dm);
+        assert_eq!(1, first.len());
+        let version = dm.versions_.current();
+        // the blob file holds a, b, c and e
+        let record_size = version.blob_file(first[0]).unwrap().size() / 4;
+        assert!(record_size > 200);
+        let file = &version.get_files(0)[0];
+        assert!(points_into(file, first[0])?);
+        drop(version);
+        assert_eq!(Some(big(b'a')), dm.get(&keys[0])?);
+        assert_eq!(Some(make_payload(b"d".to_vec())), dm.get(&keys[3])?);
+        assert!(dm.get(&keys[4])?.unwrap().is_expired());
+
+        let snapshot = dm.snapshot();
+        dm.insert(keys[0].clone(), big(b'A'))?;
+        dm.remove(&keys[1])?;
+        flush(&dm)?;
+        dm.major_compaction()?;
+        assert_eq!(2, blob_numbers(&dm).len());
+        assert_eq!(Some(big(b'a')), dm.get_at(&snapshot, &keys[0])?);
+        assert_eq!(Some(big(b'b')), dm.get_at(&snapshot, &keys[1])?);
+        // only the expired value is stale, the snapshot sees the others
+        let stale = |dm: &DataManager| dm.versions_.current().blob_file(first[0]).unwrap().stale();
+        assert_eq!(record_size, stale(&dm));
+
+        drop(snapshot);
+        dm.insert(keys[3].clone(), make_payload(b"D".to_vec()))?;
+        flush(&dm)?;
+        dm.major_compaction()?;
+        assert_eq!(3 * record_size, stale(&dm));
+
+        // the garbage collection moves the value of c
+        dm.major_compaction()?;
+        assert_eq!(1, dm.stats_.blob_gcs());
+        let blobs = blob_numbers(&dm);
+        assert_eq!(2, blobs.len());
+        assert!(!blobs.contains(&first[0]));
+        let path = Path::new(&opt.work_dir).join(make_file_name(first[0], "blob"));
+        assert!(!path.exists());
+        assert!(dm.versions_.current().pick_blob_file_for_gc().is_none());
+
+        let expected = vec![
+            (keys[0].clone(), big(b'A')),
+            (keys[2].clone(), big(b'c')),
+            (keys[3].clone(), make_payload(b"D".to_vec())),
+        ];
+        assert_eq!(expected, dm.iter(None, None, None).collect::<Vec<_>>());
+        drop(dm);
+
+        let dm = DataManager::new(opt.clone())?;
+        assert_eq!(blobs, blob_numbers(&dm));
+        for (k, v) in &expected {
+            assert_eq!(Some(v.clone()), dm.get(k)?);
+        }
+        assert_eq!(None, dm.get(&keys[1])?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_blob_gc_files() -> MyResult<()> {
+        let mut opt = get_test_opt();
+        opt.mem_table_max_size = MB;
+        opt.blob_value_threshold = 100;
+        opt.blob_gc_ratio = 0.5;
+
+        let big = |c: u8| make_payload(vec![c; 200]);
+        let dm = DataManager::new(opt.clone())?;
+        // two sstables of level 0, each with a blob file of its own
+        dm.insert(make_key(b"a".to_vec()), big(b'a'))?;
+        dm.rotate_memtable(&mut write_lock(&dm.wal_))?;
+        dm.minor_compaction()?;
+        dm.insert(make_key(b"b".to_vec()), big(b'b'))?;
+        dm.insert(make_key(b"c".to_vec()), make_payload(b"c".to_vec()))?;
+        dm.rotate_memtable(&mut write_lock(&dm.wal_))?;
+        dm.minor_compaction()?;
+
+        let version = dm.versions_.current();
+        let files = version.get_files(0).to_vec();
+        assert_eq!(2, files.len());
+        let victim = files[1].blob_files().unwrap()[0];
+        let other = files[0].blob_files().unwrap()[0];
+        assert_ne!(victim, other);
+        assert_eq!(Some(&[other][..]), files[0].blob_files());
+        let size = version.blob_file(victim).unwrap().size();
+        drop(version);
+
+        let mut edit = VersionEdit::default();
+        edit.add_blob_stale(victim, size);
+        dm.versions_.log_and_apply(edit, vec![])?;
+        assert!(dm.blob_gc()?);
+
+        // only the sstable pointing into the victim is rewritten
+        let version = dm.versions_.current();
+        let new_files = version.get_files(0);
+        assert_eq!(2, new_files.len());
+        assert!(new_files.iter().any(|x| Arc::ptr_eq(x, &files[0])));
+        assert!(!new_files.iter().any(|x| Arc::ptr_eq(x, &files[1])));
+        assert!(version.blob_file(victim).is_none());
+        drop(version);
+        assert_eq!(Some(big(b'a')), dm.get(&make_key(b"a".to_vec()))?);
+        assert_eq!(Some(big(b'b')), dm.get(&make_key(b"b".to_vec()))?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete_range() -> MyResult<()> {
+        let mut opt = get_test_opt();
+        opt.mem_table_max_size = 20;
+        opt.l0_compaction_trigger = 1;
+
+        let dm = DataManager::new(opt.clone())?;
+        let key = |k: &str| make_key(k.as_bytes().to_vec());
+        for k in &["a", "b", "c", "d", "e"] {
+            dm.insert(key(k), make_payload(k.as_bytes().to_vec()))?;
+        }
+        dm.minor_compaction()?;
+        dm.major_compaction()?;
+
+        let snapshot = dm.snapshot();
+        dm.delete_range(b"b", b"d")?;
+        assert!(dm.delete_range(b"d", b"b").is_err());
+        let check = |dm: &DataManager| -> MyResult<()> {
+            assert_eq!(None, dm.get(&key("b"))?);
+            assert_eq!(Some(make_payload(b"d".to_vec())), dm.get(&key("d"))?);
+            let keys: Vec<StoreKey> = ["a", "b", "c", "d"].iter().map(|k| key(k)).collect();
+            let found: Vec<bool> = dm
+                .multi_get(None, &keys)?
+                .iter()
+                .map(|x| x.is_some())
+                .collect();
+            assert_eq!(vec![true, false, false, true], found);
+            let keys: Vec<StoreKey> = dm.iter(None, None, None).map(|(k, _)| k).collect();
+            assert_eq!(vec![key("a"), key("d"), key("e")], keys);
+            Ok(())
+        };
+        // in the memtables, then in level 0
+        check(&dm)?;
+        dm.minor_compaction()?;
+        check(&dm)?;
+        assert_eq!(1, dm.versions_.current().range_deletions().len());
+        assert_eq!(
+            Some(make_payload(b"b".to_vec())),
+            dm.get_at(&snapshot, &key("b"))?
+        );
+        assert_eq!(2, dm.iter(Some(&snapshot), None, Some(key("c"))).count());
+
+        // a later write is not deleted
+        dm.insert(key("c"), make_payload(b"C".to_vec()))?;
+        assert_eq!(Some(make_payload(b"C".to_vec())), dm.get(&key("c"))?);
+
+        // the snapshot keeps what it sees
+        dm.minor_compaction()?;
+        dm.major_compaction()?;
+        assert_eq!(6, sst_entry_count(&dm));
+        assert_eq!(
+            Some(make_payload(b"c".to_vec())),
+            dm.get_at(&snapshot, &key("c"))?
+        );
+
+        // then the deleted keys and the tombstone are dropped, nothing is
+        // left under it
+        drop(snapshot);
+        dm.insert(key("a"), make_payload(b"A".to_vec()))?;
+        dm.insert(key("e"), make_payload(b"E".to_vec()))?;
+        dm.minor_compaction()?;
+        dm.major_compaction()?;
+        assert_eq!(4, sst_entry_count(&dm));
+        assert!(dm.versions_.current().range_deletions().is_empty());
+        assert_eq!(None, dm.get(&key("b"))?);
+        assert_eq!(Some(make_payload(b"C".to_vec())), dm.get(&key("c"))?);
+
+        // replayed from the log
+        dm.delete_range(b"a", b"d")?;
+        drop(dm);
+        let dm = DataManager::new(opt.clone())?;
+        let keys: Vec<StoreKey> = dm.iter(None, None, None).map(|(k, _)| k).collect();
+        assert_eq!(vec![key("d"), key("e")], keys);
+        Ok(())
+    }
+
+    #[test]
+    fn test_range_deletion_bounds() -> MyResult<()> {
+        let mut opt = get_test_opt();
+        opt.mem_table_max_size = MB;
+        opt.l0_compaction_trigger = 1;
+
+        let dm = DataManager::new(opt)?;
+        let key = |k: &str| make_key(k.as_bytes().to_vec());
+        for k in &["a", "b", "c"] {
+            dm.insert(key(k), make_payload(k.as_bytes().to_vec()))?;
+        }
+        dm.rotate_memtable(&mut write_lock(&dm.wal_))?;
+        dm.minor_compaction()?;
+        dm.major_compaction()?;
+
+        // the tombstone outlives the entry it deletes, it starts in the
+        // file of a to c, which the compaction doesn't take
+        let snapshot = dm.snapshot();
+        dm.insert(key("m"), make_payload(b"m".to_vec()))?;
+        dm.delete_range(b"b", b"z")?;
+        dm.rotate_memtable(&mut write_lock(&dm.wal_))?;
+        dm.minor_compaction()?;
+        dm.major_compaction()?;
+
+        let version = dm.versions_.current();
+        let files = version.get_files(1);
+        assert_eq!(2, files.len());
+        assert_eq!(1, files[1].range_tombstones().len());
+        assert_eq!(0, files[1].iter().count());
+        for pair in files.windows(2) {
+            assert!(user_key(pair[0].max_key()) < user_key(pair[1].min_key()));
+        }
+        drop(version);
+        assert_eq!(Some(make_payload(b"a".to_vec())), dm.get(&key("a"))?);
+        assert_eq!(None, dm.get(&key("b"))?);
+        assert_eq!(None, dm.get(&key("m"))?);
+        assert_eq!(
+            Some(make_payload(b"b".to_vec())),
+            dm.get_at(&snapshot, &key("b"))?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete_range_locks() -> MyResult<()> {
+        let mut opt = get_test_opt();
+        opt.mem_table_max_size = MB;
+        let dm = DataManager::new(opt)?;
+        let k = make_key(b"b".to_vec());
+        dm.insert(k.clone(), make_payload(b"b".to_vec()))?;
+
+        // waits for a write which read the key under its stripe
+        let guard = dm.lock_key(k.as_ref());
+        let done = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let dm = dm.clone();
+            let done = done.clone();
+            thread::spawn(move || {
+                dm.delete_range(b"a", b"c").unwrap();
+                done.store(true, Relaxed);
+            })
+        };
+        thread::sleep(Duration::from_millis(200));
+        assert!(!done.load(Relaxed));
+        dm.write(
+            k.as_ref(),
+            Slice::from(serialize(&make_payload(b"B".to_vec()))?),
+            ValueType::Value,
+        )?;
+        drop(guard);
+        handle.join().unwrap();
+        assert_eq!(None, dm.get(&k)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_stall() -> MyResult<()> {
+        let mut opt = get_test_opt();
+        opt.imm_mem_table_max_count = 2;
+        opt.l0_slowdown_writes_trigger = 1;
+
+        let dm = DataManager::new(opt)?;
+        dm.stall_writes_.store(true, Relaxed);
+        dm.insert(make_key(b"a".to_vec()), make_payload(b"a".to_vec()))?;
+        dm.insert(make_key(b"b".to_vec()), make_payload(b"b".to_vec()))?;
+        assert_eq!(0, dm.stats_.stop_micros());
+
+        // the memtables are full until a flush
+        let done = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let dm = dm.clone();
+            let done = done.clone();
+            thread::spawn(move || {
+                dm.insert(make_key(b"c".to_vec()), make_payload(b"c".to_vec()))
+                    .unwrap();
+                done.store(true, Relaxed);
+            })
+        };
+        thread::sleep(Duration::from_millis(200));
+        assert!(!done.load(Relaxed));
+        dm.minor_compaction()?;
+        handle.join().unwrap();
+        assert!(dm.stats_.stop_micros() > 0);
+        assert_eq!(0, dm.stats_.slowdown_micros());
+
+        // level 0 is past the slowdown trigger
+        dm.insert(make_key(b"d".to_vec()), make_payload(b"d".to_vec()))?;
+        assert!(dm.stats_.slowdown_micros() > 0);
+        assert_eq!(
+            Some(make_payload(b"c".to_vec())),
+            dm.get(&make_key(b"c".to_vec()))?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_shutdown() -> MyResult<()> {
+        let mut opt = get_test_opt();
+        opt.mem_table_max_size = 1 << 20;
+
+        let dm = DataManager::new(opt.clone())?;
+        DataManager::start_background_jobs(&dm);
+        for i in 0..10 {
+            let k = make_key(format!("{:02}", i).into_bytes());
+            dm.insert(k, make_payload(vec![i; 10]))?;
+        }
+        let mem_table_bytes = |dm: &DataManager| -> usize {
+            let items = dm.stats();
+            let item = items.iter().find(|x| x.0 == "mem_table_bytes").unwrap();
+            item.1.parse().unwrap()
+        };
+        let before = mem_table_bytes(&dm);
+        assert!(before > 10 * 10);
+        dm.shutdown()?;
+        assert!(mem_table_bytes(&dm) < before);
+        assert!(read_lock(&dm.scheduler_).is_none());
+        assert_eq!(0, read_lock(&dm.mut_).length());
+        assert_eq!(0, dm.imm_table_count());
+        assert_eq!(1, dm.level_file_count(0));
+        drop(dm);
+
+        // everything is in the sstables
+        let dm = DataManager::new(opt)?;
+        assert_eq!(0, read_lock(&dm.mut_).length());
+        assert_eq!(1, dm.level_file_count(0));
+        for i in 0..10 {
+            let k = make_key(format!("{:02}", i).into_bytes());
+            assert_eq!(Some(make_payload(vec![i; 10])), dm.get(&k)?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_rotate_memtable() -> MyResult<()> {
+        let mut opt = get_test_opt();
+        opt.mem_table_max_size = 1 << 20;
+        opt.imm_mem_table_max_count = 4;
+
+        let dm = DataManager::new(opt)?;
+        for i in 0..10 {
+            let k = make_key(format!("{:02}", i).into_bytes());
+            dm.insert(k, make_payload(vec![i; 10]))?;
+        }
+        let sealed = read_lock(&dm.mut_).clone();
+        assert_eq!(10, sealed.length());
+        dm.rotate_memtable(&mut write_lock(&dm.wal_))?;
+
+        // the table itself is moved, not a copy of it
+        assert_eq!(1, dm.imm_table_count());
+        assert!(Arc::ptr_eq(
+            &sealed,
+            read_lock(&dm.imm_).tables_iter().next().unwrap()
+        ));
+        let muttable = read_lock(&dm.mut_).clone();
+        assert!(!Arc::ptr_eq(&sealed, &muttable));
+        assert_eq!(0, muttable.length());
+        assert_eq!(10, sealed.length());
+        for i in 0..10 {
+            let k = make_key(format!("{:02}", i).into_bytes());
+            assert_eq!(Some(make_payload(vec![i; 10])), dm.get(&k)?);
+        }
+        Ok(())
+    }
 }
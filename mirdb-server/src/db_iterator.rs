@@ -0,0 +1,578 @@
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use bincode::deserialize;
+
+use sstable::Comparator;
+use sstable::SsIterator;
+use sstable::TableIter;
+
+use crate::error::MyResult;
+use crate::error::Status;
+use crate::error::StatusCode;
+use crate::internal_key::make_lookup_key;
+use crate::internal_key::parse_internal_key;
+use crate::internal_key::InternalKeyComparator;
+use crate::internal_key::SeqNum;
+use crate::internal_key::ValueType;
+use crate::internal_key::MAX_SEQ_NUM;
+use crate::memtable::MemtableIter;
+use crate::merge_operator::MergeContext;
+use crate::merge_operator::MergeOperator;
+use crate::merger::Merger;
+use crate::range_del::RangeTombstones;
+use crate::slice::Slice;
+use crate::store::StoreKey;
+use crate::store::StorePayload;
+use crate::version::TableFile;
+use crate::version::Version;
+
+/// How a `DbIterator` reads: the order of the user keys and how their merge
+/// operands are applied, the sequence number it reads at and the bounds of
+/// the keys, `[lower_bound, upper_bound)`.
+pub struct ReadOptions {
+    pub comparator: Arc<dyn Comparator>,
+    pub merge_operator: Option<Arc<dyn MergeOperator>>,
+    pub seq: SeqNum,
+    pub lower_bound: Option<StoreKey>,
+    pub upper_bound: Option<StoreKey>,
+}
+
+/// Iterates the user keys visible at a sequence number, in the range
+/// `[lower_bound, upper_bound)`. Deleted and expired keys are hidden, as are
+/// the older entries of a key and the ones under a range tombstone, merge
+/// operands are applied.
+///
+/// Moving forward, the merger is at or past the entries of the current key.
+/// Moving backward, it is at the last entry before the entries of the
+/// current key.
+pub struct DbIterator {
+    cmp_: Arc<dyn Comparator>,
+    merger_: Merger<'static>,
+    version_: Arc<Version>,
+    range_dels_: RangeTombstones,
+    merge_operator_: Option<Arc<dyn MergeOperator>>,
+    seq_: SeqNum,
+    lower_bound_: Option<StoreKey>,
+    upper_bound_: Option<StoreKey>,
+    current_: Option<(StoreKey, StorePayload)>,
+    backward_: bool,
+    started_: bool,
+    status_: Option<Status>,
+}
+
+impl DbIterator {
+    /// `memtables` go from the newest to the oldest, `range_dels` are the
+    /// range tombstones of the memtables and of the version.
+    pub fn new(
+        memtables: Vec<MemtableIter>,
+        version: Arc<Version>,
+        range_dels: RangeTombstones,
+        max_level: usize,
+        opt: ReadOptions,
+    ) -> Self {
+        let mut iters: Vec<Box<dyn SsIterator>> = vec![];
+        for memtable in memtables {
+            iters.push(Box::new(memtable));
+        }
+        for level in 0..max_level {
+            let files = version.get_files(level);
+            let files: Vec<&Arc<TableFile>> = if level == 0 {
+                files.iter().rev().collect()
+            } else {
+                files.iter().collect()
+            };
+            for file in files {
+                iters.push(Box::new(TableIter::new_shared(file.clone())));
+            }
+        }
+        let cmp = Arc::new(InternalKeyComparator::new(opt.comparator.clone()));
+        DbIterator {
+            cmp_: opt.comparator,
+            merger_: Merger::new(iters, cmp),
+            version_: version,
+            range_dels_: range_dels,
+            merge_operator_: opt.merge_operator,
+            seq_: opt.seq,
+            lower_bound_: opt.lower_bound,
+            upper_bound_: opt.upper_bound,
+            current_: None,
+            backward_: false,
+            started_: false,
+            status_: None,
+        }
+    }
+
+    pub fn valid(&self) -> bool {
+        self.current_.is_some()
+    }
+
+    pub fn key(&self) -> Option<&StoreKey> {
+        self.current_.as_ref().map(|x| &x.0)
+    }
+
+    pub fn value(&self) -> Option<&StorePayload> {
+        self.current_.as_ref().map(|x| &x.1)
+    }
+
+    /// The error which stopped the iterator, if any.
+    pub fn status(&self) -> Option<&Status> {
+        self.status_.as_ref()
+    }
+
+    pub fn seek_to_first(&mut self) {
+        match self.lower_bound_.clone() {
+            Some(lower_bound) => self.seek(&lower_bound[..]),
+            None => {
+                self.started_ = true;
+                self.merger_.reset();
+                self.merger_.advance();
+                self.find_next_user_entry(None);
+            }
+        }
+    }
+
+    pub fn seek_to_last(&mut self) {
+        self.started_ = true;
+        match self.upper_bound_.clone() {
+            Some(upper_bound) => {
+                self.merger_
+                    .seek(&make_lookup_key(upper_bound.as_ref(), MAX_SEQ_NUM)[..]);
+                if self.merger_.valid() {
+                    self.merger_.prev();
+                } else {
+                    self.merger_.seek_to_last();
+                }
+            }
+            None => self.merger_.seek_to_last(),
+        }
+        self.find_prev_user_entry();
+    }
+
+    /// Moves to the first key not less than `key`.
+    pub fn seek(&mut self, key: &[u8]) {
+        self.started_ = true;
+        let key = match &self.lower_bound_ {
+            Some(lower_bound)
+                if self.cmp_.compare(lower_bound.as_ref(), key) == Ordering::Greater =>
+            {
+                lower_bound.clone()
+            }
+            _ => Slice::from(key),
+        };
+        self.merger_
+            .seek(&make_lookup_key(key.as_ref(), self.seq_)[..]);
+        self.find_next_user_entry(None);
+    }
+
+    pub fn advance(&mut self) -> bool {
+        if !self.started_ {
+            self.seek_to_first();
+            return self.valid();
+        }
+        let key = match self.current_.take() {
+            Some((key, _)) => key,
+            None => return false,
+        };
+        if self.backward_ {
+            if self.merger_.valid() {
+                self.merger_.advance();
+            } else {
+                self.merger_.reset();
+                self.merger_.advance();
+            }
+        }
+        // every entry left of the current key is older than the one returned
+        self.find_next_user_entry(Some(key));
+        self.valid()
+    }
+
+    pub fn prev(&mut self) -> bool {
+        let key = match self.current_.take() {
+            Some((key, _)) => key,
+            None => return false,
+        };
+        if !self.backward_ {
+            self.merger_
+                .seek(&make_lookup_key(key.as_ref(), MAX_SEQ_NUM)[..]);
+            if self.merger_.valid() {
+                self.merger_.prev();
+            } else {
+                self.merger_.seek_to_last();
+            }
+        }
+        self.find_prev_user_entry();
+        self.valid()
+    }
+
+    fn fail(&mut self, status: Status) {
+        self.current_ = None;
+        self.status_ = Some(status);
+    }
+
+    fn decode(&mut self, v: &[u8]) -> Option<StorePayload> {
+        match deserialize(v) {
+            Ok(payload) => Some(payload),
+            Err(e) => {
+                self.fail(Status::from(e));
+                None
+            }
+        }
+    }
+
+    fn is_past_upper_bound(&self, key: &[u8]) -> bool {
+        match &self.upper_bound_ {
+            Some(upper_bound) => self.cmp_.compare(key, upper_bound.as_ref()) != Ordering::Less,
+            None => false,
+        }
+    }
+
+    fn is_before_lower_bound(&self, key: &[u8]) -> bool {
+        match &self.lower_bound_ {
+            Some(lower_bound) => self.cmp_.compare(key, lower_bound.as_ref()) == Ordering::Less,
+            None => false,
+        }
+    }
+
+    /// A context for the entries of `key`, which deletes the ones under a
+    /// range tombstone.
+    fn new_context(&self, key: &[u8]) -> MergeContext {
+        let mut ctx = MergeContext::new();
+        if let Some(seq) = self.range_dels_.covering_seq(key, self.seq_) {
+            ctx.set_range_deletion(seq);
+        }
+        ctx
+    }
+
+    /// The value of `key` out of its entries, `None` if it has no live value.
+    fn resolve(&mut self, key: &[u8], mut ctx: MergeContext) -> Option<StorePayload> {
+        if let Err(e) = self.version_.resolve_blob(&mut ctx) {
+            self.fail(e);
+            return None;
+        }
+        let merged = ctx.finish(key, self.merge_operator_.as_ref().map(|x| x.as_ref()));
+        let v = match merged {
+            Ok(Some(v)) => v,
+            Ok(None) => return None,
+            Err(e) => {
+                self.fail(e);
+                return None;
+            }
+        };
+        let payload = self.decode(&v[..])?;
+        if payload.is_expired() {
+            None
+        } else {
+            Some(payload)
+        }
+    }
+
+    /// Moves forward to the next live key, the entries of `skip` are passed
+    /// over. The merger ends up at or past the entries the value of the key
+    /// is made of.
+    fn find_next_user_entry(&mut self, mut skip: Option<StoreKey>) {
+        self.backward_ = false;
+        self.current_ = None;
+        while let Some((k, v)) = self.merger_.current_kv() {
+            let parsed = match parse_internal_key(&k) {
+                Some(parsed) => parsed,
+                None => return self.fail(Status::new(StatusCode::Other, "bad internal key")),
+            };
+            if self.is_past_upper_bound(parsed.user_key) {
+                return;
+            }
+            let skipped = match &skip {
+                Some(skip) => skip.as_ref() == parsed.user_key,
+                None => false,
+            };
+            if parsed.seq > self.seq_ || skipped {
+                self.merger_.advance();
+                continue;
+            }
+
+            let user_key = Slice::from(parsed.user_key);
+            let mut ctx = self.new_context(&user_key[..]);
+            ctx.add(parsed.seq, parsed.value_type, Slice::from(v));
+            // the older entries, which the merge operands apply to
+            while !ctx.is_done() {
+                self.merger_.advance();
+                let (k, v) = match self.merger_.current_kv() {
+                    Some(kv) => kv,
+                    None => break,
+                };
+                match parse_internal_key(&k) {
+                    Some(parsed) if parsed.user_key == &user_key[..] => {
+                        ctx.add(parsed.seq, parsed.value_type, Slice::from(v))
+                    }
+                    _ => break,
+                }
+            }
+            if let Some(payload) = self.resolve(&user_key[..], ctx) {
+                self.current_ = Some((user_key, payload));
+                return;
+            }
+            if self.status_.is_some() {
+                return;
+            }
+            skip = Some(user_key);
+        }
+    }
+
+    /// Moves backward to the previous live key, collecting its visible
+    /// entries on the way, and stops before its entries.
+    fn find_prev_user_entry(&mut self) {
+        self.backward_ = true;
+        self.current_ = None;
+        let mut saved_key: Option<StoreKey> = None;
+        // the visible entries of the saved key from the oldest, the older
+        // ones than its newest value or deletion don't matter
+        let mut entries: Vec<(SeqNum, ValueType, Slice)> = vec![];
+        while let Some((k, v)) = self.merger_.current_kv() {
+            let parsed = match parse_internal_key(&k) {
+                Some(parsed) => parsed,
+                None => return self.fail(Status::new(StatusCode::Other, "bad internal key")),
+            };
+            if self.is_before_lower_bound(parsed.user_key) {
+                break;
+            }
+            if parsed.seq <= self.seq_ {
+                let is_new_user_key = match &saved_key {
+                    Some(saved_key) => saved_key.as_ref() != parsed.user_key,
+                    None => true,
+                };
+                if is_new_user_key {
+                    if let Some(key) = saved_key.take() {
+                        if let Some(payload) = self.resolve_entries(&key[..], &entries) {
+                            self.current_ = Some((key, payload));
+                            return;
+                        }
+                        if self.status_.is_some() {
+                            return;
+                        }
+                    }
+                    saved_key = Some(Slice::from(parsed.user_key));
+                    entries.clear();
+                }
+                match parsed.value_type {
+                    ValueType::Merge => {
+                        entries.push((parsed.seq, ValueType::Merge, Slice::from(v)))
+                    }
+                    value_type => entries = vec![(parsed.seq, value_type, Slice::from(v))],
+                }
+            }
+            self.merger_.prev();
+        }
+        if let Some(key) = saved_key {
+            if let Some(payload) = self.resolve_entries(&key[..], &entries) {
+                self.current_ = Some((key, payload));
+            }
+        }
+    }
+
+    fn resolve_entries(
+        &mut self,
+        key: &[u8],
+        entries: &[(SeqNum, ValueType, Slice)],
+    ) -> Option<StorePayload> {
+        let mut ctx = self.new_context(key);
+        for (seq, value_type, v) in entries.iter().rev() {
+            if ctx.is_done() {
+                break;
+            }
+            ctx.add(*seq, *value_type, v.clone());
+        }
+        self.resolve(key, ctx)
+    }
+}
+
+impl Iterator for DbIterator {
+    type Item = (StoreKey, StorePayload);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.advance() {
+            self.current_.clone()
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bincode::serialize;
+
+    use crate::data_manager::DataManager;
+    use crate::store::StoreOperand;
+    use crate::test_utils::get_test_opt;
+
+    use super::*;
+
+    fn make_key(k: &[u8]) -> StoreKey {
+        Slice::from(k)
+    }
+
+    fn make_payload(v: &[u8]) -> StorePayload {
+        StorePayload::new(Slice::from(v), 0, 0, 0, 0)
+    }
+
+    fn keys(iter: DbIterator) -> Vec<Vec<u8>> {
+        iter.map(|(k, _)| k[..].to_vec()).collect()
+    }
+
+    fn build() -> MyResult<Arc<DataManager>> {
+        let mut opt = get_test_opt();
+        opt.imm_mem_table_max_count = 3;
+        opt.mem_table_max_size = 20;
+        opt.sst_max_size = 60;
+        opt.l0_compaction_trigger = 1;
+
+        let dm = DataManager::new(opt)?;
+        for k in b'a'..=b'h' {
+            dm.insert(make_key(&[k]), make_payload(&[k; 20]))?;
+        }
+        dm.minor_compaction()?;
+        dm.major_compaction()?;
+        // shadow and delete some of the compacted keys
+        dm.insert(make_key(b"b"), make_payload(b"b2"))?;
+        dm.remove(&make_key(b"c"))?;
+        dm.minor_compaction()?;
+        dm.remove(&make_key(b"e"))?;
+        dm.insert(
+            make_key(b"f"),
+            StorePayload::new(Slice::from(&b"f2"[..]), 0, 1, 0, 0),
+        )?;
+        dm.insert(make_key(b"i"), make_payload(b"i1"))?;
+        Ok(dm)
+    }
+
+    #[test]
+    fn test_forward_backward() -> MyResult<()> {
+        let dm = build()?;
+        let expected: Vec<Vec<u8>> = vec![b"a", b"b", b"d", b"g", b"h", b"i"]
+            .into_iter()
+            .map(|x| x.to_vec())
+            .collect();
+        assert_eq!(expected, keys(dm.iter(None, None, None)));
+
+        let mut iter = dm.iter(None, None, None);
+        iter.seek_to_last();
+        let mut backward = vec![];
+        while iter.valid() {
+            backward.push(iter.key().unwrap()[..].to_vec());
+            iter.prev();
+        }
+        backward.reverse();
+        assert_eq!(expected, backward);
+        assert!(iter.status().is_none());
+
+        let mut iter = dm.iter(None, None, None);
+        iter.seek(b"b");
+        assert_eq!(Some(&make_payload(b"b2")), iter.value());
+        assert!(iter.advance());
+        assert_eq!(Some(&make_key(b"d")), iter.key());
+        assert!(iter.prev());
+        assert_eq!(Some(&make_key(b"b")), iter.key());
+        assert!(iter.prev());
+        assert_eq!(Some(&make_key(b"a")), iter.key());
+        assert!(!iter.prev());
+        iter.seek(b"c");
+        assert_eq!(Some(&make_key(b"d")), iter.key());
+        iter.seek(b"j");
+        assert!(!iter.valid());
+        Ok(())
+    }
+
+    #[test]
+    fn test_bounds() -> MyResult<()> {
+        let dm = build()?;
+        let iter = dm.iter(None, Some(make_key(b"b")), Some(make_key(b"h")));
+        assert_eq!(
+            vec![b"b".to_vec(), b"d".to_vec(), b"g".to_vec()],
+            keys(iter)
+        );
+
+        let mut iter = dm.iter(None, Some(make_key(b"c")), Some(make_key(b"h")));
+        iter.seek_to_last();
+        assert_eq!(Some(&make_key(b"g")), iter.key());
+        assert!(iter.prev());
+        assert_eq!(Some(&make_key(b"d")), iter.key());
+        assert!(!iter.prev());
+        iter.seek(b"a");
+        assert_eq!(Some(&make_key(b"d")), iter.key());
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot() -> MyResult<()> {
+        let dm = build()?;
+        let snapshot = dm.snapshot();
+        dm.insert(make_key(b"c"), make_payload(b"c3"))?;
+        dm.remove(&make_key(b"a"))?;
+        dm.minor_compaction()?;
+
+        let iter = dm.iter(Some(&snapshot), None, None);
+        assert_eq!(
+            vec![b"a", b"b", b"d", b"g", b"h", b"i"]
+                .into_iter()
+                .map(|x| x.to_vec())
+                .collect::<Vec<_>>(),
+            keys(iter)
+        );
+
+        // the iterator is not affected by the later writes
+        let iter = dm.iter(None, None, None);
+        dm.remove(&make_key(b"b"))?;
+        dm.minor_compaction()?;
+        dm.major_compaction()?;
+        assert_eq!(
+            vec![b"b", b"c", b"d", b"g", b"h", b"i"]
+                .into_iter()
+                .map(|x| x.to_vec())
+                .collect::<Vec<_>>(),
+            keys(iter)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge() -> MyResult<()> {
+        let dm = build()?;
+        let append = |data: &[u8]| {
+            let operand = StoreOperand::Append(make_payload(data));
+            Slice::from(serialize(&operand).unwrap())
+        };
+        dm.merge(make_key(b"a"), append(b"x"))?;
+        dm.minor_compaction()?;
+        dm.merge(make_key(b"a"), append(b"y"))?;
+        dm.merge(make_key(b"b"), append(b"x"))?;
+        // no value to merge into
+        dm.merge(make_key(b"c"), append(b"x"))?;
+
+        let mut a = vec![b'a'; 20];
+        a.extend_from_slice(b"xy");
+        let expected = vec![
+            (make_key(b"a"), Slice::from(a)),
+            (make_key(b"b"), Slice::from("b2x")),
+            (make_key(b"d"), Slice::from(vec![b'd'; 20])),
+        ];
+        let forward: Vec<_> = dm
+            .iter(None, None, Some(make_key(b"e")))
+            .map(|(k, v)| (k, v.data))
+            .collect();
+        assert_eq!(expected, forward);
+
+        let mut iter = dm.iter(None, None, Some(make_key(b"e")));
+        iter.seek_to_last();
+        let mut backward = vec![];
+        while iter.valid() {
+            backward.push((
+                iter.key().unwrap().clone(),
+                iter.value().unwrap().data.clone(),
+            ));
+            iter.prev();
+        }
+        backward.reverse();
+        assert_eq!(expected, backward);
+        Ok(())
+    }
+}
@@ -16,6 +16,10 @@ pub enum StatusCode {
     ChecksumError,
     SnapError,
     ConfigError,
+    ManifestError,
+    BlobError,
+    /// Data which passes its checksum but can't be decoded.
+    Corruption,
 }
 
 #[derive(Debug, PartialEq)]
@@ -66,6 +70,7 @@ impl Into<::std::io::Error> for Status {
     fn into(self) -> ::std::io::Error {
         match self.code {
             StatusCode::NotFound => ::std::io::ErrorKind::NotFound.into(),
+            StatusCode::Corruption => ::std::io::ErrorKind::InvalidData.into(),
             _ => ::std::io::ErrorKind::Other.into(),
         }
     }
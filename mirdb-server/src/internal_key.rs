@@ -0,0 +1,281 @@
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use sstable::Comparator;
+
+use crate::slice::Slice;
+
+pub type SeqNum = u64;
+
+/// Sequence numbers share 8 bytes with the value type.
+pub const MAX_SEQ_NUM: SeqNum = (1 << 56) - 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueType {
+    Deletion = 0,
+    Value = 1,
+    Merge = 2,
+    /// The value is in a blob file, the entry holds a `BlobIndex`.
+    BlobIndex = 3,
+    /// Deletes the user keys from the one of the entry to the one its value
+    /// holds, excluded.
+    RangeDeletion = 4,
+}
+
+/// The greatest value type, it sorts first among the entries of a sequence
+/// number, so a lookup key built with it is not past any of them.
+const VALUE_TYPE_FOR_SEEK: ValueType = ValueType::RangeDeletion;
+
+impl ValueType {
+    fn from_u8(t: u8) -> Option<Self> {
+        match t {
+            0 => Some(ValueType::Deletion),
+            1 => Some(ValueType::Value),
+            2 => Some(ValueType::Merge),
+            3 => Some(ValueType::BlobIndex),
+            4 => Some(ValueType::RangeDeletion),
+            _ => None,
+        }
+    }
+}
+
+// An internal key is laid out as `user key | 0 | !(seq << 8 | type)`, the
+// trailer is big endian and inverted so that the newer entries of a user key
+// sort first with a bytewise comparison. User keys never contain a zero
+// byte, the protocol does not allow it, so the separator keeps different
+// user keys in the order of the user keys.
+const TRAILER_SIZE: usize = 9;
+
+#[derive(Debug, PartialEq)]
+pub struct ParsedInternalKey<'a> {
+    pub user_key: &'a [u8],
+    pub seq: SeqNum,
+    pub value_type: ValueType,
+}
+
+pub fn make_internal_key(user_key: &[u8], seq: SeqNum, value_type: ValueType) -> Slice {
+    assert!(seq <= MAX_SEQ_NUM);
+    let mut buf = Vec::with_capacity(user_key.len() + TRAILER_SIZE);
+    buf.extend_from_slice(user_key);
+    buf.push(0);
+    buf.extend_from_slice(&(!(seq << 8 | value_type as u64)).to_be_bytes());
+    Slice::from(buf)
+}
+
+/// The key to seek to for the newest entry of `user_key` which is visible
+/// at `seq`.
+pub fn make_lookup_key(user_key: &[u8], seq: SeqNum) -> Slice {
+    make_internal_key(user_key, seq, VALUE_TYPE_FOR_SEEK)
+}
+
+pub fn user_key(internal_key: &[u8]) -> &[u8] {
+    if internal_key.len() < TRAILER_SIZE {
+        return internal_key;
+    }
+    &internal_key[..internal_key.len() - TRAILER_SIZE]
+}
+
+pub fn parse_internal_key(internal_key: &[u8]) -> Option<ParsedInternalKey> {
+    if internal_key.len() < TRAILER_SIZE {
+        return None;
+    }
+    let n = internal_key.len() - TRAILER_SIZE;
+    if internal_key[n] != 0 {
+        return None;
+    }
+    let mut trailer = [0; 8];
+    trailer.copy_from_slice(&internal_key[n + 1..]);
+    let trailer = !u64::from_be_bytes(trailer);
+    Some(ParsedInternalKey {
+        user_key: &internal_key[..n],
+        seq: trailer >> 8,
+        value_type: ValueType::from_u8(trailer as u8)?,
+    })
+}
+
+/// The user key and the trailer of a key, which is all user key if it is
+/// not an internal one, the separators of an index may be so.
+fn split(k: &[u8]) -> (&[u8], &[u8]) {
+    if k.len() < TRAILER_SIZE || k[k.len() - TRAILER_SIZE] != 0 {
+        return (k, &[]);
+    }
+    k.split_at(k.len() - TRAILER_SIZE)
+}
+
+/// Orders the internal keys by their user keys with the user comparator,
+/// then by their trailers, so the newest entry of a user key comes first.
+/// The memtables and the sstables are sorted by it.
+#[derive(Clone)]
+pub struct InternalKeyComparator {
+    user_: Arc<dyn Comparator>,
+}
+
+impl InternalKeyComparator {
+    pub fn new(user: Arc<dyn Comparator>) -> Self {
+        InternalKeyComparator { user_: user }
+    }
+
+    pub fn user_comparator(&self) -> &Arc<dyn Comparator> {
+        &self.user_
+    }
+
+    /// `key` if `user_key` does not shorten it, otherwise the first internal
+    /// key of `user_key`.
+    fn shortened(&self, key: &[u8], user_key: Vec<u8>) -> Vec<u8> {
+        let (k, _) = split(key);
+        if user_key.len() < k.len() && self.user_.compare(k, &user_key) == Ordering::Less {
+            make_lookup_key(&user_key, MAX_SEQ_NUM)[..].to_vec()
+        } else {
+            key.to_vec()
+        }
+    }
+}
+
+impl Comparator for InternalKeyComparator {
+    /// The name of the user comparator, a table records the order of its
+    /// user keys.
+    fn name(&self) -> &str {
+        self.user_.name()
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        let (a, a_trailer) = split(a);
+        let (b, b_trailer) = split(b);
+        self.user_
+            .compare(a, b)
+            .then_with(|| a_trailer.cmp(b_trailer))
+    }
+
+    fn find_shortest_sep(&self, a: &[u8], b: &[u8]) -> Vec<u8> {
+        let sep = self.user_.find_shortest_sep(split(a).0, split(b).0);
+        self.shortened(a, sep)
+    }
+
+    fn find_short_succ(&self, a: &[u8]) -> Vec<u8> {
+        let succ = self.user_.find_short_succ(split(a).0);
+        self.shortened(a, succ)
+    }
+}
+
+impl skip_list::Comparator<[u8]> for InternalKeyComparator {
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        Comparator::compare(self, a, b)
+    }
+}
+
+impl skip_list::Comparator<Slice> for InternalKeyComparator {
+    fn compare(&self, a: &Slice, b: &Slice) -> Ordering {
+        Comparator::compare(self, &a[..], &b[..])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use sstable::BytewiseComparator;
+
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let k = make_internal_key(b"abc", 42, ValueType::Value);
+        assert_eq!(b"abc", user_key(&k[..]));
+        assert_eq!(
+            Some(ParsedInternalKey {
+                user_key: b"abc",
+                seq: 42,
+                value_type: ValueType::Value,
+            }),
+            parse_internal_key(&k[..])
+        );
+        let k = make_internal_key(b"", MAX_SEQ_NUM, ValueType::Deletion);
+        assert_eq!(MAX_SEQ_NUM, parse_internal_key(&k[..]).unwrap().seq);
+        assert_eq!(None, parse_internal_key(b"abc"));
+    }
+
+    #[test]
+    fn test_order() {
+        let mut keys = [
+            make_internal_key(b"b", 1, ValueType::Value),
+            make_internal_key(b"ab", 3, ValueType::Value),
+            make_internal_key(b"a", 1, ValueType::Value),
+            make_internal_key(b"a", 2, ValueType::Deletion),
+            make_internal_key(b"a", 2, ValueType::Merge),
+            make_internal_key(b"a", 256, ValueType::Value),
+        ];
+        keys.sort();
+        let parsed: Vec<_> = keys
+            .iter()
+            .map(|k| {
+                let p = parse_internal_key(&k[..]).unwrap();
+                (p.user_key.to_vec(), p.seq, p.value_type)
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                (b"a".to_vec(), 256, ValueType::Value),
+                (b"a".to_vec(), 2, ValueType::Merge),
+                (b"a".to_vec(), 2, ValueType::Deletion),
+                (b"a".to_vec(), 1, ValueType::Value),
+                (b"ab".to_vec(), 3, ValueType::Value),
+                (b"b".to_vec(), 1, ValueType::Value),
+            ],
+            parsed
+        );
+
+        // the lookup key is not past any entry visible at its sequence
+        let lookup_key = make_lookup_key(b"a", 2);
+        assert!(lookup_key < make_internal_key(b"a", 2, ValueType::Deletion));
+        assert!(lookup_key < make_internal_key(b"a", 2, ValueType::BlobIndex));
+        assert!(lookup_key <= make_internal_key(b"a", 2, ValueType::RangeDeletion));
+        assert!(lookup_key > make_internal_key(b"a", 3, ValueType::Value));
+    }
+
+    struct ReverseComparator;
+
+    impl Comparator for ReverseComparator {
+        fn name(&self) -> &str {
+            "test.ReverseComparator"
+        }
+
+        fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+            b.cmp(a)
+        }
+    }
+
+    #[test]
+    fn test_comparator() {
+        let cmp = InternalKeyComparator::new(Arc::new(ReverseComparator));
+        assert_eq!("test.ReverseComparator", cmp.name());
+        let mut keys = vec![
+            make_internal_key(b"a", 1, ValueType::Value),
+            make_internal_key(b"b", 1, ValueType::Value),
+            make_internal_key(b"ab", 3, ValueType::Value),
+            make_internal_key(b"b", 2, ValueType::Deletion),
+        ];
+        keys.sort_by(|a, b| Comparator::compare(&cmp, &a[..], &b[..]));
+        // the user keys backward, the newest entry of one first
+        assert_eq!(
+            vec![
+                make_internal_key(b"b", 2, ValueType::Deletion),
+                make_internal_key(b"b", 1, ValueType::Value),
+                make_internal_key(b"ab", 3, ValueType::Value),
+                make_internal_key(b"a", 1, ValueType::Value),
+            ],
+            keys
+        );
+
+        // a shorter user key separates the blocks, the first entry of it
+        let cmp = InternalKeyComparator::new(Arc::new(BytewiseComparator));
+        let a = make_internal_key(b"abcd", 5, ValueType::Value);
+        let b = make_internal_key(b"abz", 9, ValueType::Value);
+        let sep = cmp.find_shortest_sep(&a[..], &b[..]);
+        assert_eq!(make_lookup_key(b"abd", MAX_SEQ_NUM)[..], sep[..]);
+        assert_eq!(Ordering::Less, cmp.compare(&a[..], &sep));
+        assert_eq!(Ordering::Less, cmp.compare(&sep, &b[..]));
+        // none between the entries of a user key
+        let b = make_internal_key(b"abcd", 4, ValueType::Value);
+        assert_eq!(a[..], cmp.find_shortest_sep(&a[..], &b[..])[..]);
+        let succ = cmp.find_short_succ(&a[..]);
+        assert_eq!(make_lookup_key(b"b", MAX_SEQ_NUM)[..], succ[..]);
+    }
+}
@@ -7,22 +7,30 @@ use std::io::{Error as IOError, ErrorKind, Read, Result, Write};
 use std::net::SocketAddr;
 use std::net::{TcpListener, TcpStream};
 use std::rc::Rc;
+use std::sync::mpsc;
 use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use clap::App;
 use clap::Arg;
 use env_logger;
+use futures::sync::oneshot;
 use futures::{future, Future};
+use log::{error, info};
+use tokio::net::TcpListener as AsyncTcpListener;
 use tokio::prelude::*;
-use tokio_proto::TcpServer;
-use tokio_service::{NewService, Service};
+use tokio::runtime::Runtime;
+use tokio_service::Service;
 
 use crate::error::MyResult;
 use crate::options::Options;
 use crate::parser::parse;
-use crate::proto::Proto;
+use crate::proto::ServerCodec;
 use crate::request::Request;
 use crate::response::Response;
+use crate::shutdown::wait_for_signal;
+use crate::shutdown::Gate;
 use crate::store::Store;
 use crate::thread_pool::ThreadPool;
 use crate::utils::to_str;
@@ -36,31 +44,47 @@ mod request;
 mod response;
 #[macro_use]
 mod parser_util;
+mod blob;
+mod compaction_filter;
 mod config;
 mod data_manager;
+mod db_iterator;
+mod internal_key;
 mod manifest;
 mod memtable;
 mod memtable_list;
+mod merge_operator;
 mod merger;
+mod migration;
 mod options;
 mod parser;
 mod proto;
+mod range_del;
+mod row_cache;
+mod scheduler;
+mod shutdown;
 mod slice;
+mod snapshot;
 mod sstable_builder;
-mod sstable_reader;
+mod stats;
 mod store;
 mod test_utils;
 mod thread_pool;
 mod types;
+mod version;
+mod version_set;
 mod wal;
 
+const DEFAULT_SHUTDOWN_TIMEOUT_MS: u64 = 30_000;
+
 pub struct Server {
     store: Arc<Store>,
+    gate: Arc<Gate>,
 }
 
 impl Server {
-    fn new(store: Arc<Store>) -> Self {
-        Server { store }
+    fn new(store: Arc<Store>, gate: Arc<Gate>) -> Self {
+        Server { store, gate }
     }
 }
 
@@ -68,24 +92,83 @@ impl Service for Server {
     type Request = Request;
     type Response = Response;
     type Error = io::Error;
-    type Future = Box<Future<Item = Response, Error = io::Error>>;
+    type Future = Box<Future<Item = Response, Error = io::Error> + Send>;
 
     fn call(&self, req: Self::Request) -> Self::Future {
-        Box::new(future::done(match self.store.apply(req) {
-            Ok(response) => Ok(response),
-            Err(e) => Ok(Response::ServerError(e.msg)),
-        }))
+        if !self.gate.enter() {
+            return Box::new(future::ok(Response::ServerError(
+                "shutting down".to_owned(),
+            )));
+        }
+        let response = match self.store.apply(req) {
+            Ok(response) => response,
+            Err(e) => Response::ServerError(e.msg),
+        };
+        self.gate.leave();
+        Box::new(future::ok(response))
     }
 }
 
-pub fn serve<T>(addr: SocketAddr, new_service: T)
-where
-    T: NewService<Request = Request, Response = Response, Error = io::Error>
-        + Send
-        + Sync
-        + 'static,
-{
-    TcpServer::new(Proto, addr).serve(new_service);
+/// Serves until `shutdown` resolves. Then stops accepting connections,
+/// waits for the requests being served and shuts the store down, all
+/// within `timeout`.
+pub fn serve(
+    addr: SocketAddr,
+    store: Arc<Store>,
+    shutdown: oneshot::Receiver<()>,
+    timeout: Duration,
+) -> MyResult<()> {
+    let gate = Arc::new(Gate::new());
+    let listener = AsyncTcpListener::bind(&addr)?;
+    let server = {
+        let store = store.clone();
+        let gate = gate.clone();
+        listener
+            .incoming()
+            .for_each(move |socket| {
+                let server = Server::new(store.clone(), gate.clone());
+                #[allow(deprecated)]
+                let (sink, stream) = socket.framed(ServerCodec).split();
+                let responses = stream.and_then(move |req| server.call(req));
+                tokio::spawn(sink.send_all(responses).then(|_| Ok(())));
+                Ok(())
+            })
+            .map_err(|e| error!("accept error: {}", e))
+    };
+
+    let mut rt = Runtime::new()?;
+    // the listener is dropped with the server
+    let _ = rt.block_on(
+        server
+            .select(shutdown.map_err(|_| ()))
+            .map(|_| ())
+            .map_err(|_| ()),
+    );
+    info!("stopped accepting connections");
+
+    let deadline = Instant::now() + timeout;
+    if !gate.close(deadline) {
+        error!("requests still running at shutdown");
+    }
+    let _ = rt.shutdown_now().wait();
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = tx.send(store.shutdown());
+    });
+    let now = Instant::now();
+    let left = if deadline > now {
+        deadline - now
+    } else {
+        Duration::from_millis(0)
+    };
+    match rx.recv_timeout(left) {
+        Ok(Ok(())) => info!("bye!"),
+        Ok(Err(e)) => error!("shutdown error: {:?}", e),
+        // the log is replayed on the next start
+        Err(_) => error!("shutdown timed out"),
+    }
+    Ok(())
 }
 
 fn main() -> MyResult<()> {
@@ -127,7 +210,9 @@ Welcome to MirDB!
         .trim_matches('\n')
     );
 
-    serve(addr, move || Ok(Server::new(store.clone())));
-
-    Ok(())
+    let timeout = Duration::from_millis(
+        conf.shutdown_timeout_ms
+            .unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT_MS),
+    );
+    serve(addr, store, wait_for_signal(), timeout)
 }
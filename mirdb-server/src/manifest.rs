@@ -1,19 +1,48 @@
 use std::collections::HashSet;
 use std::fmt;
+use std::fs::remove_file;
+use std::fs::rename;
 use std::fs::File;
+use std::fs::OpenOptions;
 use std::io::Read;
 use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::Arc;
 
-use bincode::deserialize_from;
+use bincode::deserialize;
 use bincode::serialize;
+use crc::crc32;
+use crc::crc32::Hasher32;
+use integer_encoding::FixedInt;
+use log::warn;
 use serde::{Deserialize, Serialize};
 
+use sstable::TableReader;
+
+use crate::blob::BlobFile;
+use crate::error::err;
 use crate::error::MyResult;
+use crate::error::StatusCode;
+use crate::internal_key::SeqNum;
 use crate::options::Options;
 
-const MANIFEST_FILENAME: &str = "MANIFEST";
+const LEGACY_MANIFEST_FILENAME: &str = "MANIFEST";
+const CURRENT_FILENAME: &str = "CURRENT";
+const CURRENT_TMP_FILENAME: &str = "CURRENT.tmp";
+const RECORD_HEADER_SIZE: usize = 8;
+/// The layout of the edit of a descriptor record, its first byte. A change to
+/// `VersionEdit` takes a new one, and the records of the older ones are
+/// decoded by their own structs.
+const EDIT_LAYOUT: u8 = 1;
+
+/// The format of the entries of the sstables and the wal segs, internal keys
+/// since version 1. Version 0 is the one from before the sequence numbers,
+/// with plain user keys and bincode `Option<StorePayload>` values, `None`
+/// for a deletion, which the work dir is upgraded from when it is opened.
+pub const FORMAT_VERSION: u32 = 1;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileMeta {
@@ -46,20 +75,110 @@ impl LevelMeta {
     }
 }
 
+/// A change to the manifest, appended as one record to the descriptor log.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct VersionEdit {
+    pub log_number: Option<usize>,
+    pub next_file_number: Option<usize>,
+    pub last_sequence: Option<SeqNum>,
+    pub new_files: Vec<(usize, FileMeta)>,
+    pub deleted_files: Vec<(usize, String)>,
+    pub new_blob_files: Vec<usize>,
+    pub deleted_blob_files: Vec<usize>,
+    /// The bytes of the blob files which went stale, added to what they had.
+    pub blob_stale_bytes: Vec<(usize, u64)>,
+    pub format_version: Option<u32>,
+}
+
+impl VersionEdit {
+    pub fn add_file(&mut self, level: usize, reader: &TableReader) {
+        self.new_files.push((
+            level,
+            FileMeta {
+                file_name: reader.file_name().clone(),
+            },
+        ));
+    }
+
+    pub fn delete_file(&mut self, level: usize, reader: &TableReader) {
+        self.deleted_files.push((level, reader.file_name().clone()));
+    }
+
+    pub fn add_blob_file(&mut self, blob: &BlobFile) {
+        self.new_blob_files.push(blob.number());
+    }
+
+    pub fn delete_blob_file(&mut self, blob: &BlobFile) {
+        self.deleted_blob_files.push(blob.number());
+    }
+
+    pub fn add_blob_stale(&mut self, number: usize, bytes: u64) {
+        self.blob_stale_bytes.push((number, bytes));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.log_number.is_none()
+            && self.next_file_number.is_none()
+            && self.last_sequence.is_none()
+            && self.new_files.is_empty()
+            && self.deleted_files.is_empty()
+            && self.new_blob_files.is_empty()
+            && self.deleted_blob_files.is_empty()
+            && self.blob_stale_bytes.is_empty()
+            && self.format_version.is_none()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlobMeta {
+    pub number: usize,
+    pub stale_bytes: u64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Manifest {
     pub level_metas: Vec<LevelMeta>,
+    /// The oldest wal seg still needed, older segs are already in sstables.
+    pub log_number: usize,
+    pub next_file_number: usize,
+    /// The sequence number of the last write logged before this edit.
+    pub last_sequence: SeqNum,
+    /// Only kept in the descriptor log, the single file layout never had
+    /// blob files.
+    #[serde(skip)]
+    pub blob_metas: Vec<BlobMeta>,
+    /// See `FORMAT_VERSION`, the single file layout is of format 0.
+    #[serde(skip)]
+    pub format_version: u32,
+}
+
+/// The manifest layout before the log number was recorded.
+#[derive(Deserialize)]
+struct LegacyManifest {
+    level_metas: Vec<LevelMeta>,
 }
 
 impl Manifest {
     fn new(opt: &Options) -> Self {
         Manifest {
             level_metas: Vec::with_capacity(opt.max_level),
+            log_number: 0,
+            next_file_number: 0,
+            last_sequence: 0,
+            blob_metas: vec![],
+            format_version: FORMAT_VERSION,
         }
     }
 
     pub fn next_file_number(&self) -> usize {
-        let mut m = None;
+        ::std::cmp::max(
+            ::std::cmp::max(self.next_file_number, self.log_number),
+            self.next_table_file_number(),
+        )
+    }
+
+    fn next_table_file_number(&self) -> usize {
+        let mut m = self.blob_metas.iter().map(|x| x.number).max();
         for lm in &self.level_metas {
             for fm in &lm.file_metas {
                 let pieces = fm.file_name.split('.').collect::<Vec<&str>>();
@@ -80,23 +199,86 @@ impl Manifest {
         }
     }
 
-    pub fn gen_path(opt: &Options) -> PathBuf {
-        let p = Path::new(&opt.work_dir);
-        p.join(MANIFEST_FILENAME)
+    fn legacy_path(opt: &Options) -> PathBuf {
+        Path::new(&opt.work_dir).join(LEGACY_MANIFEST_FILENAME)
     }
 
-    pub fn load(opt: &Options) -> MyResult<Self> {
-        let p = Manifest::gen_path(opt);
-        if !p.exists() {
-            return Ok(Manifest::new(opt));
+    /// Loads a manifest written as a single bincode file, which is how the
+    /// manifest was stored before the descriptor log.
+    fn load_legacy(opt: &Options) -> MyResult<Self> {
+        let mut buf = vec![];
+        File::open(Manifest::legacy_path(opt))?.read_to_end(&mut buf)?;
+        match deserialize(&buf) {
+            Ok(manifest) => Ok(manifest),
+            Err(_) => {
+                let legacy: LegacyManifest = deserialize(&buf)?;
+                let mut manifest = Manifest::new(opt);
+                manifest.level_metas = legacy.level_metas;
+                manifest.format_version = 0;
+                Ok(manifest)
+            }
         }
-        let f = File::open(&p)?;
-        Ok(deserialize_from(f)?)
     }
 
-    pub fn flush<T: Write>(&self, w: &mut T) -> MyResult<()> {
-        w.write_all(&serialize(self)?)?;
-        Ok(())
+    pub fn apply(&mut self, edit: &VersionEdit) {
+        if let Some(log_number) = edit.log_number {
+            self.log_number = log_number;
+        }
+        if let Some(next_file_number) = edit.next_file_number {
+            self.next_file_number = next_file_number;
+        }
+        if let Some(last_sequence) = edit.last_sequence {
+            self.last_sequence = last_sequence;
+        }
+        if let Some(format_version) = edit.format_version {
+            self.format_version = format_version;
+        }
+        for (level, file_name) in &edit.deleted_files {
+            self.remove_file_meta_by_file_name(*level, file_name.clone());
+        }
+        for (level, file_meta) in &edit.new_files {
+            self.add_file_meta(*level, file_meta.clone());
+        }
+        self.apply_blobs(edit);
+    }
+
+    fn apply_blobs(&mut self, edit: &VersionEdit) {
+        for number in &edit.new_blob_files {
+            self.blob_metas.push(BlobMeta {
+                number: *number,
+                stale_bytes: 0,
+            });
+        }
+        for (number, bytes) in &edit.blob_stale_bytes {
+            if let Some(meta) = self.blob_metas.iter_mut().find(|x| x.number == *number) {
+                meta.stale_bytes += bytes;
+            }
+        }
+        self.blob_metas
+            .retain(|x| !edit.deleted_blob_files.contains(&x.number));
+    }
+
+    /// An edit that rebuilds the whole manifest when applied to an empty one.
+    pub fn snapshot(&self) -> VersionEdit {
+        let mut edit = VersionEdit {
+            log_number: Some(self.log_number),
+            next_file_number: Some(self.next_file_number),
+            last_sequence: Some(self.last_sequence),
+            format_version: Some(self.format_version),
+            ..VersionEdit::default()
+        };
+        for (level, lm) in self.level_metas.iter().enumerate() {
+            for fm in &lm.file_metas {
+                edit.new_files.push((level, fm.clone()));
+            }
+        }
+        for meta in &self.blob_metas {
+            edit.new_blob_files.push(meta.number);
+            if meta.stale_bytes > 0 {
+                edit.blob_stale_bytes.push((meta.number, meta.stale_bytes));
+            }
+        }
+        edit
     }
 
     fn ensure_level(&mut self, level: usize) {
@@ -129,17 +311,207 @@ impl Manifest {
     }
 }
 
+fn descriptor_file_name(num: usize) -> String {
+    format!("MANIFEST-{:08}", num)
+}
+
+fn parse_descriptor_file_name(file_name: &str) -> Option<usize> {
+    if file_name.starts_with("MANIFEST-") {
+        file_name["MANIFEST-".len()..].parse::<usize>().ok()
+    } else {
+        None
+    }
+}
+
+fn checksum(data: &[u8]) -> u32 {
+    let mut digest = crc32::Digest::new(crc32::CASTAGNOLI);
+    digest.write(data);
+    digest.sum32()
+}
+
+/// Appends version edits to a descriptor file, each record is laid out as
+/// `crc32 (4 bytes) | length (4 bytes) | edit layout (1 byte) | bincode
+/// encoded edit`, the checksum and the length cover the layout byte.
+struct DescriptorWriter {
+    file: File,
+    file_name: String,
+    size: usize,
+}
+
+impl DescriptorWriter {
+    fn create(opt: &Options, num: usize) -> MyResult<Self> {
+        let file_name = descriptor_file_name(num);
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(Path::new(&opt.work_dir).join(&file_name))?;
+        Ok(DescriptorWriter {
+            file,
+            file_name,
+            size: 0,
+        })
+    }
+
+    fn open(opt: &Options, file_name: &str, size: usize) -> MyResult<Self> {
+        let file = OpenOptions::new()
+            .append(true)
+            .open(Path::new(&opt.work_dir).join(file_name))?;
+        // drop a torn record left by a crash, so new records follow the last good one
+        file.set_len(size as u64)?;
+        Ok(DescriptorWriter {
+            file,
+            file_name: file_name.to_owned(),
+            size,
+        })
+    }
+
+    fn append(&mut self, edit: &VersionEdit) -> MyResult<()> {
+        let mut payload = vec![EDIT_LAYOUT];
+        payload.extend_from_slice(&serialize(edit)?);
+        let mut buf = vec![0; RECORD_HEADER_SIZE + payload.len()];
+        checksum(&payload).encode_fixed(&mut buf[..4]);
+        (payload.len() as u32).encode_fixed(&mut buf[4..RECORD_HEADER_SIZE]);
+        buf[RECORD_HEADER_SIZE..].copy_from_slice(&payload);
+        self.file.write_all(&buf)?;
+        self.file.sync_data()?;
+        self.size += buf.len();
+        Ok(())
+    }
+}
+
+/// Decodes the edit of a record by the layout of its first byte.
+fn decode_edit(record: &[u8]) -> MyResult<VersionEdit> {
+    match record.split_first() {
+        Some((&EDIT_LAYOUT, edit)) => match deserialize(edit) {
+            Ok(edit) => Ok(edit),
+            Err(e) => err(StatusCode::Corruption, format!("bad edit: {}", e)),
+        },
+        Some((layout, _)) => err(
+            StatusCode::Corruption,
+            format!("unknown edit layout {}", layout),
+        ),
+        None => err(StatusCode::Corruption, "empty record"),
+    }
+}
+
+/// Replays the edits of a descriptor file, returns the manifest and the size
+/// of the valid prefix. A truncated record, or one failing its checksum, is
+/// what a crash in the middle of an append leaves behind, so it ends the log
+/// instead of failing. A record which passes its checksum but can't be
+/// decoded is a corruption, the log is left as it is.
+fn replay_descriptor(opt: &Options, file_name: &str) -> MyResult<(Manifest, usize)> {
+    let mut buf = vec![];
+    File::open(Path::new(&opt.work_dir).join(file_name))?.read_to_end(&mut buf)?;
+
+    let mut manifest = Manifest::new(opt);
+    let mut offset = 0;
+    while offset + RECORD_HEADER_SIZE <= buf.len() {
+        let crc = u32::decode_fixed(&buf[offset..offset + 4]);
+        let size = u32::decode_fixed(&buf[offset + 4..offset + RECORD_HEADER_SIZE]) as usize;
+        let start = offset + RECORD_HEADER_SIZE;
+        if start + size > buf.len() || checksum(&buf[start..start + size]) != crc {
+            break;
+        }
+        let edit = match decode_edit(&buf[start..start + size]) {
+            Ok(edit) => edit,
+            Err(e) => {
+                return err(
+                    StatusCode::Corruption,
+                    format!("record at {} of {}: {}", offset, file_name, e.msg),
+                )
+            }
+        };
+        manifest.apply(&edit);
+        offset = start + size;
+    }
+    if offset < buf.len() {
+        warn!(
+            "ignore {} bytes of broken records at the tail of {}",
+            buf.len() - offset,
+            file_name
+        );
+    }
+    Ok((manifest, offset))
+}
+
+fn set_current(opt: &Options, file_name: &str) -> MyResult<()> {
+    let work_dir = Path::new(&opt.work_dir);
+    let tmp_path = work_dir.join(CURRENT_TMP_FILENAME);
+    {
+        let mut f = File::create(&tmp_path)?;
+        f.write_all(format!("{}\n", file_name).as_bytes())?;
+        f.sync_all()?;
+    }
+    rename(&tmp_path, work_dir.join(CURRENT_FILENAME))?;
+    File::open(work_dir)?.sync_all()?;
+    Ok(())
+}
+
+fn read_current(opt: &Options) -> MyResult<Option<String>> {
+    let path = Path::new(&opt.work_dir).join(CURRENT_FILENAME);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let mut s = String::new();
+    File::open(&path)?.read_to_string(&mut s)?;
+    let file_name = s.trim_end_matches('\n');
+    if parse_descriptor_file_name(file_name).is_none() {
+        return err(StatusCode::ManifestError, "invalid CURRENT file");
+    }
+    Ok(Some(file_name.to_owned()))
+}
+
 pub struct ManifestBuilder {
     opt: Options,
     manifest_: Manifest,
+    pending_: VersionEdit,
+    descriptor_: DescriptorWriter,
+    next_file_number_: Arc<AtomicUsize>,
 }
 
 impl ManifestBuilder {
     pub fn new(opt: Options) -> MyResult<Self> {
-        Ok(ManifestBuilder {
-            opt: opt.clone(),
-            manifest_: Manifest::load(&opt)?,
-        })
+        match read_current(&opt)? {
+            Some(file_name) => {
+                let (manifest_, size) = replay_descriptor(&opt, &file_name)?;
+                let next_file_number = ::std::cmp::max(
+                    manifest_.next_file_number(),
+                    parse_descriptor_file_name(&file_name).unwrap() + 1,
+                );
+                Ok(ManifestBuilder {
+                    descriptor_: DescriptorWriter::open(&opt, &file_name, size)?,
+                    opt,
+                    manifest_,
+                    pending_: VersionEdit::default(),
+                    next_file_number_: Arc::new(AtomicUsize::new(next_file_number)),
+                })
+            }
+            None => {
+                let legacy_path = Manifest::legacy_path(&opt);
+                let is_legacy = legacy_path.exists();
+                let manifest_ = if is_legacy {
+                    Manifest::load_legacy(&opt)?
+                } else {
+                    Manifest::new(&opt)
+                };
+                let next_file_number = manifest_.next_file_number();
+                let descriptor_ = DescriptorWriter::create(&opt, next_file_number)?;
+                let mut mb = ManifestBuilder {
+                    opt,
+                    manifest_,
+                    pending_: VersionEdit::default(),
+                    descriptor_,
+                    next_file_number_: Arc::new(AtomicUsize::new(next_file_number + 1)),
+                };
+                mb.write_snapshot()?;
+                set_current(&mb.opt, &mb.descriptor_.file_name)?;
+                if is_legacy {
+                    remove_file(legacy_path)?;
+                }
+                Ok(mb)
+            }
+        }
     }
 
     pub fn file_metas(&self, level: usize) -> Option<&Vec<FileMeta>> {
@@ -148,42 +520,135 @@ impl ManifestBuilder {
 
     pub fn add_file_meta(&mut self, level: usize, file_meta: FileMeta) {
         assert!(level < self.opt.max_level);
+        self.pending_.new_files.push((level, file_meta.clone()));
         self.manifest_.add_file_meta(level, file_meta)
     }
 
     pub fn remove_file_meta_by_file_name(&mut self, level: usize, file_name: String) {
         assert!(level < self.opt.max_level);
+        self.pending_.deleted_files.push((level, file_name.clone()));
         self.manifest_
             .remove_file_meta_by_file_name(level, file_name)
     }
 
     pub fn remove_file_meta_by_file_names(&mut self, level: usize, file_names: &HashSet<String>) {
         assert!(level < self.opt.max_level);
+        for file_name in file_names {
+            self.pending_.deleted_files.push((level, file_name.clone()));
+        }
         self.manifest_
             .remove_file_meta_by_file_names(level, file_names)
     }
 
+    pub fn blob_metas(&self) -> &Vec<BlobMeta> {
+        &self.manifest_.blob_metas
+    }
+
     pub fn manifest(&self) -> &Manifest {
         &self.manifest_
     }
 
-    pub fn flush(&self) -> MyResult<()> {
-        let mut file_ = File::create(Manifest::gen_path(&self.opt))?;
-        self.manifest_.flush(&mut file_)
+    pub fn log_number(&self) -> usize {
+        self.manifest_.log_number
+    }
+
+    pub fn set_log_number(&mut self, log_number: usize) {
+        assert!(log_number >= self.manifest_.log_number);
+        self.pending_.log_number = Some(log_number);
+        self.manifest_.log_number = log_number;
+    }
+
+    pub fn last_sequence(&self) -> SeqNum {
+        self.manifest_.last_sequence
+    }
+
+    pub fn set_last_sequence(&mut self, last_sequence: SeqNum) {
+        assert!(last_sequence >= self.manifest_.last_sequence);
+        self.pending_.last_sequence = Some(last_sequence);
+        self.manifest_.last_sequence = last_sequence;
+    }
+
+    pub fn format_version(&self) -> u32 {
+        self.manifest_.format_version
+    }
+
+    pub fn log_and_apply(&mut self, edit: VersionEdit) -> MyResult<()> {
+        if let Some(format_version) = edit.format_version {
+            self.pending_.format_version = Some(format_version);
+            self.manifest_.format_version = format_version;
+        }
+        self.manifest_.apply_blobs(&edit);
+        self.pending_.new_blob_files.extend(edit.new_blob_files);
+        self.pending_
+            .deleted_blob_files
+            .extend(edit.deleted_blob_files);
+        self.pending_.blob_stale_bytes.extend(edit.blob_stale_bytes);
+        if let Some(log_number) = edit.log_number {
+            self.set_log_number(log_number);
+        }
+        if let Some(last_sequence) = edit.last_sequence {
+            self.set_last_sequence(last_sequence);
+        }
+        for (level, file_name) in edit.deleted_files {
+            self.remove_file_meta_by_file_name(level, file_name);
+        }
+        for (level, file_meta) in edit.new_files {
+            self.add_file_meta(level, file_meta);
+        }
+        self.flush()
+    }
+
+    /// Appends the changes made since the last flush to the descriptor log,
+    /// rolling over to a new descriptor once the current one is too large.
+    pub fn flush(&mut self) -> MyResult<()> {
+        self.manifest_.next_file_number = self.next_file_number();
+        let mut edit = ::std::mem::take(&mut self.pending_);
+        edit.next_file_number = Some(self.manifest_.next_file_number);
+        self.descriptor_.append(&edit)?;
+        if self.descriptor_.size >= self.opt.manifest_max_size {
+            self.roll_over()?;
+        }
+        Ok(())
+    }
+
+    fn write_snapshot(&mut self) -> MyResult<()> {
+        self.manifest_.next_file_number = self.next_file_number();
+        let edit = self.manifest_.snapshot();
+        self.descriptor_.append(&edit)
+    }
+
+    fn roll_over(&mut self) -> MyResult<()> {
+        let num = self.next_file_number_.fetch_add(1, Relaxed);
+        let descriptor = DescriptorWriter::create(&self.opt, num)?;
+        let old = ::std::mem::replace(&mut self.descriptor_, descriptor);
+        self.write_snapshot()?;
+        set_current(&self.opt, &self.descriptor_.file_name)?;
+        remove_file(Path::new(&self.opt.work_dir).join(&old.file_name))?;
+        Ok(())
+    }
+
+    pub fn descriptor_file_name(&self) -> &str {
+        &self.descriptor_.file_name
     }
 
     pub fn next_file_number(&self) -> usize {
-        self.manifest_.next_file_number()
+        self.next_file_number_.load(Relaxed)
+    }
+
+    /// The allocator shared by sstables and wal segs, so that every file in
+    /// the work dir gets a unique and increasing number.
+    pub fn file_number_allocator(&self) -> Arc<AtomicUsize> {
+        self.next_file_number_.clone()
     }
 }
 
 impl fmt::Display for ManifestBuilder {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "Next file number: {}\n\n",
-            self.manifest_.next_file_number()
-        )?;
+        writeln!(f, "Manifest: {}", self.descriptor_file_name())?;
+        writeln!(f, "Log number: {}", self.manifest_.log_number)?;
+        writeln!(f, "Last sequence: {}", self.manifest_.last_sequence)?;
+        writeln!(f, "Format version: {}", self.manifest_.format_version)?;
+        write!(f, "Next file number: {}\n\n", self.next_file_number())?;
         for (i, lm) in self.manifest_.level_metas.iter().enumerate() {
             writeln!(f, "Level{} ({}):", i, lm.file_metas.len())?;
             for (i, fm) in lm.file_metas.iter().enumerate() {
@@ -200,6 +665,225 @@ impl fmt::Display for ManifestBuilder {
             }
             writeln!(f)?;
         }
+        let blob_metas = &self.manifest_.blob_metas;
+        writeln!(f, "Blob files ({}):", blob_metas.len())?;
+        for (i, meta) in blob_metas.iter().enumerate() {
+            if i == 0 {
+                write!(f, "\t")?;
+            } else {
+                write!(f, ", ")?;
+            }
+            if i > 10 {
+                write!(f, "...")?;
+                break;
+            }
+            write!(f, "{:08}.blob ({} stale)", meta.number, meta.stale_bytes)?;
+        }
+        writeln!(f)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::read_to_string;
+
+    use crate::test_utils::get_test_opt;
+
+    use super::*;
+
+    fn file_meta(num: usize) -> FileMeta {
+        FileMeta {
+            file_name: format!("{:08}.sst", num),
+        }
+    }
+
+    fn file_names(mb: &ManifestBuilder, level: usize) -> Vec<String> {
+        mb.file_metas(level)
+            .map(|x| x.iter().map(|fm| fm.file_name.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Appends a record of any payload to the descriptor at `path`.
+    fn append_record(path: &Path, payload: &[u8]) -> MyResult<()> {
+        let mut buf = vec![0; RECORD_HEADER_SIZE];
+        checksum(payload).encode_fixed(&mut buf[..4]);
+        (payload.len() as u32).encode_fixed(&mut buf[4..RECORD_HEADER_SIZE]);
+        buf.extend_from_slice(payload);
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(&buf)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_replay() -> MyResult<()> {
+        let opt = get_test_opt();
+        let mut mb = ManifestBuilder::new(opt.clone())?;
+        mb.add_file_meta(0, file_meta(3));
+        mb.add_file_meta(0, file_meta(4));
+        mb.set_log_number(5);
+        mb.set_last_sequence(42);
+        mb.flush()?;
+        let mut set = HashSet::new();
+        set.insert(file_meta(3).file_name);
+        mb.remove_file_meta_by_file_names(0, &set);
+        mb.add_file_meta(1, file_meta(6));
+        mb.flush()?;
+        let next_file_number = mb.next_file_number();
+        drop(mb);
+
+        let mb = ManifestBuilder::new(opt.clone())?;
+        assert_eq!(vec![file_meta(4).file_name], file_names(&mb, 0));
+        assert_eq!(vec![file_meta(6).file_name], file_names(&mb, 1));
+        assert_eq!(5, mb.log_number());
+        assert_eq!(42, mb.last_sequence());
+        assert_eq!(FORMAT_VERSION, mb.format_version());
+        assert!(mb.next_file_number() >= next_file_number);
+        Ok(())
+    }
+
+    #[test]
+    fn test_torn_tail() -> MyResult<()> {
+        let opt = get_test_opt();
+        let mut mb = ManifestBuilder::new(opt.clone())?;
+        mb.add_file_meta(0, file_meta(3));
+        mb.flush()?;
+        let path = Path::new(&opt.work_dir).join(mb.descriptor_file_name());
+        drop(mb);
+
+        // mock a crash in the middle of appending a record
+        let mut f = OpenOptions::new().append(true).open(&path)?;
+        f.write_all(&[1, 2, 3, 4, 5, 6, 7, 8, 9])?;
+        drop(f);
+
+        let mut mb = ManifestBuilder::new(opt.clone())?;
+        assert_eq!(vec![file_meta(3).file_name], file_names(&mb, 0));
+        mb.add_file_meta(0, file_meta(4));
+        mb.flush()?;
+        drop(mb);
+
+        let mb = ManifestBuilder::new(opt.clone())?;
+        assert_eq!(
+            vec![file_meta(3).file_name, file_meta(4).file_name],
+            file_names(&mb, 0)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_corruption() -> MyResult<()> {
+        let opt = get_test_opt();
+        let mut mb = ManifestBuilder::new(opt.clone())?;
+        mb.add_file_meta(0, file_meta(3));
+        mb.flush()?;
+        let path = Path::new(&opt.work_dir).join(mb.descriptor_file_name());
+        drop(mb);
+
+        // records with a good checksum which are no edits
+        for payload in &[&[EDIT_LAYOUT, 0xff, 0xff][..], &[EDIT_LAYOUT + 1][..], &[]] {
+            append_record(&path, payload)?;
+            let size = path.metadata()?.len();
+            for _ in 0..2 {
+                let e = ManifestBuilder::new(opt.clone()).err().unwrap();
+                assert_eq!(StatusCode::Corruption, e.code);
+                // nothing is cut off
+                assert_eq!(size, path.metadata()?.len());
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_legacy() -> MyResult<()> {
+        let opt = get_test_opt();
+        let mut lm = LevelMeta::new();
+        lm.push_file_meta(file_meta(7));
+        let legacy_path = Path::new(&opt.work_dir).join(LEGACY_MANIFEST_FILENAME);
+        File::create(&legacy_path)?.write_all(&serialize(&vec![lm])?)?;
+
+        let mb = ManifestBuilder::new(opt.clone())?;
+        assert_eq!(vec![file_meta(7).file_name], file_names(&mb, 0));
+        assert!(mb.next_file_number() > 7);
+        assert!(!legacy_path.exists());
+        assert_eq!(0, mb.format_version());
+        drop(mb);
+
+        let mut mb = ManifestBuilder::new(opt.clone())?;
+        assert_eq!(vec![file_meta(7).file_name], file_names(&mb, 0));
+        assert_eq!(0, mb.format_version());
+        mb.log_and_apply(VersionEdit {
+            format_version: Some(FORMAT_VERSION),
+            ..VersionEdit::default()
+        })?;
+        drop(mb);
+
+        let mut mb = ManifestBuilder::new(opt.clone())?;
+        assert_eq!(FORMAT_VERSION, mb.format_version());
+        // the snapshot of a new descriptor keeps the format version
+        mb.roll_over()?;
+        drop(mb);
+        let mb = ManifestBuilder::new(opt.clone())?;
+        assert_eq!(FORMAT_VERSION, mb.format_version());
+        assert_eq!(vec![file_meta(7).file_name], file_names(&mb, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_roll_over() -> MyResult<()> {
+        let mut opt = get_test_opt();
+        opt.manifest_max_size = 1;
+        let mut mb = ManifestBuilder::new(opt.clone())?;
+        let first = mb.descriptor_file_name().to_owned();
+        mb.add_file_meta(0, file_meta(3));
+        mb.flush()?;
+        let second = mb.descriptor_file_name().to_owned();
+        assert_ne!(first, second);
+        assert!(!Path::new(&opt.work_dir).join(&first).exists());
+        let current = read_to_string(Path::new(&opt.work_dir).join(CURRENT_FILENAME))?;
+        assert_eq!(format!("{}\n", second), current);
+        drop(mb);
+
+        let mb = ManifestBuilder::new(opt.clone())?;
+        assert_eq!(vec![file_meta(3).file_name], file_names(&mb, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_blob_files() -> MyResult<()> {
+        let mut opt = get_test_opt();
+        opt.manifest_max_size = 1024;
+        let mut mb = ManifestBuilder::new(opt.clone())?;
+        mb.log_and_apply(VersionEdit {
+            new_blob_files: vec![20, 21],
+            ..VersionEdit::default()
+        })?;
+        let mut edit = VersionEdit::default();
+        edit.add_blob_stale(20, 100);
+        edit.add_blob_stale(21, 7);
+        edit.add_blob_stale(20, 10);
+        mb.log_and_apply(edit)?;
+        mb.log_and_apply(VersionEdit {
+            deleted_blob_files: vec![21],
+            ..VersionEdit::default()
+        })?;
+        let expected = vec![BlobMeta {
+            number: 20,
+            stale_bytes: 110,
+        }];
+        assert_eq!(&expected, mb.blob_metas());
+        drop(mb);
+
+        let mut mb = ManifestBuilder::new(opt.clone())?;
+        assert_eq!(&expected, mb.blob_metas());
+        assert!(mb.next_file_number() > 20);
+        // the snapshot of a new descriptor keeps the stale bytes
+        mb.roll_over()?;
+        drop(mb);
+        let mb = ManifestBuilder::new(opt.clone())?;
+        assert_eq!(&expected, mb.blob_metas());
         Ok(())
     }
 }
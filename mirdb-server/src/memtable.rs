@@ -1,50 +1,312 @@
 use std::borrow::Borrow;
+use std::cmp;
+use std::mem;
+use std::ops::Bound;
 use std::path::Path;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::Arc;
+use std::sync::RwLock;
 
-use skip_list::SkipList;
-use skip_list::SkipListIter;
+use skip_list::Arena;
+use skip_list::ArenaCopy;
+use skip_list::Comparator;
+use skip_list::ConcurrentSkipList;
+use skip_list::ConcurrentSkipListIter;
+use skip_list::GenHeight;
+use skip_list::HeightGenerator;
+use skip_list::OrdComparator;
+use sstable::SsIterator;
 use sstable::TableBuilder;
-use sstable::TableReader;
 
 use crate::error::MyResult;
+use crate::internal_key::make_lookup_key;
+use crate::internal_key::parse_internal_key;
+use crate::internal_key::user_key;
+use crate::internal_key::InternalKeyComparator;
+use crate::internal_key::SeqNum;
+use crate::internal_key::ValueType;
+use crate::merge_operator::MergeContext;
 use crate::options::Options;
+use crate::options::KB;
+use crate::range_del::RangeTombstone;
+use crate::range_del::RangeTombstones;
 use crate::slice::Slice;
 use crate::sstable_builder::skiplist_to_sstable;
+use crate::sstable_builder::BuiltTable;
 use crate::types::Table;
+use crate::utils::read_lock;
+use crate::utils::write_lock;
 
-#[derive(Clone)]
-pub struct Memtable<K: Ord + Clone, V: Clone> {
+const MIN_ARENA_BLOCK_SIZE: usize = 4 * KB;
+
+/// The entries are added through a shared reference, so the readers never
+/// wait for the writers. The nodes, with the bytes of their keys and values,
+/// are kept in an arena, an eighth of the table per block, and freed with
+/// the table. The keys are in the order of `C`, the store's are internal
+/// keys in the order of an `InternalKeyComparator`.
+pub struct Memtable<K: Ord + Clone, V: Clone, C = OrdComparator> {
     max_size_: usize,
-    size_: usize,
-    map_: SkipList<K, V>,
+    // the bytes of the range tombstones, the entries are counted by the
+    // arena
+    size_: AtomicUsize,
+    map_: ConcurrentSkipList<K, V, C>,
+    // kept apart from the entries, which are looked up by user key
+    range_dels_: RwLock<RangeTombstones>,
 }
 
-impl<K: Ord + Clone, V: Clone> Memtable<K, V> {
+impl<K: Ord + Clone + ArenaCopy, V: Clone + ArenaCopy> Memtable<K, V> {
     pub fn new(max_size: usize, max_height: usize) -> Self {
-        let map = SkipList::new(max_height);
+        Self::new_with_height_generator(max_size, max_height, Box::new(GenHeight::new()))
+    }
+
+    pub fn new_with_height_generator(
+        max_size: usize,
+        max_height: usize,
+        height_generator: Box<dyn HeightGenerator + Send>,
+    ) -> Self {
+        Memtable::new_with(
+            max_size,
+            max_height,
+            height_generator,
+            OrdComparator,
+            RangeTombstones::default(),
+        )
+    }
+}
+
+impl<K: Ord + Clone + ArenaCopy, V: Clone + ArenaCopy, C> Memtable<K, V, C> {
+    fn new_with(
+        max_size: usize,
+        max_height: usize,
+        height_generator: Box<dyn HeightGenerator + Send>,
+        cmp: C,
+        range_dels: RangeTombstones,
+    ) -> Self {
+        let arena = Arena::new(cmp::max(max_size / 8, MIN_ARENA_BLOCK_SIZE));
+        let map = ConcurrentSkipList::new_in_arena_with_comparator(
+            max_height,
+            height_generator,
+            cmp,
+            arena,
+        );
         Memtable {
             max_size_: max_size,
-            size_: 0,
+            size_: AtomicUsize::new(0),
             map_: map,
+            range_dels_: RwLock::new(range_dels),
         }
     }
+}
 
-    pub fn iter(&self) -> SkipListIter<K, V> {
+impl<K: Ord + Clone, V: Clone, C: Comparator<K>> Memtable<K, V, C> {
+    /// The entries point into the arena of the table, they must not be kept
+    /// past it.
+    pub fn iter(&self) -> ConcurrentSkipListIter<K, V, C> {
         self.map_.iter()
     }
 
     pub fn length(&self) -> usize {
         self.map_.length()
     }
+
+    /// Adds an entry, safe alongside other adds and reads. Returns false if
+    /// the key is in the table already.
+    pub fn add(&self, k: K, v: V) -> bool {
+        self.map_.insert(k, v).is_ok()
+    }
+
+    pub fn clear(&mut self) {
+        self.size_.store(0, Relaxed);
+        write_lock(&self.range_dels_).clear();
+        self.map_.clear()
+    }
+
+    #[inline]
+    pub fn is_full(&self) -> bool {
+        self.size() >= self.max_size_
+    }
+
+    /// The bytes the table takes in memory, the arena blocks are all
+    /// counted.
+    pub fn size(&self) -> usize {
+        self.size_.load(Relaxed) + self.map_.memory_usage()
+    }
 }
 
-impl Memtable<Slice, Slice> {
+impl Memtable<Slice, Slice, InternalKeyComparator> {
+    /// A table of the size and the node heights `opt` asks for, in the
+    /// order of its comparator.
+    pub fn with_options(opt: &Options) -> Self {
+        Memtable::new_with_comparator(
+            opt.mem_table_max_size,
+            opt.mem_table_max_height,
+            opt.mem_table_height_generator(),
+            opt.internal_comparator(),
+        )
+    }
+
+    /// The range tombstones are in the order of the user comparator.
+    pub fn new_with_comparator(
+        max_size: usize,
+        max_height: usize,
+        height_generator: Box<dyn HeightGenerator + Send>,
+        cmp: InternalKeyComparator,
+    ) -> Self {
+        let range_dels = RangeTombstones::new(cmp.user_comparator().clone());
+        Memtable::new_with(max_size, max_height, height_generator, cmp, range_dels)
+    }
+
+    /// Adds a range tombstone, the readers of the table take it into
+    /// account from now on.
+    pub fn add_range_deletion(&self, tombstone: RangeTombstone) {
+        let size = tombstone.start.len() + tombstone.end.len();
+        write_lock(&self.range_dels_).add(tombstone);
+        self.size_.fetch_add(size, Relaxed);
+    }
+
+    pub fn range_deletions(&self) -> RangeTombstones {
+        read_lock(&self.range_dels_).clone()
+    }
+
+    /// See `RangeTombstones::covering_seq`.
+    pub fn covering_seq(&self, key: &[u8], seq: SeqNum) -> Option<SeqNum> {
+        read_lock(&self.range_dels_).covering_seq(key, seq)
+    }
+
+    /// See `skiplist_to_sstable`, the range tombstones go along.
     pub fn build_sstable(
         &self,
         opt: &Options,
         path: &Path,
-    ) -> MyResult<Option<(String, TableReader)>> {
-        skiplist_to_sstable(&self.map_, opt, path)
+        newest_snapshot: SeqNum,
+    ) -> MyResult<Option<BuiltTable>> {
+        let range_dels: Vec<(Slice, Slice)> = read_lock(&self.range_dels_)
+            .iter()
+            .map(|x| (x.internal_key(), x.end.clone()))
+            .collect();
+        let entries = self
+            .map_
+            .iter()
+            .chain(range_dels.iter().map(|(k, v)| (k, v)));
+        skiplist_to_sstable(entries, opt, path, newest_snapshot)
+    }
+
+    /// Feeds `ctx` the entries of the user key of `lookup_key` which are not
+    /// newer than the sequence number of `lookup_key`, from the newest.
+    pub fn collect(&self, lookup_key: &[u8], ctx: &mut MergeContext) {
+        let key = user_key(lookup_key);
+        let mut lookup_key = Slice::from(lookup_key);
+        while !ctx.is_done() {
+            let parsed = match self.map_.seek(&lookup_key[..]) {
+                Some((k, v)) => match parse_internal_key(k.as_ref()) {
+                    Some(parsed) if parsed.user_key == key => {
+                        // copied out of the arena, it may outlive the table
+                        ctx.add(parsed.seq, parsed.value_type, Slice::from(&v[..]));
+                        parsed
+                    }
+                    _ => return,
+                },
+                None => return,
+            };
+            if parsed.seq == 0 {
+                return;
+            }
+            lookup_key = make_lookup_key(key, parsed.seq - 1);
+        }
+    }
+}
+
+enum Position {
+    BeforeFirst,
+    At(Slice, Slice),
+    AfterLast,
+}
+
+/// Iterates a memtable, which it keeps alive. It holds the current entry and
+/// finds the next one from its key, so it is not bothered by the entries
+/// added meanwhile. It may or may not see those, they are newer than any
+/// sequence number the reader can be at.
+pub struct MemtableIter {
+    table_: Arc<Memtable<Slice, Slice, InternalKeyComparator>>,
+    pos_: Position,
+}
+
+impl MemtableIter {
+    pub fn new(table: Arc<Memtable<Slice, Slice, InternalKeyComparator>>) -> Self {
+        MemtableIter {
+            table_: table,
+            pos_: Position::BeforeFirst,
+        }
+    }
+
+    fn current(&self) -> Option<(&Slice, &Slice)> {
+        match self.pos_ {
+            Position::At(ref k, ref v) => Some((k, v)),
+            _ => None,
+        }
+    }
+}
+
+/// The position of `entry`, or `otherwise` without one.
+fn position(entry: Option<(&Slice, &Slice)>, otherwise: Position) -> Position {
+    match entry {
+        Some((k, v)) => Position::At(k.clone(), v.clone()),
+        None => otherwise,
+    }
+}
+
+impl SsIterator for MemtableIter {
+    fn valid(&self) -> bool {
+        self.current().is_some()
+    }
+
+    fn advance(&mut self) -> bool {
+        let next = match self.pos_ {
+            Position::BeforeFirst => self.table_.map_.iter().next(),
+            Position::At(ref k, _) => self
+                .table_
+                .map_
+                .range::<[u8], _>((Bound::Excluded(&k[..]), Bound::Unbounded))
+                .next(),
+            Position::AfterLast => return false,
+        };
+        self.pos_ = position(next, Position::AfterLast);
+        self.valid()
+    }
+
+    fn prev(&mut self) -> bool {
+        let prev = match self.pos_ {
+            Position::BeforeFirst => return false,
+            Position::At(ref k, _) => self
+                .table_
+                .map_
+                .range::<[u8], _>((Bound::Unbounded, Bound::Excluded(&k[..])))
+                .next_back(),
+            Position::AfterLast => self.table_.map_.rev_iter().next(),
+        };
+        self.pos_ = position(prev, Position::BeforeFirst);
+        self.valid()
+    }
+
+    fn current_k(&self) -> Option<Vec<u8>> {
+        self.current().map(|(k, _)| k[..].to_vec())
+    }
+
+    fn current_v(&self) -> Option<Vec<u8>> {
+        self.current().map(|(_, v)| v[..].to_vec())
+    }
+
+    fn reset(&mut self) {
+        self.pos_ = Position::BeforeFirst;
+    }
+
+    fn seek(&mut self, key: &[u8]) {
+        self.pos_ = position(self.table_.map_.seek(key), Position::AfterLast);
+    }
+
+    fn seek_to_last(&mut self) {
+        self.pos_ = position(self.table_.map_.rev_iter().next(), Position::BeforeFirst);
     }
 }
 
@@ -57,7 +319,7 @@ impl<K: Ord + Clone, V: Clone> Table<K, V> for Memtable<K, V> {
         self.map_.get(k)
     }
 
-    fn get_mut<Q: ?Sized>(&self, k: &Q) -> Option<&mut V>
+    fn get_mut<Q: ?Sized>(&mut self, k: &Q) -> Option<&mut V>
     where
         K: Borrow<Q>,
         Q: Ord,
@@ -66,28 +328,45 @@ impl<K: Ord + Clone, V: Clone> Table<K, V> for Memtable<K, V> {
     }
 
     fn insert(&mut self, k: K, v: V) -> Option<V> {
-        self.map_.insert(k, v)
+        if let Some(value) = self.map_.get_mut(&k) {
+            // the key is kept, only the value is replaced
+            return Some(mem::replace(value, v));
+        }
+        self.add(k, v);
+        None
     }
 
     fn clear(&mut self) {
-        self.size_ = 0;
-        self.map_.clear()
+        Memtable::clear(self)
     }
 
-    #[inline]
     fn is_full(&self) -> bool {
-        false
+        Memtable::is_full(self)
     }
 
     fn size(&self) -> usize {
-        unimplemented!()
+        Memtable::size(self)
     }
 }
 
 #[cfg(test)]
 mod test {
+    use sstable::BytewiseComparator;
+
+    use crate::internal_key::make_internal_key;
+    use crate::internal_key::make_lookup_key;
+
     use super::*;
 
+    fn new_table(max_size: usize) -> Memtable<Slice, Slice, InternalKeyComparator> {
+        Memtable::new_with_comparator(
+            max_size,
+            10,
+            Box::new(GenHeight::new()),
+            InternalKeyComparator::new(Arc::new(BytewiseComparator)),
+        )
+    }
+
     #[test]
     fn test_get() {
         let mut table = Memtable::new(::std::mem::size_of_val(&1) * 6, 10);
@@ -111,4 +390,135 @@ mod test {
         assert_eq!(Some(&Slice::from("b")), table.get(&Slice::from("b")));
         assert_eq!(Some(&Slice::from("c")), table.get(&Slice::from("c")));
     }
+
+    fn collect(
+        table: &Memtable<Slice, Slice, InternalKeyComparator>,
+        key: &[u8],
+        seq: SeqNum,
+    ) -> MergeContext {
+        let mut ctx = MergeContext::new();
+        table.collect(&make_lookup_key(key, seq)[..], &mut ctx);
+        ctx
+    }
+
+    #[test]
+    fn test_size() {
+        let mut table = new_table(64 * KB);
+        let empty = table.size();
+        table.add(Slice::from("a"), Slice::from(vec![0; 100]));
+        let one = table.size();
+        // a whole arena block is taken, it holds the bytes of the entry too
+        assert_eq!(empty + 8 * KB, one);
+        table.add(Slice::from("b"), Slice::from(vec![0; 100]));
+        assert_eq!(one, table.size());
+        assert!(!table.is_full());
+        for i in 0..=255 {
+            table.add(Slice::from(vec![i, 0]), Slice::from(vec![0; 250]));
+        }
+        assert!(table.is_full());
+        assert!(table.size() >= 256 * 252);
+
+        let size = table.size();
+        table.add_range_deletion(RangeTombstone::new(b"a", b"c", 1));
+        assert_eq!(size + 2, table.size());
+        table.clear();
+        assert_eq!(empty, table.size());
+        assert!(table.range_deletions().is_empty());
+    }
+
+    #[test]
+    fn test_arena_copy() {
+        let table = new_table(64 * KB);
+        let v = Slice::from("v1");
+        table.add(make_internal_key(b"a", 1, ValueType::Value), v.clone());
+        // the table keeps a copy of the bytes in its arena
+        let (_, copy) = table.iter().next().unwrap();
+        assert_eq!(v, *copy);
+        assert_ne!(v.as_ref().as_ptr(), copy.as_ref().as_ptr());
+
+        // what is collected is copied out of it
+        let ctx = collect(&table, b"a", 1);
+        drop(table);
+        assert_eq!(Some(v), ctx.finish(b"a", None).unwrap());
+    }
+
+    #[test]
+    fn test_collect() -> MyResult<()> {
+        let table = new_table(0);
+        table.add(
+            make_internal_key(b"a", 1, ValueType::Value),
+            Slice::from("a1"),
+        );
+        table.add(
+            make_internal_key(b"a", 3, ValueType::Value),
+            Slice::from("a3"),
+        );
+        table.add(
+            make_internal_key(b"a", 4, ValueType::Merge),
+            Slice::from("a4"),
+        );
+        table.add(
+            make_internal_key(b"b", 2, ValueType::Deletion),
+            Slice::new(),
+        );
+        assert!(collect(&table, b"a", 0).is_empty());
+        assert_eq!(
+            Some(Slice::from("a1")),
+            collect(&table, b"a", 2).finish(b"a", None)?
+        );
+        assert_eq!(
+            Some(Slice::from("a3")),
+            collect(&table, b"a", 3).finish(b"a", None)?
+        );
+        // the operand and the value under it
+        let ctx = collect(&table, b"a", 5);
+        assert!(ctx.is_done());
+        assert!(ctx.finish(b"a", None).is_err());
+        let ctx = collect(&table, b"b", 5);
+        assert!(ctx.is_done());
+        assert_eq!(None, ctx.finish(b"b", None)?);
+        assert!(collect(&table, b"c", 5).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_ss_iter() {
+        let table = Arc::new(new_table(0));
+        for k in &["b", "d", "f"] {
+            table.add(Slice::from(*k), Slice::from(*k));
+        }
+        let mut iter = MemtableIter::new(table.clone());
+        assert_eq!(3, iter.count());
+
+        let k = |k: &str| Some(k.as_bytes().to_vec());
+        iter.reset();
+        assert!(iter.advance());
+        assert_eq!(k("b"), iter.current_k());
+        assert!(!iter.prev());
+        iter.seek(b"c");
+        assert_eq!(k("d"), iter.current_k());
+        assert!(iter.advance());
+        assert!(!iter.advance());
+        assert!(iter.prev());
+        assert_eq!(k("f"), iter.current_k());
+        iter.seek(b"g");
+        assert!(!iter.valid());
+        assert!(iter.prev());
+        assert_eq!(k("f"), iter.current_k());
+        iter.seek_to_last();
+        assert_eq!(k("f"), iter.current_k());
+
+        // an entry added meanwhile is found from the current one
+        iter.seek(b"b");
+        table.add(Slice::from("c"), Slice::from("c"));
+        assert!(iter.advance());
+        assert_eq!(k("c"), iter.current_k());
+        assert!(iter.prev());
+        assert_eq!(k("b"), iter.current_k());
+
+        let mut iter = MemtableIter::new(Arc::new(new_table(0)));
+        assert!(!iter.advance());
+        iter.seek_to_last();
+        assert!(!iter.valid());
+    }
 }
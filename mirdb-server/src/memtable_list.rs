@@ -1,57 +1,70 @@
 use std::borrow::Borrow;
 use std::collections::linked_list;
 use std::collections::LinkedList;
+use std::sync::Arc;
 
-use crate::error::MyResult;
+use skip_list::Comparator;
+use skip_list::OrdComparator;
+
+use crate::internal_key::InternalKeyComparator;
+use crate::internal_key::SeqNum;
 use crate::memtable::Memtable;
+use crate::merge_operator::MergeContext;
 use crate::options::Options;
+use crate::range_del::RangeTombstones;
+use crate::slice::Slice;
 use crate::types::Table;
 
+/// The sealed memtables, from the newest to the oldest. They are shared
+/// with the readers and not changed anymore.
 #[derive(Clone)]
-pub struct MemtableList<K: Ord + Clone, V: Clone> {
+pub struct MemtableList<K: Ord + Clone, V: Clone, C = OrdComparator> {
     max_table_count_: usize,
-    per_table_max_size_: usize,
-    per_table_max_height_: usize,
-    tables_: LinkedList<Memtable<K, V>>,
+    tables_: LinkedList<Arc<Memtable<K, V, C>>>,
     opt_: Options,
 }
 
-impl<K: Ord + Clone, V: Clone> MemtableList<K, V> {
-    pub fn new(
-        opt: Options,
-        max_table_count: usize,
-        per_table_max_size: usize,
-        per_table_max_height: usize,
-    ) -> Self {
+impl<K: Ord + Clone, V: Clone, C: Comparator<K>> MemtableList<K, V, C> {
+    pub fn new(opt: Options, max_table_count: usize) -> Self {
         let tables_ = LinkedList::new();
         MemtableList {
             max_table_count_: max_table_count,
-            per_table_max_size_: per_table_max_size,
-            per_table_max_height_: per_table_max_height,
             opt_: opt,
             tables_,
         }
     }
 
-    pub fn add(&mut self, table: Memtable<K, V>) {
+    pub fn add(&mut self, table: Arc<Memtable<K, V, C>>) {
         self.tables_.push_front(table);
     }
 
-    pub fn consume(&mut self) -> Option<Memtable<K, V>> {
+    pub fn consume(&mut self) -> Option<Arc<Memtable<K, V, C>>> {
         self.tables_.pop_back()
     }
 
-    pub fn tables_iter(&self) -> linked_list::Iter<Memtable<K, V>> {
+    pub fn tables_iter(&self) -> linked_list::Iter<Arc<Memtable<K, V, C>>> {
         self.tables_.iter()
     }
 
     pub fn table_count(&self) -> usize {
         self.tables_.len()
     }
+
+    pub fn clear(&mut self) {
+        self.tables_.clear();
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.tables_.len() >= self.max_table_count_
+    }
+
+    pub fn size(&self) -> usize {
+        self.tables_.iter().map(|x| x.size()).sum()
+    }
 }
 
-impl<K: Ord + Clone, V: Clone> Table<K, V> for MemtableList<K, V> {
-    fn get<Q: ?Sized>(&self, k: &Q) -> Option<&V>
+impl<K: Ord + Clone, V: Clone> MemtableList<K, V> {
+    pub fn get<Q: ?Sized>(&self, k: &Q) -> Option<&V>
     where
         K: Borrow<Q>,
         Q: Ord,
@@ -64,52 +77,32 @@ impl<K: Ord + Clone, V: Clone> Table<K, V> for MemtableList<K, V> {
         }
         None
     }
+}
 
-    fn get_mut<Q: ?Sized>(&self, k: &Q) -> Option<&mut V>
-    where
-        K: Borrow<Q>,
-        Q: Ord,
-    {
+impl MemtableList<Slice, Slice, InternalKeyComparator> {
+    /// Collects from the newest table to the oldest, see `Memtable::collect`.
+    pub fn collect(&self, lookup_key: &[u8], ctx: &mut MergeContext) {
         for table in &self.tables_ {
-            let r = table.get_mut(k);
-            if r.is_some() {
-                return r;
+            if ctx.is_done() {
+                return;
             }
+            table.collect(lookup_key, ctx);
         }
-        None
     }
 
-    fn insert(&mut self, k: K, v: V) -> Option<V> {
-        assert!(!self.is_full());
-
-        if self.tables_.is_empty() {
-            self.tables_.push_back(Memtable::new(
-                self.per_table_max_size_,
-                self.per_table_max_height_,
-            ));
-        }
-
-        for table in &mut self.tables_ {
-            if !table.is_full() {
-                return table.insert(k, v);
-            }
-        }
-
-        unreachable!("can not access here!");
+    /// See `RangeTombstones::covering_seq`.
+    pub fn covering_seq(&self, key: &[u8], seq: SeqNum) -> Option<SeqNum> {
+        self.tables_
+            .iter()
+            .filter_map(|x| x.covering_seq(key, seq))
+            .max()
     }
 
-    fn clear(&mut self) {
-        for table in &mut self.tables_ {
-            table.clear();
+    pub fn range_deletions(&self) -> RangeTombstones {
+        let mut range_dels = RangeTombstones::new(self.opt_.table_opt.comparator.clone());
+        for table in &self.tables_ {
+            range_dels.extend(table.range_deletions().iter());
         }
-        self.tables_.clear();
-    }
-
-    fn is_full(&self) -> bool {
-        self.tables_.len() >= self.max_table_count_
-    }
-
-    fn size(&self) -> usize {
-        unimplemented!()
+        range_dels
     }
 }
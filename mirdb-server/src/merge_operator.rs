@@ -0,0 +1,214 @@
+use crate::blob::BlobIndex;
+use crate::error::err;
+use crate::error::MyResult;
+use crate::error::StatusCode;
+use crate::internal_key::SeqNum;
+use crate::internal_key::ValueType;
+use crate::slice::Slice;
+
+/// Combines the merge operands of a key with its older value. Operands are
+/// written blindly and applied lazily, by the reads and by the compactions.
+/// Values and operands are the encoded bytes.
+pub trait MergeOperator: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Applies `operands`, from the oldest to the newest, to `existing`,
+    /// which is `None` when the key has no value. Returns the new value, or
+    /// `None` when the key ends up with no value.
+    fn full_merge(
+        &self,
+        key: &[u8],
+        existing: Option<&[u8]>,
+        operands: &[&[u8]],
+    ) -> MyResult<Option<Vec<u8>>>;
+}
+
+/// The entries of a user key, gathered from the newest to the oldest until
+/// a value or a deletion, which may be a range tombstone covering the key.
+#[derive(Default)]
+pub struct MergeContext {
+    // from the newest to the oldest
+    operands_: Vec<Slice>,
+    base_: Option<(ValueType, Slice)>,
+    // the entries older than it are deleted by a range tombstone
+    range_deletion_: Option<SeqNum>,
+}
+
+impl MergeContext {
+    pub fn new() -> Self {
+        MergeContext::default()
+    }
+
+    /// Deletes the entries older than `seq`, a range tombstone of that
+    /// sequence number covers the key. It is set before the entries are added.
+    pub fn set_range_deletion(&mut self, seq: SeqNum) {
+        self.range_deletion_ = Some(seq);
+    }
+
+    /// Takes the next older entry of the key.
+    pub fn add(&mut self, seq: SeqNum, value_type: ValueType, value: Slice) {
+        assert!(!self.is_done());
+        if let Some(deleted) = self.range_deletion_ {
+            if seq < deleted {
+                self.base_ = Some((ValueType::Deletion, Slice::new()));
+                return;
+            }
+        }
+        match value_type {
+            ValueType::Merge => self.operands_.push(value),
+            _ => self.base_ = Some((value_type, value)),
+        }
+    }
+
+    /// Whether the older entries don't matter anymore.
+    pub fn is_done(&self) -> bool {
+        self.base_.is_some()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operands_.is_empty() && self.base_.is_none()
+    }
+
+    /// Replaces a value which is in a blob file with the value `read` gets
+    /// for its index, which is returned.
+    pub fn resolve_blob<F>(&mut self, read: F) -> MyResult<Option<BlobIndex>>
+    where
+        F: FnOnce(&BlobIndex) -> MyResult<Slice>,
+    {
+        let index = match &self.base_ {
+            Some((ValueType::BlobIndex, v)) => BlobIndex::decode(&v[..])?,
+            _ => return Ok(None),
+        };
+        self.base_ = Some((ValueType::Value, read(&index)?));
+        Ok(Some(index))
+    }
+
+    /// The value of the key, `None` if it has none. A value in a blob file
+    /// must be resolved first.
+    pub fn finish(
+        self,
+        key: &[u8],
+        operator: Option<&dyn MergeOperator>,
+    ) -> MyResult<Option<Slice>> {
+        let existing = match self.base_ {
+            Some((ValueType::Value, v)) => Some(v),
+            Some((ValueType::BlobIndex, _)) => {
+                return err(StatusCode::BlobError, "unresolved blob index")
+            }
+            _ => None,
+        };
+        if self.operands_.is_empty() {
+            return Ok(existing);
+        }
+        let operator = match operator {
+            Some(operator) => operator,
+            None => return err(StatusCode::NotSupport, "no merge operator"),
+        };
+        let operands: Vec<&[u8]> = self.operands_.iter().rev().map(|x| &x[..]).collect();
+        let merged = operator.full_merge(key, existing.as_ref().map(|x| &x[..]), &operands)?;
+        Ok(merged.map(Slice::from))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Concatenates the operands.
+    struct Concat;
+
+    impl MergeOperator for Concat {
+        fn name(&self) -> &str {
+            "concat"
+        }
+
+        fn full_merge(
+            &self,
+            _key: &[u8],
+            existing: Option<&[u8]>,
+            operands: &[&[u8]],
+        ) -> MyResult<Option<Vec<u8>>> {
+            let mut v = existing.unwrap_or_default().to_vec();
+            for operand in operands {
+                v.extend_from_slice(operand);
+            }
+            Ok(Some(v))
+        }
+    }
+
+    #[test]
+    fn test_finish() -> MyResult<()> {
+        let mut ctx = MergeContext::new();
+        assert!(ctx.is_empty());
+        ctx.add(10, ValueType::Merge, Slice::from("c"));
+        ctx.add(9, ValueType::Merge, Slice::from("b"));
+        assert!(!ctx.is_done());
+        ctx.add(8, ValueType::Value, Slice::from("a"));
+        assert!(ctx.is_done());
+        assert_eq!(Some(Slice::from("abc")), ctx.finish(b"k", Some(&Concat))?);
+
+        let mut ctx = MergeContext::new();
+        ctx.add(10, ValueType::Merge, Slice::from("b"));
+        ctx.add(9, ValueType::Deletion, Slice::new());
+        assert_eq!(Some(Slice::from("b")), ctx.finish(b"k", Some(&Concat))?);
+
+        let mut ctx = MergeContext::new();
+        ctx.add(10, ValueType::Value, Slice::from("a"));
+        assert_eq!(Some(Slice::from("a")), ctx.finish(b"k", None)?);
+
+        let mut ctx = MergeContext::new();
+        ctx.add(10, ValueType::Merge, Slice::from("b"));
+        assert!(ctx.finish(b"k", None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_blob() -> MyResult<()> {
+        let index = BlobIndex {
+            file_number: 1,
+            offset: 0,
+            size: 1,
+            expires_at: 0,
+        };
+        let mut ctx = MergeContext::new();
+        ctx.add(10, ValueType::Merge, Slice::from("b"));
+        ctx.add(9, ValueType::BlobIndex, index.encode()?);
+        assert!(ctx.is_done());
+        assert!(MergeContext {
+            operands_: vec![],
+            base_: Some((ValueType::BlobIndex, index.encode()?)),
+            range_deletion_: None,
+        }
+        .finish(b"k", None)
+        .is_err());
+
+        assert_eq!(
+            Some(index),
+            ctx.resolve_blob(|x| {
+                assert_eq!(&index, x);
+                Ok(Slice::from("a"))
+            })?
+        );
+        assert_eq!(None, ctx.resolve_blob(|_| unreachable!())?);
+        assert_eq!(Some(Slice::from("ab")), ctx.finish(b"k", Some(&Concat))?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_range_deletion() -> MyResult<()> {
+        let mut ctx = MergeContext::new();
+        ctx.set_range_deletion(5);
+        ctx.add(6, ValueType::Merge, Slice::from("b"));
+        assert!(!ctx.is_done());
+        // deleted, whatever it is
+        ctx.add(4, ValueType::Value, Slice::from("a"));
+        assert!(ctx.is_done());
+        assert_eq!(Some(Slice::from("b")), ctx.finish(b"k", Some(&Concat))?);
+
+        let mut ctx = MergeContext::new();
+        ctx.set_range_deletion(5);
+        ctx.add(5, ValueType::Value, Slice::from("a"));
+        assert_eq!(Some(Slice::from("a")), ctx.finish(b"k", None)?);
+        Ok(())
+    }
+}
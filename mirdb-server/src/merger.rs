@@ -1,99 +1,142 @@
-use crate::utils::to_str;
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use sstable::Comparator;
 use sstable::SsIterator;
-use sstable::TableIter;
 
+/// Merges sorted iterators into one sorted iterator, on a tie the entry of
+/// the iterator given first wins and the others are skipped. The iterators
+/// are sorted by `cmp`.
 pub struct Merger<'a> {
-    iters: Vec<TableIter<'a>>,
+    iters: Vec<Box<dyn SsIterator + 'a>>,
+    cmp: Arc<dyn Comparator>,
     i: Option<usize>,
+    backward: bool,
 }
 
 impl<'a> Merger<'a> {
-    pub fn new(iters: Vec<TableIter<'a>>) -> Self {
-        Self { iters, i: None }
+    pub fn new(iters: Vec<Box<dyn SsIterator + 'a>>, cmp: Arc<dyn Comparator>) -> Self {
+        Self {
+            iters,
+            cmp,
+            i: None,
+            backward: false,
+        }
+    }
+
+    fn is_current(&self, k: &Option<Vec<u8>>, key: &Option<Vec<u8>>) -> bool {
+        match (k, key) {
+            (Some(k), Some(key)) => self.cmp.compare(k, key) == Ordering::Equal,
+            _ => false,
+        }
+    }
+
+    fn find_smallest(&mut self) {
+        let mut smallest: Option<(usize, Vec<u8>)> = None;
+        for (i, iter) in self.iters.iter().enumerate() {
+            if let Some(k) = iter.current_k() {
+                match &smallest {
+                    Some((_, sk)) if self.cmp.compare(sk, &k) != Ordering::Greater => {}
+                    _ => smallest = Some((i, k)),
+                }
+            }
+        }
+        self.i = smallest.map(|x| x.0);
+    }
+
+    fn find_largest(&mut self) {
+        let mut largest: Option<(usize, Vec<u8>)> = None;
+        for (i, iter) in self.iters.iter().enumerate() {
+            if let Some(k) = iter.current_k() {
+                match &largest {
+                    Some((_, lk)) if self.cmp.compare(lk, &k) != Ordering::Less => {}
+                    _ => largest = Some((i, k)),
+                }
+            }
+        }
+        self.i = largest.map(|x| x.0);
     }
 }
 
 impl<'a> SsIterator for Merger<'a> {
     fn valid(&self) -> bool {
-        self.iters.iter().any(SsIterator::valid)
+        self.i.map_or(false, |i| self.iters[i].valid())
     }
 
-    #[allow(clippy::op_ref)]
     fn advance(&mut self) -> bool {
-        let mut pk: Option<Vec<u8>> = None;
-        let mut pi = None;
-        let mut i = 0;
-        while i < self.iters.len() {
-            let nk = {
-                let iter = &mut self.iters[i];
-                if iter.advance() {
-                    iter.current_k()
-                } else {
-                    None
+        match self.i {
+            None => {
+                // unpositioned, or every iterator is exhausted
+                for iter in &mut self.iters {
+                    iter.advance();
                 }
-            };
-            if nk.is_none() {
-                i += 1;
-                continue;
             }
-            let nk = nk.unwrap();
-            if let Some(ref pk_) = pk {
-                if pk_ > &nk {
-                    let iter: &mut TableIter = &mut self.iters[pi.unwrap()];
-                    iter.prev();
-                    pk = Some(nk);
-                    pi = Some(i);
-                } else if pk_ < &nk {
-                    let iter = &mut self.iters[i];
-                    iter.prev();
+            Some(i) => {
+                if self.backward {
+                    // the others are before the current key, move them past it
+                    let key = self.iters[i].current_k().unwrap();
+                    let cmp = &self.cmp;
+                    for (j, iter) in self.iters.iter_mut().enumerate() {
+                        if j == i {
+                            continue;
+                        }
+                        iter.seek(&key);
+                        let is_same = iter
+                            .current_k()
+                            .map_or(false, |k| cmp.compare(&k, &key) == Ordering::Equal);
+                        if is_same {
+                            iter.advance();
+                        }
+                    }
+                    self.backward = false;
+                }
+                let key = self.iters[i].current_k();
+                for j in 0..self.iters.len() {
+                    if j != i && self.is_current(&self.iters[j].current_k(), &key) {
+                        self.iters[j].advance();
+                    }
                 }
-            } else {
-                pk = Some(nk);
-                pi = Some(i);
+                self.iters[i].advance();
             }
-            i += 1;
         }
-        self.i = pi;
-        pk.is_some()
+        self.find_smallest();
+        self.valid()
     }
 
-    #[allow(clippy::op_ref)]
     fn prev(&mut self) -> bool {
-        let mut pk = None;
-        let mut pi = None;
-        let mut i = 0;
-        while i < self.iters.len() {
-            let nk = {
-                let iter = &mut self.iters[i];
-                if iter.prev() {
-                    iter.current_k()
-                } else {
-                    None
-                }
-            };
-            if nk.is_none() {
-                i += 1;
-                continue;
+        match self.i {
+            None => {
+                self.seek_to_last();
+                return self.valid();
             }
-            let nk = nk.unwrap();
-            if let Some(pk_) = &pk {
-                if pk_ < &nk {
-                    let iter: &mut TableIter = &mut self.iters[pi.unwrap()];
-                    iter.advance();
-                    pk = Some(nk);
-                    pi = Some(i);
-                } else if pk_ > &nk {
-                    let iter = &mut self.iters[i];
-                    iter.advance();
+            Some(i) => {
+                if !self.backward {
+                    // the others are after the current key, move them before it
+                    let key = self.iters[i].current_k().unwrap();
+                    for (j, iter) in self.iters.iter_mut().enumerate() {
+                        if j == i {
+                            continue;
+                        }
+                        iter.seek(&key);
+                        if iter.valid() {
+                            iter.prev();
+                        } else {
+                            iter.seek_to_last();
+                        }
+                    }
+                    self.backward = true;
                 }
-            } else {
-                pk = Some(nk);
-                pi = Some(i);
+                let key = self.iters[i].current_k();
+                for j in 0..self.iters.len() {
+                    if j != i && self.is_current(&self.iters[j].current_k(), &key) {
+                        self.iters[j].prev();
+                    }
+                }
+                self.iters[i].prev();
             }
-            i += 1;
         }
-        self.i = pi;
-        self.current_k().is_some()
+        self.find_largest();
+        self.valid()
     }
 
     fn current_k(&self) -> Option<Vec<u8>> {
@@ -109,14 +152,23 @@ impl<'a> SsIterator for Merger<'a> {
             iter.reset()
         }
         self.i = None;
+        self.backward = false;
     }
 
-    fn seek(&mut self, _key: &[u8]) {
-        unimplemented!()
+    fn seek(&mut self, key: &[u8]) {
+        for iter in &mut self.iters {
+            iter.seek(key);
+        }
+        self.backward = false;
+        self.find_smallest();
     }
 
     fn seek_to_last(&mut self) {
-        unimplemented!()
+        for iter in &mut self.iters {
+            iter.seek_to_last();
+        }
+        self.backward = true;
+        self.find_largest();
     }
 }
 
@@ -124,6 +176,7 @@ impl<'a> SsIterator for Merger<'a> {
 mod test {
     use std::path::Path;
 
+    use sstable::BytewiseComparator;
     use sstable::TableBuilder;
     use sstable::TableReader;
 
@@ -133,8 +186,7 @@ mod test {
     use crate::error::MyResult;
     use crate::utils::to_str;
 
-    #[test]
-    fn test() -> MyResult<()> {
+    fn build_tables() -> MyResult<Vec<TableReader>> {
         let mut opt = Options::default();
         opt.table_opt.block_size = 20;
         let opt = opt.get_table_opt();
@@ -145,7 +197,7 @@ mod test {
         t.add("c".as_bytes(), "0".as_bytes())?;
         t.flush()?;
         let t = TableReader::new(path, opt.clone())?;
-        ts.push(t.iter());
+        ts.push(t);
         let path = Path::new("/tmp/test_merger1");
         let mut t = TableBuilder::new(path, opt.clone())?;
         t.add("c".as_bytes(), "c".as_bytes())?;
@@ -153,7 +205,7 @@ mod test {
         t.add("e".as_bytes(), "0".as_bytes())?;
         t.flush()?;
         let t = TableReader::new(path, opt.clone())?;
-        ts.push(t.iter());
+        ts.push(t);
         let path = Path::new("/tmp/test_merger2");
         let mut t = TableBuilder::new(path, opt.clone())?;
         t.add("a".as_bytes(), "a".as_bytes())?;
@@ -161,13 +213,66 @@ mod test {
         t.add("f".as_bytes(), "f".as_bytes())?;
         t.flush()?;
         let t = TableReader::new(path, opt.clone())?;
-        ts.push(t.iter());
+        ts.push(t);
         ts.reverse();
-        let mut m = Merger::new(ts);
+        Ok(ts)
+    }
+
+    fn iters(ts: &[TableReader]) -> Vec<Box<dyn SsIterator + '_>> {
+        ts.iter()
+            .map(|t| Box::new(t.iter()) as Box<dyn SsIterator>)
+            .collect()
+    }
+
+    #[test]
+    fn test() -> MyResult<()> {
+        let ts = build_tables()?;
+        let mut m = Merger::new(iters(&ts), Arc::new(BytewiseComparator));
+        let mut keys = vec![];
         while let Some((k, v)) = m.next() {
             println!("{}: {}", to_str(&k), to_str(&v));
             assert_eq!(k, v);
+            keys.push(k);
         }
+        let expected: Vec<&[u8]> = vec![b"a", b"b", b"c", b"d", b"e", b"f"];
+        assert_eq!(expected, keys);
+        Ok(())
+    }
+
+    #[test]
+    fn test_seek_prev() -> MyResult<()> {
+        let ts = build_tables()?;
+        let mut m = Merger::new(iters(&ts), Arc::new(BytewiseComparator));
+        let kv = |k: &str| Some((k.as_bytes().to_vec(), k.as_bytes().to_vec()));
+
+        m.seek(b"c");
+        assert_eq!(kv("c"), m.current_kv());
+        assert!(m.prev());
+        assert_eq!(kv("b"), m.current_kv());
+        assert!(m.advance());
+        assert_eq!(kv("c"), m.current_kv());
+        assert!(m.advance());
+        assert_eq!(kv("d"), m.current_kv());
+        assert!(m.prev());
+        assert_eq!(kv("c"), m.current_kv());
+        assert!(m.prev());
+        assert!(m.prev());
+        assert_eq!(kv("a"), m.current_kv());
+        assert!(!m.prev());
+        assert!(m.advance());
+        assert_eq!(kv("a"), m.current_kv());
+
+        m.seek_to_last();
+        assert_eq!(kv("f"), m.current_kv());
+        assert!(m.prev());
+        assert_eq!(kv("e"), m.current_kv());
+        assert!(m.prev());
+        assert_eq!(kv("d"), m.current_kv());
+        assert!(m.advance());
+        assert_eq!(kv("e"), m.current_kv());
+        assert!(m.advance());
+        assert_eq!(kv("f"), m.current_kv());
+        assert!(!m.advance());
         Ok(())
     }
 }
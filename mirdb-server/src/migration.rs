@@ -0,0 +1,233 @@
+use std::path::Path;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::Relaxed;
+
+use bincode::deserialize;
+use bincode::serialize;
+use log::info;
+
+use sstable::SsIterator;
+use sstable::TableReader;
+
+use crate::error::MyResult;
+use crate::internal_key::make_internal_key;
+use crate::internal_key::SeqNum;
+use crate::internal_key::ValueType;
+use crate::manifest::VersionEdit;
+use crate::manifest::FORMAT_VERSION;
+use crate::options::Options;
+use crate::slice::Slice;
+use crate::sstable_builder::SstableBuilder;
+use crate::store::StorePayload;
+use crate::utils::make_file_name;
+use crate::version::TableFile;
+use crate::version_set::VersionSet;
+use crate::wal::WAL;
+
+/// The entry of a user key and a bincode `Option<StorePayload>` of format 0
+/// under the sequence number `seq`.
+fn upgrade_entry(k: &[u8], v: &[u8], seq: SeqNum) -> MyResult<(Slice, Slice)> {
+    let payload: Option<StorePayload> = deserialize(v)?;
+    Ok(match payload {
+        Some(payload) => (
+            make_internal_key(k, seq, ValueType::Value),
+            Slice::from(serialize(&payload)?),
+        ),
+        None => (make_internal_key(k, seq, ValueType::Deletion), Slice::new()),
+    })
+}
+
+/// Writes the entries, in key order, to a new sstable.
+fn build_table(
+    opt: &Options,
+    next_file_number: &AtomicUsize,
+    entries: &[(Slice, Slice)],
+) -> MyResult<Option<TableFile>> {
+    if entries.is_empty() {
+        return Ok(None);
+    }
+    let path = Path::new(&opt.work_dir).join(make_file_name(
+        next_file_number.fetch_add(1, Relaxed),
+        "sst",
+    ));
+    let mut table = SstableBuilder::new(&path, opt)?;
+    for (k, v) in entries {
+        table.add(&k[..], &v[..])?;
+    }
+    table.finish()?;
+    let reader = TableReader::new(&path, opt.get_table_opt())?;
+    Ok(Some(TableFile::new(&path, reader)))
+}
+
+/// Rewrites the sstables and the wal segs of a work dir of format 0 with
+/// internal keys, in one edit which records the current format. The
+/// entries get the sequence numbers in the order they were written: the
+/// deepest level first, level 0 from its oldest file, then the wal segs
+/// from the oldest record. The wal segs become sstables of level 0.
+pub fn upgrade_format(opt: &Options, versions: &VersionSet) -> MyResult<()> {
+    info!(
+        "upgrading the work dir from format {} to {}...",
+        versions.format_version(),
+        FORMAT_VERSION
+    );
+    let next_file_number = versions.file_number_allocator();
+    let version = versions.current();
+    let mut seq = versions.last_sequence();
+    let mut edit = VersionEdit {
+        format_version: Some(FORMAT_VERSION),
+        ..VersionEdit::default()
+    };
+    let mut new_files = vec![];
+
+    for level in (0..opt.max_level).rev() {
+        for file in version.get_files(level) {
+            // a level holds a user key once, the entries stay in order
+            let mut entries = vec![];
+            let mut iter = file.iter();
+            while let Some((k, v)) = iter.next() {
+                seq += 1;
+                entries.push(upgrade_entry(&k, &v, seq)?);
+            }
+            edit.delete_file(level, file);
+            if let Some(file) = build_table(opt, &next_file_number, &entries)? {
+                edit.add_file(level, &file);
+                new_files.push(file);
+            }
+        }
+    }
+
+    let wal = WAL::new(opt.clone(), versions.log_number(), next_file_number.clone())?;
+    for seg in &wal.segs {
+        let mut entries = vec![];
+        for (k, v) in seg.iter()? {
+            seq += 1;
+            entries.push(upgrade_entry(&k[..], &v[..], seq)?);
+        }
+        entries.sort();
+        if let Some(file) = build_table(opt, &next_file_number, &entries)? {
+            edit.add_file(0, &file);
+            new_files.push(file);
+        }
+    }
+    // the wal segs are left behind, to be removed once the wal is opened
+    edit.log_number = Some(next_file_number.fetch_add(1, Relaxed));
+
+    info!(
+        "upgraded {} sstables and {} wal segs, {} entries",
+        edit.deleted_files.len(),
+        wal.seg_count(),
+        seq - versions.last_sequence()
+    );
+    versions.recover_last_sequence(seq);
+    versions.log_and_apply(edit, new_files)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+    use std::io::Write;
+    use std::sync::Arc;
+
+    use sstable::TableBuilder;
+
+    use crate::data_manager::DataManager;
+    use crate::manifest::FileMeta;
+    use crate::manifest::LevelMeta;
+    use crate::store::StoreKey;
+    use crate::test_utils::get_test_opt;
+
+    use super::*;
+
+    fn make_payload(data: &[u8]) -> StorePayload {
+        StorePayload::new(Slice::from(data), 0, 0, 0, 0)
+    }
+
+    /// A value of format 0.
+    fn legacy_value(data: Option<&[u8]>) -> Slice {
+        Slice::from(serialize(&data.map(make_payload)).unwrap())
+    }
+
+    fn legacy_table(opt: &Options, number: usize, entries: &[(&str, Option<&[u8]>)]) -> LevelMeta {
+        let file_name = make_file_name(number, "sst");
+        let path = Path::new(&opt.work_dir).join(&file_name);
+        let mut tb = TableBuilder::new(&path, opt.get_table_opt()).unwrap();
+        for (k, v) in entries {
+            tb.add(k.as_bytes(), &legacy_value(*v)[..]).unwrap();
+        }
+        tb.flush().unwrap();
+        let mut lm = LevelMeta::new();
+        lm.push_file_meta(FileMeta { file_name });
+        lm
+    }
+
+    #[test]
+    fn test_upgrade_format() -> MyResult<()> {
+        let opt = get_test_opt();
+        let mut l0 = legacy_table(&opt, 2, &[("b", None), ("d", Some(b"d2"))]);
+        l0.file_metas
+            .extend(legacy_table(&opt, 3, &[("d", Some(b"d3"))]).file_metas);
+        let l1 = legacy_table(
+            &opt,
+            1,
+            &[("a", Some(b"a1")), ("b", Some(b"b1")), ("c", Some(b"c1"))],
+        );
+        // the single file manifest, which is of format 0
+        File::create(Path::new(&opt.work_dir).join("MANIFEST"))?
+            .write_all(&serialize(&vec![l0, l1])?)?;
+        let mut wal = WAL::new(opt.clone(), 0, Arc::new(AtomicUsize::new(4)))?;
+        let records: Vec<(&str, Option<&[u8]>)> = vec![
+            ("c", Some(b"c4")),
+            ("e", Some(b"e4")),
+            ("a", None),
+            ("e", Some(b"e5")),
+        ];
+        for (k, v) in records {
+            wal.append(&Slice::from(k), &legacy_value(v))?;
+        }
+        drop(wal);
+
+        let expected: Vec<(StoreKey, StorePayload)> = vec![
+            (Slice::from("c"), make_payload(b"c4")),
+            (Slice::from("d"), make_payload(b"d3")),
+            (Slice::from("e"), make_payload(b"e5")),
+        ];
+        let check = |dm: &DataManager| -> MyResult<()> {
+            assert_eq!(None, dm.get(&Slice::from("a"))?);
+            assert_eq!(None, dm.get(&Slice::from("b"))?);
+            for (k, v) in &expected {
+                assert_eq!(Some(v.clone()), dm.get(k)?);
+            }
+            assert_eq!(expected, dm.iter(None, None, None).collect::<Vec<_>>());
+            Ok(())
+        };
+        let dm = DataManager::new(opt.clone())?;
+        check(&dm)?;
+        // newer writes go after the upgraded entries
+        dm.insert(Slice::from("a"), make_payload(b"a6"))?;
+        assert_eq!(Some(make_payload(b"a6")), dm.get(&Slice::from("a"))?);
+        dm.remove(&Slice::from("a"))?;
+        drop(dm);
+
+        for file_name in &[
+            "00000001.sst",
+            "00000002.sst",
+            "00000003.sst",
+            "00000004.wal",
+        ] {
+            assert!(!Path::new(&opt.work_dir).join(file_name).exists());
+        }
+        let versions = VersionSet::new(opt.clone())?;
+        assert_eq!(FORMAT_VERSION, versions.format_version());
+        let version = versions.current();
+        assert_eq!(1, version.get_files(1).len());
+        assert!(version.get_files(0).len() >= 3);
+        drop(version);
+        drop(versions);
+
+        let dm = DataManager::new(opt.clone())?;
+        check(&dm)?;
+        dm.major_compaction()?;
+        check(&dm)?;
+        Ok(())
+    }
+}
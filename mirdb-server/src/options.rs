@@ -1,5 +1,14 @@
+use skip_list::GenHeight;
+use skip_list::HeightGenerator;
 use sstable::Options as TableOptions;
 use std::path::Path;
+use std::sync::Arc;
+
+use crate::compaction_filter::CompactionFilter;
+use crate::internal_key::user_key;
+use crate::internal_key::InternalKeyComparator;
+use crate::merge_operator::MergeOperator;
+use crate::store::StoreMergeOperator;
 
 pub const KB: usize = 1 << 10;
 pub const MB: usize = KB * KB;
@@ -17,18 +26,69 @@ pub struct Options {
     pub sst_max_size: usize,
     pub mem_table_max_size: usize,
     pub mem_table_max_height: usize,
+    /// The chance a memtable node goes up a level, LevelDB uses 1/4.
+    pub mem_table_height_probability: f64,
+    /// Seeds the heights of the memtable nodes, so runs can be reproduced.
+    pub mem_table_height_seed: Option<u64>,
     pub imm_mem_table_max_count: usize,
+    /// The bytes of the values the row cache keeps, 0 for no row cache.
+    pub row_cache_size: usize,
+    /// Values of at least this many encoded bytes are moved out of the
+    /// sstables into blob files, 0 keeps every value in the sstables.
+    pub blob_value_threshold: usize,
+    /// A blob file is garbage collected once this share of it is stale.
+    pub blob_gc_ratio: f64,
 
     pub l0_compaction_trigger: usize,
+    /// Each write is delayed a bit from this many level 0 files on.
+    pub l0_slowdown_writes_trigger: usize,
+    /// Writes wait for the compactions from this many level 0 files on, or
+    /// once `imm_mem_table_max_count` memtables wait for a flush.
+    pub l0_stop_writes_trigger: usize,
 
+    /// The delay before a failed flush or compaction is retried.
     pub thread_sleep_ms: usize,
+    pub flush_threads: usize,
+    pub compaction_threads: usize,
+
+    /// The descriptor log rolls over to a new file beyond this size.
+    pub manifest_max_size: usize,
 
+    /// Its comparator orders the user keys, the tables get it wrapped in an
+    /// `InternalKeyComparator`, see `get_table_opt`.
     pub table_opt: TableOptions,
+
+    /// Drops or rewrites the values on flush and compaction.
+    pub compaction_filter: Option<Arc<dyn CompactionFilter>>,
+
+    /// Applies the merge operands, the store writes `StoreOperand`s.
+    pub merge_operator: Option<Arc<dyn MergeOperator>>,
 }
 
 impl Options {
-    pub fn get_table_opt(&self) -> &TableOptions {
-        &self.table_opt
+    /// The options the sstables are built and read with, their keys are
+    /// internal keys.
+    pub fn get_table_opt(&self) -> TableOptions {
+        let mut opt = self.table_opt.clone();
+        opt.comparator = Arc::new(self.internal_comparator());
+        opt
+    }
+
+    pub fn internal_comparator(&self) -> InternalKeyComparator {
+        InternalKeyComparator::new(self.table_opt.comparator.clone())
+    }
+
+    /// Whether a value of `size` encoded bytes goes to a blob file.
+    pub fn is_blob_value(&self, size: usize) -> bool {
+        self.blob_value_threshold > 0 && size >= self.blob_value_threshold
+    }
+
+    pub fn mem_table_height_generator(&self) -> Box<dyn HeightGenerator + Send> {
+        let p = self.mem_table_height_probability;
+        match self.mem_table_height_seed {
+            Some(seed) => Box::new(GenHeight::with_seed(p, seed)),
+            None => Box::new(GenHeight::with_probability(p)),
+        }
     }
 }
 
@@ -37,6 +97,7 @@ impl Default for Options {
         let mut table_opt = TableOptions::default();
         table_opt.block_size = BLOCK_MAX_SIZE;
         table_opt.block_restart_interval = 16;
+        table_opt.filter_key = user_key;
 
         Options {
             max_level: 7,
@@ -44,13 +105,28 @@ impl Default for Options {
             sst_max_size: MB * 100,
             mem_table_max_size: MB * 4,
             mem_table_max_height: 1 << 5,
+            mem_table_height_probability: 0.5,
+            mem_table_height_seed: None,
             imm_mem_table_max_count: 1 << 4,
+            row_cache_size: 0,
+            blob_value_threshold: 0,
+            blob_gc_ratio: 0.5,
 
             l0_compaction_trigger: 4,
+            l0_slowdown_writes_trigger: 8,
+            l0_stop_writes_trigger: 12,
 
             thread_sleep_ms: 500,
+            flush_threads: 1,
+            compaction_threads: 1,
+
+            manifest_max_size: MB * 4,
 
             table_opt,
+
+            compaction_filter: None,
+
+            merge_operator: Some(Arc::new(StoreMergeOperator)),
         }
     }
 }
@@ -1,4 +1,4 @@
-use crate::parser_util::macros::{digit, space, u32_parser, usize_parser, IRResult};
+use crate::parser_util::macros::{digit, space, u32_parser, u64_parser, usize_parser, IRResult};
 use crate::request::GetterType;
 use crate::request::Request;
 use crate::request::SetterType;
@@ -98,11 +98,54 @@ gen_parser!(
     )
 );
 
+gen_parser!(
+    range_deleter<Request>,
+    chain!(
+        tag!(b"delete_range")
+            >> space
+            >> start: key_parser
+            >> space
+            >> end: key_parser
+            >> opt!(space)
+            >> no_reply: opt!(tag!(b"noreply"))
+            >> tag!(b"\r\n")
+            >> (Request::RangeDeleter {
+                start: Slice::from(start),
+                end: Slice::from(end),
+                no_reply: unwrap_no_reply(no_reply),
+            })
+    )
+);
+
+gen_parser!(
+    incr<Request>,
+    chain!(
+        tag!(b"incr")
+            >> space
+            >> key: key_parser
+            >> space
+            >> value: u64_parser
+            >> opt!(space)
+            >> no_reply: opt!(tag!(b"noreply"))
+            >> tag!(b"\r\n")
+            >> (Request::Incr {
+                key: Slice::from(key),
+                value,
+                no_reply: unwrap_no_reply(no_reply),
+            })
+    )
+);
+
 gen_parser!(
     info<Request>,
     chain!(tag!(b"info") >> tag!(b"\r\n") >> (Request::Info))
 );
 
+gen_parser!(
+    stats<Request>,
+    chain!(tag!(b"stats") >> tag!(b"\r\n") >> (Request::Stats))
+);
+
 gen_parser!(
     major_compaction<Request>,
     chain!(tag!(b"major_compaction") >> tag!(b"\r\n") >> (Request::MajorCompaction))
@@ -110,7 +153,7 @@ gen_parser!(
 
 gen_parser!(
     parse<Request>,
-    alt!(getter | setter | deleter | info | major_compaction)
+    alt!(getter | setter | range_deleter | deleter | incr | info | stats | major_compaction)
 );
 
 #[cfg(test)]
@@ -289,5 +332,42 @@ mod test {
                 }
             ))
         );
+        assert_eq!(
+            parse(b"delete_range a b\r\n"),
+            IRResult::Ok((
+                "".as_bytes(),
+                Request::RangeDeleter {
+                    start: Slice::from("a"),
+                    end: Slice::from("b"),
+                    no_reply: false,
+                }
+            ))
+        );
+        assert_eq!(
+            parse(b"delete_range a b noreply\r\n"),
+            IRResult::Ok((
+                "".as_bytes(),
+                Request::RangeDeleter {
+                    start: Slice::from("a"),
+                    end: Slice::from("b"),
+                    no_reply: true,
+                }
+            ))
+        );
+        assert_eq!(
+            parse(b"incr abc 12 noreply\r\n"),
+            IRResult::Ok((
+                "".as_bytes(),
+                Request::Incr {
+                    key: Slice::from("abc"),
+                    value: 12,
+                    no_reply: true,
+                }
+            ))
+        );
+        assert_eq!(
+            parse(b"stats\r\n"),
+            IRResult::Ok(("".as_bytes(), Request::Stats))
+        );
     }
 }
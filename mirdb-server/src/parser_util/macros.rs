@@ -360,6 +360,11 @@ pub fn u32_parser(i: &[u8]) -> IRResult<u32> {
     digit::<u32>(i)
 }
 
+#[inline]
+pub fn u64_parser(i: &[u8]) -> IRResult<u64> {
+    digit::<u64>(i)
+}
+
 #[inline]
 pub fn usize_parser(i: &[u8]) -> IRResult<usize> {
     digit::<usize>(i)
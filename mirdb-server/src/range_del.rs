@@ -0,0 +1,280 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::sync::Arc;
+
+use sstable::BytewiseComparator;
+use sstable::Comparator;
+
+use crate::error::err;
+use crate::error::MyResult;
+use crate::error::StatusCode;
+use crate::internal_key::make_internal_key;
+use crate::internal_key::parse_internal_key;
+use crate::internal_key::SeqNum;
+use crate::internal_key::ValueType;
+use crate::slice::Slice;
+
+/// Deletes the user keys in `[start, end)`, the entries of those which are
+/// older than it. It is written as an entry of type `RangeDeletion` keyed by
+/// `start`, whose value is `end`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RangeTombstone {
+    pub start: Slice,
+    pub end: Slice,
+    pub seq: SeqNum,
+}
+
+impl RangeTombstone {
+    pub fn new(start: &[u8], end: &[u8], seq: SeqNum) -> Self {
+        RangeTombstone {
+            start: Slice::from(start),
+            end: Slice::from(end),
+            seq,
+        }
+    }
+
+    /// Parses an entry of the log or of a range deletion block.
+    pub fn decode(k: &[u8], v: &[u8]) -> MyResult<Self> {
+        match parse_internal_key(k) {
+            Some(parsed) if parsed.value_type == ValueType::RangeDeletion => {
+                Ok(RangeTombstone::new(parsed.user_key, v, parsed.seq))
+            }
+            _ => err(StatusCode::Other, "bad range tombstone"),
+        }
+    }
+
+    pub fn internal_key(&self) -> Slice {
+        make_internal_key(&self.start[..], self.seq, ValueType::RangeDeletion)
+    }
+
+    /// Whether it deletes `key`, the user keys are in the order of `cmp`.
+    pub fn contains(&self, key: &[u8], cmp: &dyn Comparator) -> bool {
+        cmp.compare(&self.start[..], key) != Ordering::Greater
+            && cmp.compare(key, &self.end[..]) == Ordering::Less
+    }
+
+    /// Whether it deletes any user key of `[smallest, largest]`.
+    pub fn overlaps(&self, smallest: &[u8], largest: &[u8], cmp: &dyn Comparator) -> bool {
+        cmp.compare(&self.start[..], largest) != Ordering::Greater
+            && cmp.compare(smallest, &self.end[..]) == Ordering::Less
+    }
+}
+
+/// A piece of the key space which the same tombstones cover, with their
+/// sequence numbers in ascending order.
+#[derive(Debug, Clone)]
+struct Fragment {
+    start_: Slice,
+    end_: Slice,
+    seqs_: Vec<SeqNum>,
+}
+
+/// Range tombstones sorted by their start keys, in the order of the user
+/// comparator. They are cut into fragments which don't overlap when added,
+/// so a lookup is a binary search.
+#[derive(Clone)]
+pub struct RangeTombstones {
+    cmp_: Arc<dyn Comparator>,
+    list_: Vec<RangeTombstone>,
+    fragments_: Vec<Fragment>,
+}
+
+/// Orders the user keys by their bytes.
+impl Default for RangeTombstones {
+    fn default() -> Self {
+        RangeTombstones::new(Arc::new(BytewiseComparator))
+    }
+}
+
+impl fmt::Debug for RangeTombstones {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.list_.iter()).finish()
+    }
+}
+
+impl RangeTombstones {
+    pub fn new(cmp: Arc<dyn Comparator>) -> Self {
+        RangeTombstones {
+            cmp_: cmp,
+            list_: vec![],
+            fragments_: vec![],
+        }
+    }
+
+    pub fn comparator(&self) -> &Arc<dyn Comparator> {
+        &self.cmp_
+    }
+
+    pub fn add(&mut self, tombstone: RangeTombstone) {
+        let cmp = &self.cmp_;
+        let i = match self
+            .list_
+            .binary_search_by(|x| cmp.compare(&x.start[..], &tombstone.start[..]))
+        {
+            Ok(i) | Err(i) => i,
+        };
+        self.list_.insert(i, tombstone);
+        self.fragments_ = fragment(&self.list_, cmp.as_ref());
+    }
+
+    pub fn extend<'a, I>(&mut self, tombstones: I)
+    where
+        I: IntoIterator<Item = &'a RangeTombstone>,
+    {
+        let cmp = &self.cmp_;
+        self.list_.extend(tombstones.into_iter().cloned());
+        self.list_
+            .sort_by(|a, b| cmp.compare(&a.start[..], &b.start[..]));
+        self.fragments_ = fragment(&self.list_, cmp.as_ref());
+    }
+
+    pub fn clear(&mut self) {
+        self.list_.clear();
+        self.fragments_.clear();
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &RangeTombstone> {
+        self.list_.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list_.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.list_.len()
+    }
+
+    /// The newest sequence number, not newer than `seq`, of the tombstones
+    /// containing `key`. The entries of the key older than it are deleted.
+    pub fn covering_seq(&self, key: &[u8], seq: SeqNum) -> Option<SeqNum> {
+        // the last fragment starting at or before the key
+        let i = self
+            .fragments_
+            .binary_search_by(|x| self.cmp_.compare(&x.start_[..], key))
+            .unwrap_or_else(|i| i.wrapping_sub(1));
+        let fragment = self.fragments_.get(i)?;
+        if self.cmp_.compare(key, &fragment.end_[..]) != Ordering::Less {
+            return None;
+        }
+        let n = match fragment.seqs_.binary_search(&seq) {
+            Ok(n) => n + 1,
+            Err(n) => n,
+        };
+        fragment.seqs_[..n].last().cloned()
+    }
+}
+
+/// Cuts the tombstones, sorted by their start keys, at every start and end
+/// key, and keeps the pieces which some of them cover.
+fn fragment(list: &[RangeTombstone], cmp: &dyn Comparator) -> Vec<Fragment> {
+    let mut bounds: Vec<&[u8]> = list
+        .iter()
+        .flat_map(|x| vec![&x.start[..], &x.end[..]])
+        .collect();
+    bounds.sort_by(|a, b| cmp.compare(a, b));
+    bounds.dedup_by(|a, b| cmp.compare(a, b) == Ordering::Equal);
+
+    let mut fragments = vec![];
+    let mut active: Vec<&RangeTombstone> = vec![];
+    let mut next = 0;
+    for pair in bounds.windows(2) {
+        let (start, end) = (pair[0], pair[1]);
+        while next < list.len() && cmp.compare(&list[next].start[..], start) == Ordering::Equal {
+            active.push(&list[next]);
+            next += 1;
+        }
+        active.retain(|x| cmp.compare(&x.end[..], start) == Ordering::Greater);
+        if active.is_empty() {
+            continue;
+        }
+        let mut seqs: Vec<SeqNum> = active.iter().map(|x| x.seq).collect();
+        seqs.sort();
+        fragments.push(Fragment {
+            start_: Slice::from(start),
+            end_: Slice::from(end),
+            seqs_: seqs,
+        });
+    }
+    fragments
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Orders the keys backward.
+    struct ReverseComparator;
+
+    impl Comparator for ReverseComparator {
+        fn name(&self) -> &str {
+            "test.ReverseComparator"
+        }
+
+        fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+            b.cmp(a)
+        }
+    }
+
+    #[test]
+    fn test_covering_seq() -> MyResult<()> {
+        let mut tombstones = RangeTombstones::default();
+        tombstones.add(RangeTombstone::new(b"c", b"f", 5));
+        tombstones.add(RangeTombstone::new(b"a", b"d", 3));
+        tombstones.add(RangeTombstone::new(b"b", b"e", 8));
+        assert_eq!(3, tombstones.len());
+
+        assert_eq!(None, tombstones.covering_seq(b"0", 10));
+        assert_eq!(Some(3), tombstones.covering_seq(b"a", 10));
+        assert_eq!(Some(8), tombstones.covering_seq(b"c", 10));
+        // not visible at the sequence number
+        assert_eq!(Some(5), tombstones.covering_seq(b"c", 7));
+        assert_eq!(None, tombstones.covering_seq(b"c", 2));
+        // the end is excluded
+        assert_eq!(Some(5), tombstones.covering_seq(b"e", 10));
+        assert_eq!(None, tombstones.covering_seq(b"f", 10));
+
+        // a gap between the tombstones, and ones of no key
+        tombstones.extend(&[
+            RangeTombstone::new(b"h", b"k", 4),
+            RangeTombstone::new(b"i", b"i", 9),
+            RangeTombstone::new(b"j", b"i", 9),
+        ]);
+        assert_eq!(None, tombstones.covering_seq(b"g", 10));
+        assert_eq!(Some(4), tombstones.covering_seq(b"i", 10));
+        assert_eq!(Some(4), tombstones.covering_seq(b"j", 10));
+        assert_eq!(None, tombstones.covering_seq(b"k", 10));
+
+        let t = RangeTombstone::new(b"b", b"e", 8);
+        assert_eq!(t, RangeTombstone::decode(&t.internal_key()[..], b"e")?);
+        assert!(
+            RangeTombstone::decode(&make_internal_key(b"b", 8, ValueType::Value)[..], b"e")
+                .is_err()
+        );
+        let cmp = BytewiseComparator;
+        assert!(t.overlaps(b"a", b"b", &cmp));
+        assert!(t.overlaps(b"c", b"z", &cmp));
+        assert!(!t.overlaps(b"e", b"z", &cmp));
+        assert!(!t.overlaps(b"0", b"a", &cmp));
+        assert!(t.contains(b"b", &cmp));
+        assert!(!t.contains(b"e", &cmp));
+        Ok(())
+    }
+
+    #[test]
+    fn test_comparator() {
+        // the ranges go from the greater key to the lesser one
+        let mut tombstones = RangeTombstones::new(Arc::new(ReverseComparator));
+        tombstones.add(RangeTombstone::new(b"f", b"c", 5));
+        tombstones.add(RangeTombstone::new(b"d", b"a", 3));
+        assert_eq!(Some(5), tombstones.covering_seq(b"f", 10));
+        assert_eq!(Some(5), tombstones.covering_seq(b"d", 10));
+        assert_eq!(Some(3), tombstones.covering_seq(b"c", 10));
+        assert_eq!(None, tombstones.covering_seq(b"a", 10));
+        assert_eq!(None, tombstones.covering_seq(b"g", 10));
+
+        let t = RangeTombstone::new(b"f", b"c", 5);
+        assert!(t.overlaps(b"z", b"e", &ReverseComparator));
+        assert!(!t.overlaps(b"c", b"a", &ReverseComparator));
+    }
+}
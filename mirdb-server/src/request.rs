@@ -37,7 +37,19 @@ pub enum Request {
         key: Key,
         no_reply: bool,
     },
+    /// Deletes the keys in `[start, end)`.
+    RangeDeleter {
+        start: Key,
+        end: Key,
+        no_reply: bool,
+    },
+    Incr {
+        key: Key,
+        value: u64,
+        no_reply: bool,
+    },
     Info,
+    Stats,
     Error,
     MajorCompaction,
 }
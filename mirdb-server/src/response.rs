@@ -44,6 +44,8 @@ pub enum Response {
     ClientError(String),
     ServerError(String),
     Info(String),
+    Stats(Vec<(String, String)>),
+    Incr(u64),
 }
 
 pub trait Writer {
@@ -123,6 +125,15 @@ impl Response {
             Response::Info(s) => {
                 writer.write(format!("INFO\r\n\r\n{}\r\n\r\nEND\r\n", s).as_bytes())?;
             }
+            Response::Stats(items) => {
+                for (name, value) in items {
+                    writer.write(format!("STAT {} {}\r\n", name, value).as_bytes())?;
+                }
+                writer.write(b"END\r\n")?;
+            }
+            Response::Incr(n) => {
+                writer.write(format!("{}\r\n", n).as_bytes())?;
+            }
             _ => {
                 unimplemented!();
             }
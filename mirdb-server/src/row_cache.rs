@@ -0,0 +1,272 @@
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::Mutex;
+
+use crate::slice::Slice;
+use crate::store::StorePayload;
+use crate::utils::key_stripe;
+
+/// What an entry costs besides its key and its data.
+const ENTRY_OVERHEAD: usize = mem::size_of::<(Slice, Entry)>() + mem::size_of::<(Slice, u64)>();
+
+struct Entry {
+    value_: StorePayload,
+    id_: u64,
+    // read since the eviction last passed it
+    referenced_: bool,
+    charge_: usize,
+}
+
+struct RowCacheInner {
+    map_: HashMap<Slice, Entry>,
+    // the keys in the order they were cached, the eviction goes round them
+    // and gives the referenced ones another turn. The ids tell apart the
+    // keys which were removed or cached again meanwhile.
+    queue_: VecDeque<(Slice, u64)>,
+    size_: usize,
+    next_id_: u64,
+    // of each key stripe, bumped by every invalidation in the stripe
+    epochs_: Vec<u64>,
+}
+
+/// Caches the decoded values of the user keys read from the sstables, up
+/// to `capacity` bytes, so that a hot key costs a hash lookup.
+///
+/// A write invalidates its key after it is published. A reader takes the
+/// epoch of the key's stripe before it reads and only caches the value if
+/// nothing in the stripe was invalidated since, so a value read before a
+/// write can't be cached after the write invalidated it. The keys are
+/// striped like the key locks of the `DataManager`.
+pub struct RowCache {
+    capacity_: usize,
+    inner_: Mutex<RowCacheInner>,
+}
+
+impl RowCache {
+    pub fn new(capacity: usize, stripes: usize) -> Self {
+        RowCache {
+            capacity_: capacity,
+            inner_: Mutex::new(RowCacheInner {
+                map_: HashMap::new(),
+                queue_: VecDeque::new(),
+                size_: 0,
+                next_id_: 0,
+                epochs_: vec![0; stripes],
+            }),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity_
+    }
+
+    pub fn size(&self) -> usize {
+        self.inner_.lock().unwrap().size_
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner_.lock().unwrap().map_.len()
+    }
+
+    pub fn epoch(&self, k: &[u8]) -> u64 {
+        let inner = self.inner_.lock().unwrap();
+        inner.epochs_[inner.stripe(k)]
+    }
+
+    pub fn get(&self, k: &[u8]) -> Option<StorePayload> {
+        let mut inner = self.inner_.lock().unwrap();
+        inner.map_.get_mut(k).map(|entry| {
+            entry.referenced_ = true;
+            entry.value_.clone()
+        })
+    }
+
+    /// Caches a value read as of `epoch` of its stripe. Returns false if it
+    /// is not cached, for a key of the stripe was invalidated since or it is
+    /// too big.
+    pub fn insert(&self, k: Slice, v: StorePayload, epoch: u64) -> bool {
+        let charge = k.len() + v.data.len() + ENTRY_OVERHEAD;
+        if charge > self.capacity_ {
+            return false;
+        }
+
+        let mut inner = self.inner_.lock().unwrap();
+        if inner.epochs_[inner.stripe(&k[..])] != epoch {
+            return false;
+        }
+        inner.remove(&k[..]);
+
+        let id = inner.next_id_;
+        inner.next_id_ += 1;
+        inner.size_ += charge;
+        inner.queue_.push_back((k.clone(), id));
+        inner.map_.insert(
+            k,
+            Entry {
+                value_: v,
+                id_: id,
+                referenced_: false,
+                charge_: charge,
+            },
+        );
+
+        while inner.size_ > self.capacity_ {
+            inner.evict_one();
+        }
+        inner.compact_queue();
+        true
+    }
+
+    /// Drops the cached value of the key, it was written.
+    pub fn invalidate(&self, k: &[u8]) {
+        let mut inner = self.inner_.lock().unwrap();
+        let i = inner.stripe(k);
+        inner.epochs_[i] += 1;
+        inner.remove(k);
+    }
+
+    /// Drops every cached value, after a change of the values other than a
+    /// write, like the one of a compaction filter.
+    pub fn clear(&self) {
+        let mut inner = self.inner_.lock().unwrap();
+        inner.epochs_.iter_mut().for_each(|x| *x += 1);
+        inner.map_.clear();
+        inner.queue_.clear();
+        inner.size_ = 0;
+    }
+}
+
+impl RowCacheInner {
+    fn stripe(&self, k: &[u8]) -> usize {
+        key_stripe(k, self.epochs_.len())
+    }
+
+    fn remove(&mut self, k: &[u8]) {
+        if let Some(entry) = self.map_.remove(k) {
+            self.size_ -= entry.charge_;
+        }
+    }
+
+    fn evict_one(&mut self) {
+        while let Some((k, id)) = self.queue_.pop_front() {
+            let referenced = match self.map_.get_mut(&k) {
+                Some(entry) if entry.id_ == id => mem::replace(&mut entry.referenced_, false),
+                // removed or cached again since
+                _ => continue,
+            };
+            if referenced {
+                self.queue_.push_back((k, id));
+            } else {
+                self.remove(&k[..]);
+                return;
+            }
+        }
+    }
+
+    /// Drops the stale keys of the queue once they outnumber the live ones.
+    fn compact_queue(&mut self) {
+        if self.queue_.len() <= 2 * self.map_.len() + 16 {
+            return;
+        }
+        let map = &self.map_;
+        self.queue_
+            .retain(|(k, id)| map.get(k).map(|entry| entry.id_) == Some(*id));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn make_payload(v: &[u8]) -> StorePayload {
+        StorePayload::new(Slice::from(v), 0, 0, 0, 0)
+    }
+
+    fn charge(k: &[u8], v: &[u8]) -> usize {
+        k.len() + v.len() + ENTRY_OVERHEAD
+    }
+
+    #[test]
+    fn test_get() {
+        let cache = RowCache::new(1024, 4);
+        assert_eq!(None, cache.get(b"a"));
+
+        let epoch = cache.epoch(b"a");
+        assert!(cache.insert(Slice::from("a"), make_payload(b"1"), epoch));
+        assert_eq!(Some(make_payload(b"1")), cache.get(b"a"));
+        assert_eq!(charge(b"a", b"1"), cache.size());
+
+        assert!(cache.insert(Slice::from("a"), make_payload(b"22"), epoch));
+        assert_eq!(Some(make_payload(b"22")), cache.get(b"a"));
+        assert_eq!(charge(b"a", b"22"), cache.size());
+        assert_eq!(1, cache.len());
+    }
+
+    #[test]
+    fn test_invalidate() {
+        let cache = RowCache::new(1024, 4);
+        let epoch = cache.epoch(b"a");
+        assert!(cache.insert(Slice::from("a"), make_payload(b"1"), epoch));
+        assert!(cache.insert(Slice::from("b"), make_payload(b"2"), cache.epoch(b"b")));
+
+        cache.invalidate(b"a");
+        assert_eq!(None, cache.get(b"a"));
+        assert_eq!(Some(make_payload(b"2")), cache.get(b"b"));
+        assert_eq!(charge(b"b", b"2"), cache.size());
+
+        // read before the invalidation, it may be stale
+        assert!(!cache.insert(Slice::from("a"), make_payload(b"1"), epoch));
+        assert_eq!(None, cache.get(b"a"));
+        assert!(cache.insert(Slice::from("a"), make_payload(b"3"), cache.epoch(b"a")));
+        assert_eq!(Some(make_payload(b"3")), cache.get(b"a"));
+
+        // a write to another stripe doesn't keep a read from the cache
+        let other = (0..)
+            .map(|i| format!("k{}", i))
+            .find(|x| key_stripe(x.as_bytes(), 4) != key_stripe(b"a", 4))
+            .unwrap();
+        let epoch = cache.epoch(b"a");
+        cache.invalidate(other.as_bytes());
+        assert!(cache.insert(Slice::from("a"), make_payload(b"4"), epoch));
+        assert_eq!(Some(make_payload(b"4")), cache.get(b"a"));
+
+        cache.clear();
+        assert!(!cache.insert(Slice::from("a"), make_payload(b"4"), epoch));
+        assert_eq!(None, cache.get(b"b"));
+        assert_eq!(0, cache.size());
+        assert_eq!(0, cache.len());
+    }
+
+    #[test]
+    fn test_evict() {
+        let cap = 4 * charge(b"k0", b"v");
+        let cache = RowCache::new(cap, 4);
+        assert!(!cache.insert(Slice::from("big"), make_payload(&vec![0; cap]), 0));
+
+        for i in 0..4 {
+            let k = format!("k{}", i);
+            assert!(cache.insert(Slice::from(k.as_bytes()), make_payload(b"v"), 0));
+        }
+        assert_eq!(cap, cache.size());
+
+        // k0 was read, so k1 goes first
+        assert!(cache.get(b"k0").is_some());
+        assert!(cache.insert(Slice::from("k4"), make_payload(b"v"), 0));
+        assert!(cache.get(b"k0").is_some());
+        assert_eq!(None, cache.get(b"k1"));
+        assert_eq!(4, cache.len());
+        assert!(cache.size() <= cap);
+
+        for i in 0..100 {
+            let k = format!("k{}", i % 8);
+            cache.insert(Slice::from(k.as_bytes()), make_payload(b"v"), 0);
+            cache.invalidate(b"k0");
+            let epoch = cache.epoch(b"k0");
+            cache.insert(Slice::from("k0"), make_payload(b"v"), epoch);
+        }
+        assert!(cache.size() <= cap);
+        let inner = cache.inner_.lock().unwrap();
+        assert!(inner.queue_.len() <= 2 * inner.map_.len() + 16);
+    }
+}
@@ -0,0 +1,104 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::SeqCst;
+use std::sync::Arc;
+use std::sync::Weak;
+
+use crate::data_manager::DataManager;
+use crate::thread_pool::ThreadPool;
+
+/// Runs the flushes and the compactions of a `DataManager` on their own
+/// thread pools, as soon as there is work for them. A job which is queued
+/// already is not queued again, it will see the new work when it runs.
+pub struct Scheduler {
+    dm_: Weak<DataManager>,
+    flush_pool_: ThreadPool,
+    compaction_pool_: ThreadPool,
+    flush_pending_: Arc<AtomicBool>,
+    compaction_pending_: Arc<AtomicBool>,
+}
+
+impl Scheduler {
+    pub fn new(dm: &Arc<DataManager>) -> Self {
+        Scheduler {
+            dm_: Arc::downgrade(dm),
+            flush_pool_: ThreadPool::new(dm.opt().flush_threads),
+            compaction_pool_: ThreadPool::new(dm.opt().compaction_threads),
+            flush_pending_: Arc::new(AtomicBool::new(false)),
+            compaction_pending_: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn schedule_flush(&self) {
+        self.schedule(
+            &self.flush_pool_,
+            &self.flush_pending_,
+            DataManager::flush_job,
+        );
+    }
+
+    pub fn schedule_compaction(&self) {
+        self.schedule(
+            &self.compaction_pool_,
+            &self.compaction_pending_,
+            DataManager::compaction_job,
+        );
+    }
+
+    fn schedule(&self, pool: &ThreadPool, pending: &Arc<AtomicBool>, job: fn(&DataManager)) {
+        if pending.swap(true, SeqCst) {
+            return;
+        }
+        let pending = pending.clone();
+        let dm = self.dm_.clone();
+        pool.execute(move || {
+            // the work coming in from now on needs another run
+            pending.store(false, SeqCst);
+            if let Some(dm) = dm.upgrade() {
+                job(&dm);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use std::time::Duration;
+    use std::time::Instant;
+
+    use crate::error::MyResult;
+    use crate::slice::Slice;
+    use crate::store::StorePayload;
+    use crate::test_utils::get_test_opt;
+
+    use super::*;
+
+    #[test]
+    fn test_schedule() -> MyResult<()> {
+        let mut opt = get_test_opt();
+        opt.imm_mem_table_max_count = 4;
+        opt.l0_compaction_trigger = 2;
+        // nothing happens on a timer
+        opt.thread_sleep_ms = 60_000;
+
+        let dm = DataManager::new(opt)?;
+        DataManager::start_background_jobs(&dm);
+        for i in 0..20 {
+            let k = Slice::from(format!("{:02}", i));
+            dm.insert(k, StorePayload::new(Slice::from("v"), 0, 0, 1, 0))?;
+        }
+
+        // the rotations trigger flushes, the flushes trigger compactions
+        let start = Instant::now();
+        while dm.imm_table_count() > 0 || dm.level_file_count(0) >= 2 {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(dm.level_file_count(1) > 0);
+        for i in 0..20 {
+            let k = Slice::from(format!("{:02}", i));
+            assert!(dm.get(&k)?.is_some());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,98 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::SeqCst;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use futures::sync::oneshot;
+use log::info;
+
+static SIGNALED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_signal(_: libc::c_int) {
+    // only async-signal-safe work in here
+    SIGNALED.store(true, SeqCst);
+}
+
+/// Resolves once the process gets SIGINT or SIGTERM.
+pub fn wait_for_signal() -> oneshot::Receiver<()> {
+    unsafe {
+        libc::signal(libc::SIGINT, on_signal as libc::sighandler_t);
+        libc::signal(libc::SIGTERM, on_signal as libc::sighandler_t);
+    }
+    let (tx, rx) = oneshot::channel();
+    thread::spawn(move || {
+        while !SIGNALED.load(SeqCst) {
+            thread::sleep(Duration::from_millis(100));
+        }
+        info!("got a shutdown signal");
+        let _ = tx.send(());
+    });
+    rx
+}
+
+/// Tracks the requests being served, new ones are refused once closed.
+#[derive(Default)]
+pub struct Gate {
+    closed_: AtomicBool,
+    in_flight_: AtomicUsize,
+}
+
+impl Gate {
+    pub fn new() -> Self {
+        Gate::default()
+    }
+
+    /// Lets a request in, unless the gate is closed. It must `leave` then.
+    pub fn enter(&self) -> bool {
+        self.in_flight_.fetch_add(1, SeqCst);
+        if self.closed_.load(SeqCst) {
+            self.leave();
+            return false;
+        }
+        true
+    }
+
+    pub fn leave(&self) {
+        self.in_flight_.fetch_sub(1, SeqCst);
+    }
+
+    /// Closes the gate and waits for the requests inside, until `deadline`.
+    /// Returns whether they are all done.
+    pub fn close(&self, deadline: Instant) -> bool {
+        self.closed_.store(true, SeqCst);
+        while self.in_flight_.load(SeqCst) > 0 {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn test_gate() {
+        let gate = Arc::new(Gate::new());
+        assert!(gate.enter());
+        assert!(!gate.close(Instant::now() + Duration::from_millis(20)));
+
+        let handle = {
+            let gate = gate.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                gate.leave();
+            })
+        };
+        assert!(gate.close(Instant::now() + Duration::from_secs(5)));
+        assert!(!gate.enter());
+        handle.join().unwrap();
+    }
+}
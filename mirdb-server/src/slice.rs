@@ -15,6 +15,7 @@ use bytes::Bytes;
 use bytes::BytesMut;
 use serde::de::{self, Visitor};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use skip_list::ArenaCopy;
 
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Slice {
@@ -228,6 +229,20 @@ impl From<BytesMut> for Slice {
     }
 }
 
+/// The copy in an arena is a static `Bytes`, which frees nothing. The
+/// memtables copy what they hand out of it, see `Memtable::collect`.
+unsafe impl ArenaCopy for Slice {
+    fn arena_bytes(&self) -> &[u8] {
+        self.inner.as_ref()
+    }
+
+    unsafe fn in_arena(&self, bytes: &'static [u8]) -> Self {
+        Self {
+            inner: Bytes::from_static(bytes),
+        }
+    }
+}
+
 macro_rules! impl_from {
     ($type:ty) => {
         impl From<$type> for Slice {
@@ -0,0 +1,104 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use crate::internal_key::SeqNum;
+
+/// The sequence numbers of the live snapshots, compaction keeps every entry
+/// one of them can still see.
+#[derive(Default)]
+pub struct SnapshotList {
+    seqs_: Mutex<BTreeMap<SeqNum, usize>>,
+}
+
+impl SnapshotList {
+    pub fn new() -> Self {
+        SnapshotList::default()
+    }
+
+    /// Registers a snapshot at the sequence number `seq` returns, which is
+    /// read while the list is locked so that `oldest` never misses it.
+    pub fn acquire<F>(list: &Arc<Self>, seq: F) -> Snapshot
+    where
+        F: FnOnce() -> SeqNum,
+    {
+        let mut seqs = list.seqs_.lock().unwrap();
+        let seq = seq();
+        *seqs.entry(seq).or_insert(0) += 1;
+        Snapshot {
+            seq_: seq,
+            list_: list.clone(),
+        }
+    }
+
+    fn release(&self, seq: SeqNum) {
+        let mut seqs = self.seqs_.lock().unwrap();
+        let count = seqs.get_mut(&seq).expect("release an unknown snapshot");
+        *count -= 1;
+        if *count == 0 {
+            seqs.remove(&seq);
+        }
+    }
+
+    pub fn oldest(&self) -> Option<SeqNum> {
+        self.seqs_.lock().unwrap().keys().next().cloned()
+    }
+
+    pub fn newest(&self) -> Option<SeqNum> {
+        self.seqs_.lock().unwrap().keys().next_back().cloned()
+    }
+
+    /// The sequence numbers of the snapshots in ascending order.
+    pub fn all(&self) -> Vec<SeqNum> {
+        self.seqs_.lock().unwrap().keys().cloned().collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seqs_.lock().unwrap().is_empty()
+    }
+}
+
+/// A consistent view of the db as of a sequence number. Reads through it go
+/// to the live memtables and sstables and skip the newer entries, these
+/// never drop an entry a live snapshot can see, so nothing else is pinned.
+pub struct Snapshot {
+    seq_: SeqNum,
+    list_: Arc<SnapshotList>,
+}
+
+impl Snapshot {
+    pub fn seq(&self) -> SeqNum {
+        self.seq_
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        self.list_.release(self.seq_);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_oldest() {
+        let list = Arc::new(SnapshotList::new());
+        assert_eq!(None, list.oldest());
+        let s3 = SnapshotList::acquire(&list, || 3);
+        let s1 = SnapshotList::acquire(&list, || 1);
+        let s1_ = SnapshotList::acquire(&list, || 1);
+        assert_eq!(1, s1.seq());
+        assert_eq!(Some(1), list.oldest());
+        assert_eq!(Some(3), list.newest());
+        assert_eq!(vec![1, 3], list.all());
+        drop(s1);
+        assert_eq!(Some(1), list.oldest());
+        drop(s1_);
+        assert_eq!(Some(3), list.oldest());
+        drop(s3);
+        assert_eq!(None, list.oldest());
+        assert!(list.is_empty());
+    }
+}
@@ -1,42 +1,176 @@
 use std::borrow::Borrow;
+use std::collections::BTreeSet;
 use std::fs::File;
 use std::path::Path;
+use std::path::PathBuf;
 use std::time::SystemTime;
 use std::time::UNIX_EPOCH;
 
 use bincode::{deserialize, serialize};
 use serde::Serialize;
 
-use skip_list::SkipList;
+use sstable::Comparator;
 use sstable::TableBuilder;
 use sstable::TableReader;
 
+use crate::blob::BlobFile;
+use crate::blob::BlobIndex;
+use crate::blob::BlobWriter;
+use crate::compaction_filter::apply_filter;
+use crate::error::err;
 use crate::error::MyResult;
+use crate::error::StatusCode;
+use crate::internal_key::make_internal_key;
+use crate::internal_key::parse_internal_key;
+use crate::internal_key::SeqNum;
+use crate::internal_key::ValueType;
 use crate::options::Options;
 use crate::slice::Slice;
 use crate::store::StoreKey;
 use crate::store::StorePayload;
+use crate::utils::parse_file_number;
 
-pub fn skiplist_to_sstable(
-    map: &SkipList<Slice, Slice>,
+/// The property of an sstable which holds the numbers of the blob files its
+/// blob indexes point into, a bincode `Vec<usize>`.
+pub const BLOB_FILES_PROPERTY: &str = "mirdb.blob_files";
+
+/// Builds an sstable of internal keys, recording the blob files its blob
+/// indexes point into, so a blob gc finds the sstables to rewrite without
+/// reading them.
+pub struct SstableBuilder {
+    builder_: TableBuilder,
+    blob_files_: BTreeSet<usize>,
+}
+
+impl SstableBuilder {
+    pub fn new(path: &Path, opt: &Options) -> MyResult<Self> {
+        Ok(SstableBuilder {
+            builder_: TableBuilder::new(path, opt.get_table_opt())?,
+            blob_files_: BTreeSet::new(),
+        })
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        self.builder_.path()
+    }
+
+    pub fn total_size_estimate(&self) -> usize {
+        self.builder_.total_size_estimate()
+    }
+
+    pub fn add(&mut self, k: &[u8], v: &[u8]) -> MyResult<()> {
+        match parse_internal_key(k) {
+            Some(parsed) if parsed.value_type == ValueType::BlobIndex => {
+                self.blob_files_.insert(BlobIndex::decode(v)?.file_number);
+            }
+            Some(_) => {}
+            None => return err(StatusCode::Other, "bad internal key"),
+        }
+        self.builder_.add(k, v)?;
+        Ok(())
+    }
+
+    pub fn add_range_deletion(&mut self, k: &[u8], v: &[u8]) -> MyResult<()> {
+        self.builder_.add_range_deletion(k, v)?;
+        Ok(())
+    }
+
+    /// See `TableBuilder::set_range_deletion_bounds`.
+    pub fn set_range_deletion_bounds(&mut self, min_key: &[u8], max_key: &[u8]) {
+        self.builder_.set_range_deletion_bounds(min_key, max_key);
+    }
+
+    pub fn finish(mut self) -> MyResult<()> {
+        let blob_files: Vec<usize> = self.blob_files_.into_iter().collect();
+        self.builder_
+            .set_property(BLOB_FILES_PROPERTY, &serialize(&blob_files)?);
+        self.builder_.flush()?;
+        Ok(())
+    }
+}
+
+/// The file name and the reader of a new sstable, and its blob file if
+/// values were separated.
+pub type BuiltTable = (String, TableReader, Option<BlobFile>);
+
+/// Writes out the entries of a skip list, in key order, the values newer
+/// than `newest_snapshot` go through the compaction filter of `opt` at
+/// level 0. The values `opt` separates go to a blob file of the same number
+/// as the sstable, which is returned with it. The range tombstones, in any
+/// order, go to the range deletion block.
+pub fn skiplist_to_sstable<'a, I>(
+    entries: I,
     opt: &Options,
     path: &Path,
-) -> MyResult<Option<(String, TableReader)>> {
-    if map.length() == 0 {
+    newest_snapshot: SeqNum,
+) -> MyResult<Option<BuiltTable>>
+where
+    I: Iterator<Item = (&'a Slice, &'a Slice)>,
+{
+    let mut entries = entries.peekable();
+    if entries.peek().is_none() {
         return Ok(None);
     }
 
-    let table_opt = opt.get_table_opt();
-    let mut tb = TableBuilder::new(&path, table_opt.clone())?;
+    let mut tb = SstableBuilder::new(path, opt)?;
+    let mut blob: Option<BlobWriter> = None;
+    let mut range_dels = vec![];
+
+    for (k, v) in entries {
+        let parsed = match parse_internal_key(k.borrow()) {
+            Some(parsed) => parsed,
+            None => return err(StatusCode::Other, "bad internal key"),
+        };
+        if parsed.value_type == ValueType::RangeDeletion {
+            range_dels.push((k, v));
+            continue;
+        }
+        let mut filtered = None;
+        if let Some(filter) = &opt.compaction_filter {
+            if parsed.value_type == ValueType::Value && parsed.seq > newest_snapshot {
+                let payload: StorePayload = deserialize(v.borrow())?;
+                filtered = apply_filter(filter.as_ref(), 0, parsed.user_key, &payload)?;
+            }
+        }
+        let (value_type, v): (ValueType, &[u8]) = match &filtered {
+            Some((value_type, v)) => (*value_type, &v[..]),
+            None => (parsed.value_type, v.borrow()),
+        };
+
+        if value_type == ValueType::Value && opt.is_blob_value(v.len()) {
+            if blob.is_none() {
+                let number = match parse_file_number(path) {
+                    Some(number) => number,
+                    None => return err(StatusCode::Other, "bad sstable file name"),
+                };
+                blob = Some(BlobWriter::new(opt, number)?);
+            }
+            let index = blob.as_mut().unwrap().add_value(v)?;
+            let k = make_internal_key(parsed.user_key, parsed.seq, ValueType::BlobIndex);
+            tb.add(&k[..], &index[..])?;
+        } else if filtered.is_some() {
+            let k = make_internal_key(parsed.user_key, parsed.seq, value_type);
+            tb.add(&k[..], v)?;
+        } else {
+            tb.add(k.borrow(), v)?;
+        }
+    }
 
-    for (k, v) in map.iter() {
-        tb.add(k.borrow(), v.borrow())?;
+    let cmp = opt.internal_comparator();
+    range_dels.sort_by(|a, b| cmp.compare(&a.0[..], &b.0[..]));
+    for (k, v) in range_dels {
+        tb.add_range_deletion(k.borrow(), v.borrow())?;
     }
 
-    tb.flush()?;
+    tb.finish()?;
+    let blob = match blob {
+        Some(blob) => Some(blob.finish()?),
+        None => None,
+    };
 
     Ok(Some((
         path.to_str().unwrap().to_owned(),
-        TableReader::new(path, table_opt.clone())?,
+        TableReader::new(path, opt.get_table_opt())?,
+        blob,
     )))
 }
@@ -0,0 +1,113 @@
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+
+/// Counters of the db, reported by the `stats` command.
+#[derive(Default)]
+pub struct Stats {
+    expired_reclaimed_: AtomicU64,
+    slowdown_micros_: AtomicU64,
+    stop_micros_: AtomicU64,
+    background_errors_: AtomicU64,
+    row_cache_hits_: AtomicU64,
+    row_cache_misses_: AtomicU64,
+    blob_gcs_: AtomicU64,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Stats::default()
+    }
+
+    /// Counts the expired entries a compaction dropped or turned into
+    /// tombstones.
+    pub fn add_expired_reclaimed(&self, n: u64) {
+        self.expired_reclaimed_.fetch_add(n, Relaxed);
+    }
+
+    pub fn expired_reclaimed(&self) -> u64 {
+        self.expired_reclaimed_.load(Relaxed)
+    }
+
+    /// Counts the time writers were delayed by a slowdown.
+    pub fn add_slowdown_micros(&self, n: u64) {
+        self.slowdown_micros_.fetch_add(n, Relaxed);
+    }
+
+    pub fn slowdown_micros(&self) -> u64 {
+        self.slowdown_micros_.load(Relaxed)
+    }
+
+    /// Counts the time writers were blocked until the flushes and the
+    /// compactions caught up.
+    pub fn add_stop_micros(&self, n: u64) {
+        self.stop_micros_.fetch_add(n, Relaxed);
+    }
+
+    pub fn stop_micros(&self) -> u64 {
+        self.stop_micros_.load(Relaxed)
+    }
+
+    /// Counts the flushes and the compactions which failed and are retried.
+    pub fn add_background_error(&self) {
+        self.background_errors_.fetch_add(1, Relaxed);
+    }
+
+    pub fn background_errors(&self) -> u64 {
+        self.background_errors_.load(Relaxed)
+    }
+
+    pub fn add_row_cache_hit(&self) {
+        self.row_cache_hits_.fetch_add(1, Relaxed);
+    }
+
+    pub fn row_cache_hits(&self) -> u64 {
+        self.row_cache_hits_.load(Relaxed)
+    }
+
+    pub fn add_row_cache_miss(&self) {
+        self.row_cache_misses_.fetch_add(1, Relaxed);
+    }
+
+    pub fn row_cache_misses(&self) -> u64 {
+        self.row_cache_misses_.load(Relaxed)
+    }
+
+    /// Counts the blob files garbage collected.
+    pub fn add_blob_gc(&self) {
+        self.blob_gcs_.fetch_add(1, Relaxed);
+    }
+
+    pub fn blob_gcs(&self) -> u64 {
+        self.blob_gcs_.load(Relaxed)
+    }
+
+    pub fn items(&self) -> Vec<(String, String)> {
+        vec![
+            (
+                "expired_reclaimed".to_owned(),
+                self.expired_reclaimed().to_string(),
+            ),
+            (
+                "stall_slowdown_micros".to_owned(),
+                self.slowdown_micros().to_string(),
+            ),
+            (
+                "stall_stop_micros".to_owned(),
+                self.stop_micros().to_string(),
+            ),
+            (
+                "background_errors".to_owned(),
+                self.background_errors().to_string(),
+            ),
+            (
+                "row_cache_hits".to_owned(),
+                self.row_cache_hits().to_string(),
+            ),
+            (
+                "row_cache_misses".to_owned(),
+                self.row_cache_misses().to_string(),
+            ),
+            ("blob_gcs".to_owned(), self.blob_gcs().to_string()),
+        ]
+    }
+}
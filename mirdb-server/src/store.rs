@@ -7,17 +7,21 @@ use std::path::Path;
 use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use bincode::deserialize;
+use bincode::serialize;
 use serde::{Deserialize, Serialize};
 
 use skip_list::SkipList;
 
 use crate::data_manager::DataManager;
 use crate::error::{MyResult, StatusCode};
+use crate::merge_operator::MergeOperator;
 use crate::options::Options;
 use crate::request::{GetterType, Request, SetterType};
 use crate::response::GetRespItem;
 use crate::response::Response;
 use crate::slice::Slice;
+use crate::utils::to_str;
 
 pub type StoreKey = Slice;
 
@@ -42,14 +46,132 @@ impl StorePayload {
     }
 
     pub fn is_expired(&self) -> bool {
+        self.is_expired_at(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+        )
+    }
+
+    /// When the payload expires, 0 if it never does.
+    pub(crate) fn expires_at(&self) -> u64 {
         if self.ttl == 0 {
-            return false;
+            return 0;
         }
         self.created_at + u64::from(self.ttl)
-            <= SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs()
+    }
+
+    fn is_expired_at(&self, now: u64) -> bool {
+        if self.ttl == 0 {
+            return false;
+        }
+        self.created_at + u64::from(self.ttl) <= now
+    }
+
+    /// Appends the data of `other`, which brings its flags and expiration.
+    fn append(&mut self, other: StorePayload) {
+        self.data.extend(&other.data);
+        self.take_meta(other);
+    }
+
+    fn prepend(&mut self, other: StorePayload) {
+        let mut data: Slice = other.data.to_owned();
+        data.extend(&self.data);
+        self.data = data;
+        self.take_meta(other);
+    }
+
+    fn take_meta(&mut self, other: StorePayload) {
+        self.ttl = other.ttl;
+        self.created_at = other.created_at;
+        self.bytes += other.bytes;
+        self.flags = other.flags;
+    }
+
+    /// Adds `delta` to a decimal number, wrapping around at 2^64. Returns
+    /// false if the data is not a number.
+    fn incr(&mut self, delta: u64) -> bool {
+        let n = match to_str(&self.data).parse::<u64>() {
+            Ok(n) => n,
+            Err(_) => return false,
+        };
+        let data = n.wrapping_add(delta).to_string();
+        self.bytes = data.len();
+        self.data = Slice::from(data);
+        true
+    }
+}
+
+/// The merge operands of the store, bincode encoded.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub enum StoreOperand {
+    Append(StorePayload),
+    Prepend(StorePayload),
+    Incr(u64),
+}
+
+/// Applies the `StoreOperand`s. Like the commands they come from, they don't
+/// store anything when the key has no value, or an expired one, and an
+/// increment leaves a value which is not a number alone.
+pub struct StoreMergeOperator;
+
+impl MergeOperator for StoreMergeOperator {
+    fn name(&self) -> &str {
+        "StoreMergeOperator"
+    }
+
+    fn full_merge(
+        &self,
+        _key: &[u8],
+        existing: Option<&[u8]>,
+        operands: &[&[u8]],
+    ) -> MyResult<Option<Vec<u8>>> {
+        let mut value: Option<StorePayload> = match existing {
+            Some(v) => Some(deserialize(v)?),
+            None => None,
+        };
+        for operand in operands {
+            let mut v = match value.take() {
+                Some(v) => v,
+                None => continue,
+            };
+            // the value as of the command, which set its own time
+            value = match deserialize(operand)? {
+                StoreOperand::Append(p) => {
+                    if v.is_expired_at(p.created_at) {
+                        None
+                    } else {
+                        v.append(p);
+                        Some(v)
+                    }
+                }
+                StoreOperand::Prepend(p) => {
+                    if v.is_expired_at(p.created_at) {
+                        None
+                    } else {
+                        v.prepend(p);
+                        Some(v)
+                    }
+                }
+                StoreOperand::Incr(delta) => {
+                    v.incr(delta);
+                    Some(v)
+                }
+            };
+        }
+        match value {
+            Some(v) => Ok(Some(serialize(&v)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Whether the key has a value which is not expired.
+fn is_live(v: Option<&StorePayload>) -> bool {
+    match v {
+        Some(p) => !p.is_expired(),
+        None => false,
     }
 }
 
@@ -69,17 +191,29 @@ impl Store {
         let dm = DataManager::new(opt.clone())?;
         #[cfg(not(test))]
         {
-            DataManager::background_thread(dm.clone());
+            DataManager::start_background_jobs(&dm);
         }
         Ok(Store { data: dm, opt })
     }
 
+    /// See `DataManager::shutdown`.
+    pub fn shutdown(&self) -> MyResult<()> {
+        self.data.shutdown()
+    }
+
     pub fn apply(&self, request: Request) -> MyResult<Response> {
         match request {
             Request::Getter { getter, keys } => {
                 let mut v = Vec::with_capacity(keys.len());
-                for key in keys {
-                    if let Some(p) = self.data.get(&key)? {
+                // a single key may be in the row cache, many are read as of
+                // the same point
+                let values = if keys.len() == 1 {
+                    vec![self.data.get(&keys[0])?]
+                } else {
+                    self.data.multi_get(None, &keys)?
+                };
+                for (key, p) in keys.into_iter().zip(values) {
+                    if let Some(p) = p {
                         if !p.is_expired() {
                             v.push(GetRespItem {
                                 key,
@@ -124,48 +258,26 @@ impl Store {
                         self.data.insert(key, sp)?;
                     }
                     SetterType::Add => {
-                        // Cannot use self.data.entry(key).or_insert(sp);
-                        // because of the NOT_STORED response
-                        if self.data.get(&key)?.is_none() {
-                            self.data.insert(key, sp)?;
-                        } else {
+                        if !self.data.insert_if(key, sp, |v| v.is_none())? {
                             return Ok(Response::NotStored);
                         }
                     }
                     SetterType::Replace => {
-                        // Cannot use self.data.entry(key).and_modify(|e| *e = sp);
-                        // because of the NOT_STORED response
-                        if self.data.get(&key)?.is_none() {
+                        if !self.data.insert_if(key, sp, |v| v.is_some())? {
                             return Ok(Response::NotStored);
-                        } else {
-                            self.data.insert(key, sp)?;
                         }
                     }
+                    // writes of the delta alone, applied by the reads and the
+                    // compactions, once the key is known to have a value
                     SetterType::Append => {
-                        if let Some(v) = self.data.get(&key)? {
-                            let mut c = v.clone();
-                            c.data.extend(sp.data);
-                            c.ttl = sp.ttl;
-                            c.created_at = sp.created_at;
-                            c.bytes += sp.bytes;
-                            c.flags = sp.flags;
-                            self.data.insert(key, c)?;
-                        } else {
+                        let operand = serialize(&StoreOperand::Append(sp))?;
+                        if !self.data.merge_if(key, Slice::from(operand), is_live)? {
                             return Ok(Response::NotStored);
                         }
                     }
                     SetterType::Prepend => {
-                        if let Some(v) = self.data.get(&key)? {
-                            let mut tmp: Slice = sp.data.to_owned();
-                            let mut c = v.clone();
-                            tmp.extend(&v.data);
-                            c.data = tmp;
-                            c.ttl = sp.ttl;
-                            c.created_at = sp.created_at;
-                            c.bytes += sp.bytes;
-                            c.flags = sp.flags;
-                            self.data.insert(key, c)?;
-                        } else {
+                        let operand = serialize(&StoreOperand::Prepend(sp))?;
+                        if !self.data.merge_if(key, Slice::from(operand), is_live)? {
                             return Ok(Response::NotStored);
                         }
                     }
@@ -176,7 +288,38 @@ impl Store {
                 Some(_) => Ok(Response::Deleted),
                 None => Ok(Response::NotFound),
             },
+            Request::RangeDeleter { start, end, .. } => {
+                if start > end {
+                    return Ok(Response::ClientError("bad range".to_owned()));
+                }
+                self.data.delete_range(&start[..], &end[..])?;
+                Ok(Response::Deleted)
+            }
+            Request::Incr { key, value, .. } => {
+                let operand = serialize(&StoreOperand::Incr(value))?;
+                // the operand is written only to a number
+                let mut found = false;
+                let is_number = |v: Option<&StorePayload>| match v {
+                    Some(p) if !p.is_expired() => {
+                        found = true;
+                        to_str(&p.data).parse::<u64>().is_ok()
+                    }
+                    _ => false,
+                };
+                let incremented =
+                    self.data
+                        .merge_and_get_if(key, Slice::from(operand), is_number)?;
+                let n = incremented.and_then(|p| to_str(&p.data).parse::<u64>().ok());
+                match n {
+                    Some(n) => Ok(Response::Incr(n)),
+                    None if found => Ok(Response::ClientError(
+                        "cannot increment or decrement non-numeric value".to_owned(),
+                    )),
+                    None => Ok(Response::NotFound),
+                }
+            }
             Request::Info => Ok(Response::Info(self.data.info())),
+            Request::Stats => Ok(Response::Stats(self.data.stats())),
             Request::Error => Ok(Response::Error),
             Request::MajorCompaction => {
                 self.data.major_compaction()?;
@@ -192,6 +335,8 @@ mod test {
     use std::fs::create_dir_all;
     use std::fs::remove_dir_all;
     use std::path::Path;
+    use std::sync::Barrier;
+    use std::thread;
 
     use rand::distributions::Alphanumeric;
     use rand::{thread_rng, Rng};
@@ -340,4 +485,156 @@ mod test {
         });
         assert_eq!(Ok(Response::ClientError("bad data chunk".to_owned())), r);
     }
+
+    fn setter(setter: SetterType, key: &Slice, payload: &str) -> Request {
+        Request::Setter {
+            setter,
+            key: key.clone(),
+            flags: 1,
+            ttl: 0,
+            bytes: payload.len(),
+            payload: Slice::from(payload),
+            no_reply: false,
+        }
+    }
+
+    fn incr(key: &Slice, value: u64) -> Request {
+        Request::Incr {
+            key: key.clone(),
+            value,
+            no_reply: false,
+        }
+    }
+
+    #[test]
+    fn test_delete_range() -> MyResult<()> {
+        let opt = get_test_opt();
+        let store = Store::new(opt)?;
+        for k in &["a", "b", "c"] {
+            store.apply(setter(SetterType::Set, &Slice::from(*k), "v"))?;
+        }
+        let delete_range = |start: &str, end: &str| Request::RangeDeleter {
+            start: Slice::from(start),
+            end: Slice::from(end),
+            no_reply: false,
+        };
+        assert_eq!(Response::Deleted, store.apply(delete_range("a", "c"))?);
+        assert_eq!(
+            Response::ClientError("bad range".to_owned()),
+            store.apply(delete_range("c", "a"))?
+        );
+        let r = store.apply(Request::Getter {
+            getter: GetterType::Get,
+            keys: vec![Slice::from("a"), Slice::from("b"), Slice::from("c")],
+        })?;
+        assert_eq!(
+            Response::Get(vec![GetRespItem::new(
+                Slice::from("c"),
+                Slice::from("v"),
+                1,
+                1
+            )]),
+            r
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge() -> MyResult<()> {
+        let opt = get_test_opt();
+        let store = Store::new(opt)?;
+        let a = Slice::from("a");
+        let b = Slice::from("b");
+
+        // nothing to append to
+        let r = store.apply(setter(SetterType::Append, &a, "x"))?;
+        assert_eq!(Response::NotStored, r);
+        let r = store.apply(setter(SetterType::Prepend, &a, "x"))?;
+        assert_eq!(Response::NotStored, r);
+        assert_eq!(None, store.data.get(&a)?);
+        assert_eq!(Response::NotFound, store.apply(incr(&a, 1))?);
+
+        store.apply(setter(SetterType::Set, &a, "b"))?;
+        store.apply(setter(SetterType::Append, &a, "c"))?;
+        store.apply(setter(SetterType::Prepend, &a, "a"))?;
+        let v = store.data.get(&a)?.unwrap();
+        assert_eq!(Slice::from("abc"), v.data);
+        assert_eq!(3, v.bytes);
+
+        store.apply(setter(SetterType::Set, &b, "9"))?;
+        assert_eq!(Response::Incr(10), store.apply(incr(&b, 1))?);
+        assert_eq!(Response::Incr(15), store.apply(incr(&b, 5))?);
+        assert_eq!(
+            Response::ClientError("cannot increment or decrement non-numeric value".to_owned()),
+            store.apply(incr(&a, 1))?
+        );
+        assert_eq!(Slice::from("abc"), store.data.get(&a)?.unwrap().data);
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_race() -> MyResult<()> {
+        let opt = get_test_opt();
+        let store = Arc::new(Store::new(opt)?);
+        for round in 0..20 {
+            let key = Slice::from(format!("key{}", round));
+            let barrier = Arc::new(Barrier::new(8));
+            let handles: Vec<_> = (0..8)
+                .map(|i| {
+                    let store = store.clone();
+                    let key = key.clone();
+                    let barrier = barrier.clone();
+                    thread::spawn(move || {
+                        barrier.wait();
+                        let r = store.apply(setter(SetterType::Add, &key, &i.to_string()));
+                        (i, r.unwrap())
+                    })
+                })
+                .collect();
+            let winners: Vec<_> = handles
+                .into_iter()
+                .map(|h| h.join().unwrap())
+                .filter(|(_, r)| *r == Response::Stored)
+                .map(|(i, _)| i)
+                .collect();
+            assert_eq!(1, winners.len());
+            assert_eq!(
+                Slice::from(winners[0].to_string()),
+                store.data.get(&key)?.unwrap().data
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_operator() -> MyResult<()> {
+        let payload = |data: &str, ttl: u32, created_at: u64| {
+            StorePayload::new(Slice::from(data), 0, ttl, data.len(), created_at)
+        };
+        let operand = |x: StoreOperand| serialize(&x).unwrap();
+        let merge = |existing: Option<StorePayload>, operands: &[Vec<u8>]| -> MyResult<_> {
+            let existing = existing.map(|x| serialize(&x).unwrap());
+            let operands: Vec<&[u8]> = operands.iter().map(|x| &x[..]).collect();
+            let merged = StoreMergeOperator.full_merge(
+                b"k",
+                existing.as_ref().map(|x| &x[..]),
+                &operands,
+            )?;
+            Ok(merged.map(|x| deserialize::<StorePayload>(&x).unwrap()))
+        };
+
+        let operands = vec![
+            operand(StoreOperand::Append(payload("2", 0, 20))),
+            operand(StoreOperand::Incr(3)),
+            operand(StoreOperand::Prepend(payload("1", 0, 30))),
+        ];
+        assert_eq!(None, merge(None, &operands)?);
+        assert_eq!(
+            Some(payload("115", 0, 30)),
+            merge(Some(payload("1", 0, 10)), &operands)?
+        );
+        // expired by the time of the append
+        assert_eq!(None, merge(Some(payload("1", 5, 10)), &operands)?);
+        Ok(())
+    }
 }
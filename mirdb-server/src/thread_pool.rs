@@ -86,7 +86,10 @@ impl Drop for ThreadPool {
             info!("Shutting down worker {}", worker.id);
 
             if let Some(thread) = worker.thread.take() {
-                thread.join().unwrap();
+                // the pool may be dropped by one of its own jobs
+                if thread.thread().id() != thread::current().id() {
+                    thread.join().unwrap();
+                }
             }
         }
     }
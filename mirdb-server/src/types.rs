@@ -6,7 +6,7 @@ pub trait Table<K, V> {
         K: Borrow<Q>,
         Q: Ord;
 
-    fn get_mut<Q: ?Sized>(&self, k: &Q) -> Option<&mut V>
+    fn get_mut<Q: ?Sized>(&mut self, k: &Q) -> Option<&mut V>
     where
         K: Borrow<Q>,
         Q: Ord;
@@ -1,4 +1,8 @@
 use std::borrow::Borrow;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::path::Path;
 use std::str;
 use std::sync::RwLock;
 use std::sync::RwLockReadGuard;
@@ -12,6 +16,20 @@ pub fn make_file_name(num: usize, ext: &str) -> String {
     format!("{:08}.{}", num, ext)
 }
 
+pub fn parse_file_number<T: AsRef<Path>>(path: T) -> Option<usize> {
+    path.as_ref()
+        .file_stem()
+        .and_then(|x| x.to_str())
+        .and_then(|x| x.parse::<usize>().ok())
+}
+
+/// The stripe of `stripes` which the key hashes to.
+pub fn key_stripe(k: &[u8], stripes: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    k.hash(&mut hasher);
+    hasher.finish() as usize % stripes
+}
+
 pub fn read_lock<T>(l: &RwLock<T>) -> RwLockReadGuard<T> {
     match l.read() {
         Ok(v) => v,
@@ -0,0 +1,469 @@
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fs::remove_file;
+use std::ops::Deref;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::Arc;
+
+use bincode::deserialize;
+use log::error;
+
+use sstable::Comparator;
+use sstable::TableIter;
+use sstable::TableReader;
+
+use crate::blob::BlobFile;
+use crate::blob::BlobIndex;
+use crate::error::err;
+use crate::error::MyResult;
+use crate::error::StatusCode;
+use crate::internal_key::make_lookup_key;
+use crate::internal_key::parse_internal_key;
+use crate::internal_key::user_key;
+use crate::internal_key::SeqNum;
+use crate::internal_key::ValueType;
+use crate::merge_operator::MergeContext;
+use crate::options::Options;
+use crate::range_del::RangeTombstone;
+use crate::range_del::RangeTombstones;
+use crate::slice::Slice;
+use crate::sstable_builder::BLOB_FILES_PROPERTY;
+
+/// An sstable referenced by one or more versions. The file is removed from
+/// the disk once it is marked obsolete and the last version using it is gone.
+pub struct TableFile {
+    reader_: TableReader,
+    path_: PathBuf,
+    obsolete_: AtomicBool,
+    range_tombstones_: Vec<RangeTombstone>,
+    blob_files_: Option<Vec<usize>>,
+}
+
+impl TableFile {
+    pub fn new<T: AsRef<Path>>(path: T, reader: TableReader) -> Self {
+        let range_tombstones_ = reader
+            .range_deletions()
+            .iter()
+            .filter_map(|(k, v)| RangeTombstone::decode(k, v).ok())
+            .collect();
+        let blob_files_ = reader
+            .property(BLOB_FILES_PROPERTY)
+            .and_then(|v| deserialize(v).ok());
+        TableFile {
+            reader_: reader,
+            path_: path.as_ref().to_path_buf(),
+            obsolete_: AtomicBool::new(false),
+            range_tombstones_,
+            blob_files_,
+        }
+    }
+
+    pub fn open(opt: &Options, file_name: &str) -> MyResult<Self> {
+        let path = Path::new(&opt.work_dir).join(file_name);
+        let reader = TableReader::new(&path, opt.get_table_opt())?;
+        Ok(TableFile::new(path, reader))
+    }
+
+    pub fn mark_obsolete(&self) {
+        self.obsolete_.store(true, Relaxed);
+    }
+
+    /// The range tombstones of its range deletion block.
+    pub fn range_tombstones(&self) -> &[RangeTombstone] {
+        &self.range_tombstones_
+    }
+
+    /// The blob files its blob indexes point into, `None` for an sstable
+    /// built before they were recorded.
+    pub fn blob_files(&self) -> Option<&[usize]> {
+        self.blob_files_.as_deref()
+    }
+}
+
+impl Deref for TableFile {
+    type Target = TableReader;
+
+    fn deref(&self) -> &TableReader {
+        &self.reader_
+    }
+}
+
+impl AsRef<TableReader> for TableFile {
+    fn as_ref(&self) -> &TableReader {
+        &self.reader_
+    }
+}
+
+impl Drop for TableFile {
+    fn drop(&mut self) {
+        if self.obsolete_.load(Relaxed) {
+            if let Err(e) = remove_file(&self.path_) {
+                error!("remove obsolete sstable {:?} error: {}", self.path_, e);
+            }
+        }
+    }
+}
+
+/// The entries of the user key of `lookup_key` in `file` which are not newer
+/// than the sequence number of `lookup_key`, from the newest down to the
+/// first one which is not a merge operand. They are read through `iter`, an
+/// iterator of `file`.
+fn find_entries(
+    file: &TableFile,
+    iter: &mut TableIter,
+    lookup_key: &[u8],
+) -> MyResult<Vec<(SeqNum, ValueType, Slice)>> {
+    let key = user_key(lookup_key);
+    let mut entries = vec![];
+    let mut lookup_key = Slice::from(lookup_key);
+    while let Some((k, v)) = file.find_in(iter, &lookup_key[..])? {
+        let parsed = match parse_internal_key(&k) {
+            Some(parsed) => parsed,
+            None => break,
+        };
+        entries.push((parsed.seq, parsed.value_type, Slice::from(v)));
+        if parsed.value_type != ValueType::Merge || parsed.seq == 0 {
+            break;
+        }
+        lookup_key = make_lookup_key(key, parsed.seq - 1);
+    }
+    Ok(entries)
+}
+
+fn sort_files(files: &mut Vec<Arc<TableFile>>, cmp: &dyn Comparator) {
+    files.sort_by(|a, b| cmp.compare(a.min_key(), b.min_key()))
+}
+
+/// An immutable snapshot of the sstables of every level, and of the blob
+/// files their values are in. Readers pin the current version with an
+/// `Arc`, compactions install a new one.
+///
+/// A range tombstone deletes the older entries whatever their level, so the
+/// ones of all the files are gathered.
+pub struct Version {
+    opt_: Options,
+    files_: Vec<Vec<Arc<TableFile>>>,
+    blob_files_: BTreeMap<usize, Arc<BlobFile>>,
+    range_dels_: RangeTombstones,
+}
+
+impl Version {
+    pub fn new(opt: Options) -> Self {
+        let files_ = (0..opt.max_level).map(|_| vec![]).collect();
+        let range_dels_ = RangeTombstones::new(opt.table_opt.comparator.clone());
+        Version {
+            opt_: opt,
+            files_,
+            blob_files_: BTreeMap::new(),
+            range_dels_,
+        }
+    }
+
+    /// Builds the next version, the files of level 0 are kept in the order
+    /// they were added, others are sorted by their min key.
+    pub fn apply(
+        &self,
+        deleted_files: &[(usize, String)],
+        new_files: Vec<(usize, Arc<TableFile>)>,
+    ) -> Version {
+        let cmp = self.opt_.internal_comparator();
+        let mut files_ = self.files_.clone();
+        for (level, file_name) in deleted_files {
+            files_[*level].retain(|x| x.file_name() != file_name);
+        }
+        for (level, file) in new_files {
+            files_[level].push(file);
+            if level != 0 {
+                sort_files(&mut files_[level], &cmp);
+            }
+        }
+        let mut range_dels_ = RangeTombstones::new(self.opt_.table_opt.comparator.clone());
+        for file in files_.iter().flatten() {
+            range_dels_.extend(file.range_tombstones());
+        }
+        Version {
+            opt_: self.opt_.clone(),
+            files_,
+            blob_files_: self.blob_files_.clone(),
+            range_dels_,
+        }
+    }
+
+    /// Removes and adds blob files, the way `apply` does sstables.
+    pub fn apply_blob_files(
+        mut self,
+        deleted_blob_files: &[usize],
+        new_blob_files: Vec<Arc<BlobFile>>,
+    ) -> Version {
+        for number in deleted_blob_files {
+            self.blob_files_.remove(number);
+        }
+        for blob in new_blob_files {
+            self.blob_files_.insert(blob.number(), blob);
+        }
+        self
+    }
+
+    pub fn blob_files(&self) -> impl Iterator<Item = &Arc<BlobFile>> {
+        self.blob_files_.values()
+    }
+
+    pub fn blob_file(&self, number: usize) -> Option<&Arc<BlobFile>> {
+        self.blob_files_.get(&number)
+    }
+
+    pub fn read_blob(&self, index: &BlobIndex) -> MyResult<Slice> {
+        match self.blob_file(index.file_number) {
+            Some(blob) => blob.read(index),
+            None => err(StatusCode::BlobError, "missing blob file"),
+        }
+    }
+
+    /// Reads the value of `ctx` if it is in a blob file, returns its index.
+    pub fn resolve_blob(&self, ctx: &mut MergeContext) -> MyResult<Option<BlobIndex>> {
+        ctx.resolve_blob(|index| self.read_blob(index))
+    }
+
+    /// The blob file with the largest share of stale bytes, if it is worth
+    /// a garbage collection.
+    pub fn pick_blob_file_for_gc(&self) -> Option<&Arc<BlobFile>> {
+        self.blob_files()
+            .filter(|x| x.stale_ratio() >= self.opt_.blob_gc_ratio)
+            .max_by(|a, b| a.stale_ratio().partial_cmp(&b.stale_ratio()).unwrap())
+    }
+
+    pub fn range_deletions(&self) -> &RangeTombstones {
+        &self.range_dels_
+    }
+
+    /// See `RangeTombstones::covering_seq`.
+    pub fn covering_seq(&self, key: &[u8], seq: SeqNum) -> Option<SeqNum> {
+        self.range_dels_.covering_seq(key, seq)
+    }
+
+    pub fn get_files(&self, level: usize) -> &Vec<Arc<TableFile>> {
+        assert!(level < self.opt_.max_level);
+        &self.files_[level]
+    }
+
+    /// Returns the files which may hold entries of the user key `key`.
+    pub fn search_files<K>(&self, level: usize, key: &K) -> Vec<&Arc<TableFile>>
+    where
+        K: ?Sized + Borrow<[u8]>,
+    {
+        let mut res = vec![];
+
+        if self.files_.len() <= level {
+            return res;
+        }
+
+        let files = self.get_files(level);
+        let key = key.borrow();
+
+        if level == 0 {
+            for file in files.iter().rev() {
+                if self.may_contain(file, key) {
+                    res.push(file);
+                }
+            }
+        } else if !files.is_empty() {
+            let mut left = 0;
+            let mut right = files.len() - 1;
+
+            while left < right {
+                let middle = (left + right + 1) / 2;
+                if self.compare_user_keys(user_key(files[middle].min_key()), key) == Ordering::Less
+                {
+                    left = middle;
+                } else {
+                    right = middle - 1;
+                }
+            }
+
+            assert_eq!(left, right);
+
+            for file in files.iter().skip(left) {
+                if self.may_contain(file, key) {
+                    res.push(file);
+                    continue;
+                }
+                if self.compare_user_keys(user_key(file.min_key()), key) == Ordering::Greater {
+                    break;
+                }
+            }
+        }
+
+        res
+    }
+
+    /// Feeds `ctx` the entries of the user key of `lookup_key` which are not
+    /// newer than the sequence number of `lookup_key`, from the newest. The
+    /// files of level 0 overlap, their entries are ordered by sequence number
+    /// whatever the order of the files, deeper levels only hold older entries.
+    /// A value in a blob file is read.
+    pub fn collect(&self, lookup_key: &[u8], ctx: &mut MergeContext) -> MyResult<()> {
+        self.collect_entries(lookup_key, ctx)?;
+        self.resolve_blob(ctx)?;
+        Ok(())
+    }
+
+    fn collect_entries(&self, lookup_key: &[u8], ctx: &mut MergeContext) -> MyResult<()> {
+        let key = user_key(lookup_key);
+
+        let mut entries = vec![];
+        for file in self.search_files(0, key) {
+            entries.extend(find_entries(file, &mut file.iter(), lookup_key)?);
+        }
+        entries.sort_by(|a, b| b.0.cmp(&a.0));
+        for (seq, value_type, v) in entries {
+            if ctx.is_done() {
+                return Ok(());
+            }
+            ctx.add(seq, value_type, v);
+        }
+
+        for i in 1..self.opt_.max_level {
+            for file in self.search_files(i, key) {
+                for (seq, value_type, v) in find_entries(file, &mut file.iter(), lookup_key)? {
+                    if ctx.is_done() {
+                        return Ok(());
+                    }
+                    ctx.add(seq, value_type, v);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// `collect` for many keys at once, `lookup_keys` are sorted by their
+    /// user keys and each goes with the context of the same index. Every
+    /// level is walked once, and a file keeps the block it read last for the
+    /// next key.
+    pub fn multi_collect(&self, lookup_keys: &[Slice], ctxs: &mut [MergeContext]) -> MyResult<()> {
+        assert_eq!(lookup_keys.len(), ctxs.len());
+
+        let files = self.get_files(0);
+        let mut iters: Vec<TableIter> = files.iter().map(|x| x.iter()).collect();
+        for (lookup_key, ctx) in lookup_keys.iter().zip(ctxs.iter_mut()) {
+            let key = user_key(&lookup_key[..]);
+            let mut entries = vec![];
+            for (file, iter) in files.iter().zip(iters.iter_mut()) {
+                if self.may_contain(file, key) {
+                    entries.extend(find_entries(file, iter, &lookup_key[..])?);
+                }
+            }
+            entries.sort_by(|a, b| b.0.cmp(&a.0));
+            for (seq, value_type, v) in entries {
+                if ctx.is_done() {
+                    break;
+                }
+                ctx.add(seq, value_type, v);
+            }
+        }
+
+        for i in 1..self.opt_.max_level {
+            let files = self.get_files(i);
+            let mut iters: Vec<Option<TableIter>> = files.iter().map(|_| None).collect();
+            // the files before it end before the keys still to look up
+            let mut start = 0;
+            for (lookup_key, ctx) in lookup_keys.iter().zip(ctxs.iter_mut()) {
+                let key = user_key(&lookup_key[..]);
+                while start < files.len()
+                    && self.compare_user_keys(user_key(files[start].max_key()), key)
+                        == Ordering::Less
+                {
+                    iters[start] = None;
+                    start += 1;
+                }
+                for (file, iter) in files.iter().zip(iters.iter_mut()).skip(start) {
+                    if ctx.is_done()
+                        || self.compare_user_keys(user_key(file.min_key()), key)
+                            == Ordering::Greater
+                    {
+                        break;
+                    }
+                    let iter = iter.get_or_insert_with(|| file.iter());
+                    for (seq, value_type, v) in find_entries(file, iter, &lookup_key[..])? {
+                        if ctx.is_done() {
+                            break;
+                        }
+                        ctx.add(seq, value_type, v);
+                    }
+                }
+            }
+        }
+        for ctx in ctxs.iter_mut() {
+            self.resolve_blob(ctx)?;
+        }
+        Ok(())
+    }
+
+    /// Orders the user keys with the user comparator.
+    fn compare_user_keys(&self, a: &[u8], b: &[u8]) -> Ordering {
+        self.opt_.table_opt.comparator.compare(a, b)
+    }
+
+    /// Whether the user key `key` is in the key range of `file`.
+    fn may_contain(&self, file: &TableFile, key: &[u8]) -> bool {
+        self.compare_user_keys(user_key(file.min_key()), key) != Ordering::Greater
+            && self.compare_user_keys(user_key(file.max_key()), key) != Ordering::Less
+    }
+
+    /// Whether no level below `level` may hold entries of the user key `key`,
+    /// a compaction into `level` can then drop the tombstones of the key.
+    pub fn is_base_level_for_key(&self, level: usize, key: &[u8]) -> bool {
+        (level + 1..self.opt_.max_level).all(|i| self.search_files(i, key).is_empty())
+    }
+
+    /// Whether no sstable but `inputs` may hold entries `tombstone` deletes,
+    /// a compaction of `inputs` can then drop it. A shallower level may hold
+    /// older entries than a range tombstone, unlike a point one, only level 0
+    /// is newer than every tombstone out of it.
+    pub fn can_drop_range_deletion(
+        &self,
+        tombstone: &RangeTombstone,
+        inputs: &[&Arc<TableFile>],
+    ) -> bool {
+        (1..self.opt_.max_level).all(|i| {
+            self.get_files(i).iter().all(|file| {
+                inputs.iter().any(|x| Arc::ptr_eq(x, file))
+                    || !tombstone.overlaps(
+                        user_key(file.min_key()),
+                        user_key(file.max_key()),
+                        self.opt_.table_opt.comparator.as_ref(),
+                    )
+            })
+        })
+    }
+
+    pub fn compute_compaction_levels(&self) -> Vec<usize> {
+        let mut scores = Vec::with_capacity(self.opt_.max_level);
+        for i in 0..self.opt_.max_level {
+            let files = self.get_files(i);
+            let score = if i == 0 {
+                files.len() as f64 / self.opt_.l0_compaction_trigger as f64
+            } else {
+                files.iter().map(|x| x.size()).sum::<usize>() as f64 / self.max_bytes_for_level(i)
+            };
+            if score >= 1. {
+                scores.push((i, score))
+            }
+        }
+        scores.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        scores.iter().map(|x| x.0).collect()
+    }
+
+    fn max_bytes_for_level(&self, level: usize) -> f64 {
+        let mut level = level;
+        let mut result = 10. * 1_048_576.;
+        while level > 1 {
+            result *= 10.;
+            level -= 1;
+        }
+        result
+    }
+}
@@ -0,0 +1,285 @@
+use std::fmt;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::Acquire;
+use std::sync::atomic::Ordering::Release;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::RwLock;
+
+use crate::blob::BlobFile;
+use crate::error::MyResult;
+use crate::internal_key::SeqNum;
+use crate::manifest::ManifestBuilder;
+use crate::manifest::VersionEdit;
+use crate::options::Options;
+use crate::utils::read_lock;
+use crate::utils::write_lock;
+use crate::version::TableFile;
+use crate::version::Version;
+
+pub struct VersionSet {
+    opt_: Options,
+    current_: RwLock<Arc<Version>>,
+    manifest_builder_: Mutex<ManifestBuilder>,
+    last_sequence_: AtomicU64,
+}
+
+impl VersionSet {
+    pub fn new(opt: Options) -> MyResult<Self> {
+        let manifest_builder = ManifestBuilder::new(opt.clone())?;
+        let mut new_files = vec![];
+        for level in 0..opt.max_level {
+            if let Some(fms) = manifest_builder.file_metas(level) {
+                for fm in fms {
+                    let file = TableFile::open(&opt, &fm.file_name)?;
+                    new_files.push((level, Arc::new(file)));
+                }
+            }
+        }
+        let mut blob_files = vec![];
+        for meta in manifest_builder.blob_metas() {
+            let blob = BlobFile::open(&opt, meta.number, meta.stale_bytes)?;
+            blob_files.push(Arc::new(blob));
+        }
+        let version = Version::new(opt.clone())
+            .apply(&[], new_files)
+            .apply_blob_files(&[], blob_files);
+        Ok(VersionSet {
+            opt_: opt,
+            current_: RwLock::new(Arc::new(version)),
+            last_sequence_: AtomicU64::new(manifest_builder.last_sequence()),
+            manifest_builder_: Mutex::new(manifest_builder),
+        })
+    }
+
+    /// Pins the current version, it stays readable however many versions
+    /// are installed after it.
+    pub fn current(&self) -> Arc<Version> {
+        read_lock(&self.current_).clone()
+    }
+
+    /// Records `edit` in the manifest and installs the version it leads to.
+    /// `new_files` must be the files added by `edit`, in the same order.
+    /// Deleted files are removed from the disk once no version uses them.
+    pub fn log_and_apply(&self, edit: VersionEdit, new_files: Vec<TableFile>) -> MyResult<()> {
+        self.log_and_apply_with_blobs(edit, new_files, vec![])
+    }
+
+    /// `log_and_apply` for an edit which adds blob files too, `new_blob_files`
+    /// go in the same order as in `edit`. The stale bytes of the edit are
+    /// added to the blob files, which are removed like the sstables.
+    pub fn log_and_apply_with_blobs(
+        &self,
+        mut edit: VersionEdit,
+        new_files: Vec<TableFile>,
+        new_blob_files: Vec<BlobFile>,
+    ) -> MyResult<()> {
+        assert_eq!(edit.new_files.len(), new_files.len());
+        assert_eq!(edit.new_blob_files.len(), new_blob_files.len());
+
+        let mut manifest_builder = self.manifest_builder_.lock().unwrap();
+
+        if edit.last_sequence.is_none() {
+            edit.last_sequence = Some(self.last_sequence());
+        }
+
+        let new_files: Vec<_> = edit
+            .new_files
+            .iter()
+            .zip(new_files.into_iter())
+            .map(|((level, fm), file)| {
+                assert_eq!(&fm.file_name, file.file_name());
+                (*level, Arc::new(file))
+            })
+            .collect();
+
+        let new_blob_files: Vec<_> = edit
+            .new_blob_files
+            .iter()
+            .zip(new_blob_files)
+            .map(|(number, blob)| {
+                assert_eq!(*number, blob.number());
+                Arc::new(blob)
+            })
+            .collect();
+
+        let current = self.current();
+        let version = current
+            .apply(&edit.deleted_files, new_files)
+            .apply_blob_files(&edit.deleted_blob_files, new_blob_files);
+
+        let deleted_files = edit.deleted_files.clone();
+        let deleted_blob_files = edit.deleted_blob_files.clone();
+        let blob_stale_bytes = edit.blob_stale_bytes.clone();
+        manifest_builder.log_and_apply(edit)?;
+
+        for (number, bytes) in blob_stale_bytes {
+            if let Some(blob) = current.blob_file(number) {
+                blob.add_stale(bytes);
+            }
+        }
+        for number in deleted_blob_files {
+            if let Some(blob) = current.blob_file(number) {
+                blob.mark_obsolete();
+            }
+        }
+
+        for (level, file_name) in &deleted_files {
+            for file in current.get_files(*level) {
+                if file.file_name() == file_name {
+                    file.mark_obsolete();
+                }
+            }
+        }
+
+        *write_lock(&self.current_) = Arc::new(version);
+
+        Ok(())
+    }
+
+    /// The sequence number of the last write, everything up to it is visible
+    /// to readers.
+    pub fn last_sequence(&self) -> SeqNum {
+        self.last_sequence_.load(Acquire)
+    }
+
+    pub fn set_last_sequence(&self, seq: SeqNum) {
+        assert!(seq >= self.last_sequence());
+        self.last_sequence_.store(seq, Release);
+    }
+
+    /// Raises the last sequence to one found while replaying the wal.
+    pub fn recover_last_sequence(&self, seq: SeqNum) {
+        self.last_sequence_.fetch_max(seq, Release);
+    }
+
+    pub fn log_number(&self) -> usize {
+        self.manifest_builder_.lock().unwrap().log_number()
+    }
+
+    pub fn format_version(&self) -> u32 {
+        self.manifest_builder_.lock().unwrap().format_version()
+    }
+
+    pub fn file_number_allocator(&self) -> Arc<AtomicUsize> {
+        self.manifest_builder_
+            .lock()
+            .unwrap()
+            .file_number_allocator()
+    }
+}
+
+impl fmt::Display for VersionSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.manifest_builder_.lock().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use sstable::TableBuilder;
+
+    use crate::blob::blob_file_name;
+    use crate::blob::BlobWriter;
+    use crate::slice::Slice;
+    use crate::test_utils::get_test_opt;
+    use crate::utils::make_file_name;
+
+    use super::*;
+
+    fn build_table(opt: &Options, num: usize, keys: &[&str]) -> MyResult<TableFile> {
+        let path = Path::new(&opt.work_dir).join(make_file_name(num, "sst"));
+        let mut tb = TableBuilder::new(&path, opt.get_table_opt())?;
+        for k in keys {
+            tb.add(k.as_bytes(), k.as_bytes())?;
+        }
+        tb.flush()?;
+        TableFile::open(opt, &make_file_name(num, "sst"))
+    }
+
+    #[test]
+    fn test_obsolete_files() -> MyResult<()> {
+        let opt = get_test_opt();
+        let vs = VersionSet::new(opt.clone())?;
+
+        let t0 = build_table(&opt, 100, &["a", "b"])?;
+        let t1 = build_table(&opt, 101, &["c", "d"])?;
+        let mut edit = VersionEdit::default();
+        edit.add_file(0, &t0);
+        edit.add_file(0, &t1);
+        vs.log_and_apply(edit, vec![t0, t1])?;
+
+        let pinned = vs.current();
+        assert_eq!(2, pinned.get_files(0).len());
+
+        let t2 = build_table(&opt, 102, &["a", "b", "c", "d"])?;
+        let mut edit = VersionEdit::default();
+        for file in pinned.get_files(0) {
+            edit.delete_file(0, file);
+        }
+        edit.add_file(1, &t2);
+        vs.log_and_apply(edit, vec![t2])?;
+
+        assert_eq!(0, vs.current().get_files(0).len());
+        assert_eq!(1, vs.current().get_files(1).len());
+
+        // still readable through the pinned version
+        let path = Path::new(&opt.work_dir).join(make_file_name(100, "sst"));
+        assert!(path.exists());
+        assert_eq!(Some(b"a".to_vec()), pinned.get_files(0)[0].get(&b"a"[..])?);
+
+        drop(pinned);
+        assert!(!path.exists());
+
+        // reopen from the manifest
+        drop(vs);
+        let vs = VersionSet::new(opt.clone())?;
+        assert_eq!(0, vs.current().get_files(0).len());
+        assert_eq!(1, vs.current().get_files(1).len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_blob_files() -> MyResult<()> {
+        let opt = get_test_opt();
+        let vs = VersionSet::new(opt.clone())?;
+
+        let mut writer = BlobWriter::new(&opt, 200)?;
+        let a = writer.add(b"a", 0)?;
+        let b = writer.add(b"b", 0)?;
+        let blob = writer.finish()?;
+        let mut edit = VersionEdit::default();
+        edit.add_blob_file(&blob);
+        vs.log_and_apply_with_blobs(edit, vec![], vec![blob])?;
+
+        let mut edit = VersionEdit::default();
+        edit.add_blob_stale(200, a.record_size());
+        vs.log_and_apply(edit, vec![])?;
+        let pinned = vs.current();
+        assert_eq!(a.record_size(), pinned.blob_file(200).unwrap().stale());
+        assert_eq!(Slice::from("b"), pinned.read_blob(&b)?);
+
+        // reopen from the manifest
+        drop(pinned);
+        drop(vs);
+        let vs = VersionSet::new(opt.clone())?;
+        let pinned = vs.current();
+        assert_eq!(a.record_size(), pinned.blob_file(200).unwrap().stale());
+
+        let mut edit = VersionEdit::default();
+        edit.delete_blob_file(pinned.blob_file(200).unwrap());
+        vs.log_and_apply(edit, vec![])?;
+        assert!(vs.current().blob_file(200).is_none());
+        assert!(vs.current().read_blob(&b).is_err());
+
+        // still readable through the pinned version
+        let path = Path::new(&opt.work_dir).join(blob_file_name(200));
+        assert_eq!(Slice::from("b"), pinned.read_blob(&b)?);
+        drop(pinned);
+        assert!(!path.exists());
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
 use std::borrow::Borrow;
+use std::cmp::max;
 use std::cmp::min;
 use std::collections::linked_list::Iter as LinkedListIter;
 use std::collections::LinkedList;
@@ -17,6 +18,9 @@ use std::ops::DerefMut;
 use std::path::Path;
 use std::path::PathBuf;
 use std::ptr;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::Arc;
 
 use glob::glob;
 use integer_encoding::FixedInt;
@@ -27,15 +31,18 @@ use snap::Encoder;
 use skip_list::SkipList;
 use sstable::RandomAccess;
 use sstable::TableBuilder;
-use sstable::TableReader;
 
 use crate::error::err;
 use crate::error::MyResult;
 use crate::error::StatusCode;
+use crate::internal_key::parse_internal_key;
+use crate::internal_key::SeqNum;
 use crate::options::Options;
 use crate::slice::Slice;
 use crate::sstable_builder::skiplist_to_sstable;
+use crate::sstable_builder::BuiltTable;
 use crate::utils::make_file_name;
+use crate::utils::parse_file_number;
 
 fn padding(len: usize) -> usize {
     4usize.wrapping_sub(len) & 7
@@ -54,6 +61,7 @@ pub struct WALSeg {
     file: File,
     size_: usize,
     path: PathBuf,
+    file_number_: usize,
 }
 
 impl WALSeg {
@@ -64,13 +72,23 @@ impl WALSeg {
             .create(true)
             .open(path.as_ref())?;
 
+        let file_number_ = match parse_file_number(path.as_ref()) {
+            Some(n) => n,
+            None => return err(StatusCode::WALError, "invalid wal seg file name"),
+        };
+
         Ok(WALSeg {
             file,
             size_: 0,
             path: path.as_ref().to_path_buf(),
+            file_number_,
         })
     }
 
+    pub fn file_number(&self) -> usize {
+        self.file_number_
+    }
+
     pub fn iter(&self) -> MyResult<WALSegIter> {
         WALSegIter::new(&self.path)
     }
@@ -131,21 +149,34 @@ impl WALSeg {
         Ok(())
     }
 
-    pub fn to_skiplist(&self, opt: &Options) -> MyResult<SkipList<Slice, Slice>> {
-        let mut map = SkipList::new(opt.mem_table_max_height);
+    /// Loads the records into a skiplist, along with the greatest sequence
+    /// number among them.
+    pub fn to_skiplist(&self, opt: &Options) -> MyResult<(SkipList<Slice, Slice>, SeqNum)> {
+        let mut map = SkipList::new_with_height_generator(
+            opt.mem_table_max_height,
+            opt.mem_table_height_generator(),
+        );
+        let mut last_sequence = 0;
         for (k, v) in self.iter()? {
+            if let Some(parsed) = parse_internal_key(&k[..]) {
+                last_sequence = max(last_sequence, parsed.seq);
+            }
             map.insert(k, v);
         }
-        Ok(map)
+        Ok((map, last_sequence))
     }
 
     pub fn build_sstable(
         &self,
         opt: &Options,
         path: &Path,
-    ) -> MyResult<Option<(String, TableReader)>> {
-        let map = self.to_skiplist(opt)?;
-        skiplist_to_sstable(&map, opt, path)
+    ) -> MyResult<(Option<BuiltTable>, SeqNum)> {
+        let (map, last_sequence) = self.to_skiplist(opt)?;
+        // replayed at startup, no snapshot sees the entries
+        Ok((
+            skiplist_to_sstable(map.iter(), opt, path, 0)?,
+            last_sequence,
+        ))
     }
 }
 
@@ -212,37 +243,48 @@ impl Iterator for WALSegIter {
 pub struct WAL {
     opt: Options,
     pub segs: LinkedList<WALSeg>,
-    current_file_num: usize,
+    next_file_number: Arc<AtomicUsize>,
 }
 
 impl WAL {
-    pub fn new(opt: Options) -> MyResult<Self> {
+    /// Opens the wal segs in the work dir. Segs numbered below `log_number`
+    /// have already been flushed to sstables and are removed, new segs take
+    /// their numbers from `next_file_number`, which is shared with sstables.
+    pub fn new(
+        opt: Options,
+        log_number: usize,
+        next_file_number: Arc<AtomicUsize>,
+    ) -> MyResult<Self> {
         let path = Path::new(&opt.work_dir);
         let mut paths = vec![];
         for entry in glob(path.join("*.wal").to_str().expect("path to str"))? {
             if let Ok(path) = entry {
-                paths.push(path);
+                if let Some(n) = parse_file_number(&path) {
+                    paths.push((n, path));
+                }
             }
         }
         paths.sort();
-        let segs = paths
-            .iter()
-            .map(|p| {
-                let seg = WALSeg::new(&p.as_path(), opt.mem_table_max_size).expect("new wal seg");
-                if seg.file.metadata().unwrap().len() == 0 {
-                    remove_file(&seg.path).unwrap();
-                    None
-                } else {
-                    Some(seg)
-                }
-            })
-            .filter(Option::is_some)
-            .map(Option::unwrap)
-            .collect();
+        let mut segs = LinkedList::new();
+        for (n, p) in &paths {
+            if *n < log_number {
+                remove_file(p)?;
+                continue;
+            }
+            let seg = WALSeg::new(p.as_path(), opt.mem_table_max_size)?;
+            if seg.file.metadata()?.len() == 0 {
+                seg.delete()?;
+            } else {
+                segs.push_back(seg);
+            }
+        }
+        if let Some((n, _)) = paths.last() {
+            next_file_number.fetch_max(n + 1, Relaxed);
+        }
         Ok(WAL {
             opt,
             segs,
-            current_file_num: 0,
+            next_file_number,
         })
     }
 
@@ -275,7 +317,7 @@ impl WAL {
     }
 
     pub fn new_seg(&mut self) -> MyResult<()> {
-        let file_num = self.new_file_num();
+        let file_num = self.next_file_number.fetch_add(1, Relaxed);
         let file_name = make_file_name(file_num, "wal");
         let path = Path::new(&self.opt.work_dir);
         let path = path.join(file_name);
@@ -291,10 +333,13 @@ impl WAL {
         Ok(0)
     }
 
-    fn new_file_num(&mut self) -> usize {
-        let n = self.current_file_num;
-        self.current_file_num += 1;
-        n
+    /// The log number to record once the oldest seg has been flushed, every
+    /// seg numbered from it on is still needed for recovery.
+    pub fn next_log_number(&self) -> usize {
+        match self.segs.iter().nth(1) {
+            Some(seg) => seg.file_number(),
+            None => self.next_file_number.load(Relaxed),
+        }
     }
 
     pub fn iter(&self) -> MyResult<WALIter> {
@@ -343,7 +388,7 @@ mod test {
     #[test]
     fn test_wal_seg() -> MyResult<()> {
         use std::time;
-        let p = Path::new("/tmp/wal");
+        let p = Path::new("/tmp/00000000.wal");
         if p.exists() {
             remove_file(p)?;
         }
@@ -376,7 +421,8 @@ mod test {
     #[test]
     fn test_wal() -> MyResult<()> {
         let opt = get_test_opt();
-        let mut wal = WAL::new(opt.clone())?;
+        let next_file_number = Arc::new(AtomicUsize::new(0));
+        let mut wal = WAL::new(opt.clone(), 0, next_file_number.clone())?;
         let mut kvs = Vec::with_capacity(3);
         kvs.push((
             b"a".to_vec(),
@@ -388,7 +434,7 @@ mod test {
             wal.new_seg()?;
             wal.append(&Slice::from(k.clone()), &Slice::from(v.clone()))?;
         }
-        let mut wal = WAL::new(opt.clone())?;
+        let mut wal = WAL::new(opt.clone(), 0, next_file_number.clone())?;
         let mut iter = wal.iter()?;
         for (k, v) in &kvs {
             assert_eq!(
@@ -410,7 +456,7 @@ mod test {
         }
         assert_eq!(None, iter.next());
         wal.truncate(1)?;
-        let wal = WAL::new(opt.clone())?;
+        let wal = WAL::new(opt.clone(), 0, next_file_number.clone())?;
         let mut iter = wal.iter()?;
         for (i, (k, v)) in kvs.iter().enumerate() {
             if i <= 1 {
@@ -424,4 +470,33 @@ mod test {
         assert_eq!(None, iter.next());
         Ok(())
     }
+
+    #[test]
+    fn test_log_number() -> MyResult<()> {
+        let opt = get_test_opt();
+        let next_file_number = Arc::new(AtomicUsize::new(5));
+        let mut wal = WAL::new(opt.clone(), 0, next_file_number.clone())?;
+        for k in &[b"a", b"b", b"c"] {
+            wal.new_seg()?;
+            wal.append(&Slice::from(&k[..]), &Slice::from(&k[..]))?;
+        }
+        let numbers: Vec<_> = wal.segs.iter().map(WALSeg::file_number).collect();
+        assert_eq!(vec![5, 6, 7], numbers);
+        assert_eq!(6, wal.next_log_number());
+
+        // segs below the log number are obsolete and removed on open
+        let wal = WAL::new(opt.clone(), 6, Arc::new(AtomicUsize::new(0)))?;
+        let numbers: Vec<_> = wal.segs.iter().map(WALSeg::file_number).collect();
+        assert_eq!(vec![6, 7], numbers);
+        let mut iter = wal.iter()?;
+        assert_eq!(Some((Slice::from("b"), Slice::from("b"))), iter.next());
+
+        // new segs never collide with the ones left behind
+        let next_file_number = Arc::new(AtomicUsize::new(0));
+        let mut wal = WAL::new(opt.clone(), 6, next_file_number.clone())?;
+        assert_eq!(8, next_file_number.load(Relaxed));
+        wal.new_seg()?;
+        assert_eq!(Some(8), wal.segs.back().map(WALSeg::file_number));
+        Ok(())
+    }
 }
@@ -2,10 +2,78 @@
 
 extern crate test;
 
+use skip_list::GenHeight;
 use skip_list::SkipList;
 use test::black_box;
 use test::Bencher;
 
+const SEED: u64 = 42;
+
+fn new_list(p: f64) -> SkipList<u64, u64> {
+    SkipList::new_with_height_generator(32, Box::new(GenHeight::with_seed(p, SEED)))
+}
+
+fn bench_insert(b: &mut Bencher, p: f64) {
+    b.iter(|| {
+        let mut map = new_list(p);
+
+        let mut num = 0 as u64;
+        for _ in 0..1_000 {
+            num = num.wrapping_mul(17).wrapping_add(255);
+            map.insert(num, !num);
+        }
+    });
+}
+
+fn bench_lookup(b: &mut Bencher, p: f64) {
+    let mut map = new_list(p);
+
+    let mut num = 0 as u64;
+    for _ in 0..1_000 {
+        num = num.wrapping_mul(17).wrapping_add(255);
+        map.insert(num, !num);
+    }
+
+    b.iter(|| {
+        let mut num = 0 as u64;
+
+        for _ in 0..1_000 {
+            num = num.wrapping_mul(17).wrapping_add(255);
+            black_box(map.get(&num));
+        }
+    });
+}
+
+#[bench]
+fn insert_p_half(b: &mut Bencher) {
+    bench_insert(b, 0.5);
+}
+
+#[bench]
+fn insert_p_quarter(b: &mut Bencher) {
+    bench_insert(b, 0.25);
+}
+
+#[bench]
+fn insert_p_eighth(b: &mut Bencher) {
+    bench_insert(b, 0.125);
+}
+
+#[bench]
+fn lookup_p_half(b: &mut Bencher) {
+    bench_lookup(b, 0.5);
+}
+
+#[bench]
+fn lookup_p_quarter(b: &mut Bencher) {
+    bench_lookup(b, 0.25);
+}
+
+#[bench]
+fn lookup_p_eighth(b: &mut Bencher) {
+    bench_lookup(b, 0.125);
+}
+
 #[bench]
 fn insert(b: &mut Bencher) {
     b.iter(|| {
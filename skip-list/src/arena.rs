@@ -0,0 +1,274 @@
+use std::alloc::{alloc, dealloc, handle_alloc_error, Layout};
+use std::cmp;
+use std::ptr;
+use std::slice;
+use std::sync::atomic::AtomicPtr;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use std::sync::Mutex;
+
+const BLOCK_ALIGN: usize = 8;
+
+/// A key or a value which a list in an arena keeps there whole: the bytes
+/// it points to are copied into the arena, and the one the list keeps
+/// points to the copy.
+///
+/// # Safety
+///
+/// What `in_arena` returns must free nothing when dropped, and neither it
+/// nor its clones may be used once the list is dropped or cleared.
+pub unsafe trait ArenaCopy: Sized {
+    /// The bytes it points to, none for a plain value.
+    fn arena_bytes(&self) -> &[u8];
+
+    /// The same key or value, pointing to `bytes`, the copy in the arena.
+    ///
+    /// # Safety
+    ///
+    /// `bytes` only live as long as the arena, see the trait.
+    unsafe fn in_arena(&self, bytes: &'static [u8]) -> Self;
+}
+
+macro_rules! impl_arena_copy_for_int {
+    ($($t:ty),*) => {
+        $(
+            unsafe impl ArenaCopy for $t {
+                fn arena_bytes(&self) -> &[u8] {
+                    &[]
+                }
+
+                unsafe fn in_arena(&self, _: &'static [u8]) -> Self {
+                    *self
+                }
+            }
+        )*
+    };
+}
+
+impl_arena_copy_for_int!(i32, i64, u32, u64, usize);
+
+/// Hands out memory from large blocks, by bumping an offset with
+/// compare-and-swap, so the threads allocating at once only lock to add a
+/// block. Nothing is freed on its own, all the blocks go at once when the
+/// arena is dropped or reset.
+pub struct Arena {
+    block_size_: usize,
+    // the block handed out from, null before the first one
+    current_: AtomicPtr<Block>,
+    // every block, boxed so it stays put, taken to add one
+    blocks_: Mutex<Vec<*mut Block>>,
+    memory_usage_: AtomicUsize,
+}
+
+struct Block {
+    ptr_: *mut u8,
+    layout_: Layout,
+    // the bytes handed out from the start
+    used_: AtomicUsize,
+}
+
+unsafe impl Send for Arena {}
+unsafe impl Sync for Arena {}
+
+impl Arena {
+    pub fn new(block_size: usize) -> Self {
+        Arena {
+            block_size_: cmp::max(block_size, BLOCK_ALIGN),
+            current_: AtomicPtr::default(),
+            blocks_: Mutex::new(vec![]),
+            memory_usage_: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size_
+    }
+
+    /// The bytes of all the blocks, handed out or not.
+    pub fn memory_usage(&self) -> usize {
+        self.memory_usage_.load(Relaxed)
+    }
+
+    /// Returns memory for `layout`, it lives as long as the arena.
+    pub fn allocate(&self, layout: Layout) -> *mut u8 {
+        if let Some(result) = Self::allocate_from(self.current_.load(Acquire), layout) {
+            return result;
+        }
+
+        let mut blocks = self.blocks_.lock().unwrap();
+        // another thread may have added a block meanwhile
+        if let Some(result) = Self::allocate_from(self.current_.load(Acquire), layout) {
+            return result;
+        }
+
+        // a big one gets a block of its own, so the rest of the current
+        // block is not wasted
+        if layout.size() > self.block_size_ / 4 || layout.align() > BLOCK_ALIGN {
+            let block = self.allocate_block(&mut blocks, layout.size(), layout.align());
+            return unsafe { (*block).ptr_ };
+        }
+
+        let block = self.allocate_block(&mut blocks, self.block_size_, BLOCK_ALIGN);
+        unsafe { (*block).used_.store(layout.size(), Relaxed) };
+        self.current_.store(block, Release);
+        unsafe { (*block).ptr_ }
+    }
+
+    /// Copies the bytes `x` points to, see `ArenaCopy`.
+    pub fn copy<T: ArenaCopy>(&self, x: &T) -> T {
+        let bytes = x.arena_bytes();
+        let ptr = self.allocate(Layout::array::<u8>(bytes.len()).unwrap());
+        unsafe {
+            ptr::copy_nonoverlapping(bytes.as_ptr(), ptr, bytes.len());
+            x.in_arena(slice::from_raw_parts(ptr, bytes.len()))
+        }
+    }
+
+    /// Bumps the used bytes of `block`, unless `layout` doesn't fit.
+    fn allocate_from(block: *mut Block, layout: Layout) -> Option<*mut u8> {
+        let block = unsafe { block.as_ref() }?;
+        let mut used = block.used_.load(Relaxed);
+        loop {
+            let pad = unsafe { block.ptr_.add(used) }.align_offset(layout.align());
+            let new_used = used + pad + layout.size();
+            if new_used > block.layout_.size() {
+                return None;
+            }
+            match block
+                .used_
+                .compare_exchange_weak(used, new_used, Relaxed, Relaxed)
+            {
+                Ok(_) => return Some(unsafe { block.ptr_.add(used + pad) }),
+                Err(x) => used = x,
+            }
+        }
+    }
+
+    /// Adds a block which is all handed out, but for what the caller
+    /// stores into `used_` before it publishes the block.
+    fn allocate_block(
+        &self,
+        blocks: &mut Vec<*mut Block>,
+        size: usize,
+        align: usize,
+    ) -> *mut Block {
+        let layout = Layout::from_size_align(cmp::max(size, 1), align).unwrap();
+        let ptr = unsafe { alloc(layout) };
+        if ptr.is_null() {
+            handle_alloc_error(layout);
+        }
+        self.memory_usage_.fetch_add(layout.size(), Relaxed);
+        let block = Box::into_raw(Box::new(Block {
+            ptr_: ptr,
+            layout_: layout,
+            used_: AtomicUsize::new(layout.size()),
+        }));
+        blocks.push(block);
+        block
+    }
+
+    /// Frees all the blocks, nothing handed out before may be used anymore.
+    pub fn reset(&mut self) {
+        for block in self.blocks_.get_mut().unwrap().drain(..) {
+            let block = unsafe { Box::from_raw(block) };
+            unsafe { dealloc(block.ptr_, block.layout_) };
+        }
+        self.current_.store(ptr::null_mut(), Relaxed);
+        self.memory_usage_.store(0, Relaxed);
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        self.reset();
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+
+    /// Static bytes, so a test can read what a list copied.
+    #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    pub(crate) struct Bytes(pub &'static [u8]);
+
+    unsafe impl ArenaCopy for Bytes {
+        fn arena_bytes(&self) -> &[u8] {
+            self.0
+        }
+
+        unsafe fn in_arena(&self, bytes: &'static [u8]) -> Self {
+            Bytes(bytes)
+        }
+    }
+
+    #[test]
+    fn test_allocate() {
+        let mut arena = Arena::new(1024);
+        assert_eq!(0, arena.memory_usage());
+
+        let a = arena.allocate(Layout::from_size_align(3, 1).unwrap());
+        let b = arena.allocate(Layout::new::<u64>());
+        assert_eq!(1024, arena.memory_usage());
+        assert_eq!(0, b as usize % 8);
+        assert!(b as usize >= a as usize + 3);
+        unsafe {
+            ptr::write_bytes(a, 1, 3);
+            *(b as *mut u64) = u64::max_value();
+            assert_eq!(1, *a.add(2));
+        }
+
+        // one which does not fit and is big goes to a block of its own
+        let big = arena.allocate(Layout::from_size_align(1020, 8).unwrap());
+        assert_eq!(1024 + 1020, arena.memory_usage());
+        unsafe { ptr::write_bytes(big, 2, 1020) };
+        let c = arena.allocate(Layout::new::<u64>());
+        assert_eq!(b as usize + 8, c as usize);
+
+        // the current block is full
+        for _ in 0..1024 / 8 {
+            arena.allocate(Layout::new::<u64>());
+        }
+        assert_eq!(2 * 1024 + 1020, arena.memory_usage());
+
+        arena.reset();
+        assert_eq!(0, arena.memory_usage());
+        arena.allocate(Layout::new::<u64>());
+        assert_eq!(1024, arena.memory_usage());
+    }
+
+    #[test]
+    fn test_concurrent_allocate() {
+        let arena = Arc::new(Arena::new(1024));
+        let threads: Vec<_> = (0..8)
+            .map(|t| {
+                let arena = arena.clone();
+                thread::spawn(move || {
+                    (0..1000)
+                        .map(|i| {
+                            let p = arena.allocate(Layout::new::<u64>()) as *mut u64;
+                            unsafe { *p = t * 1000 + i };
+                            p as usize
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let ptrs: Vec<usize> = threads
+            .into_iter()
+            .flat_map(|x| x.join().unwrap())
+            .collect();
+
+        // no two got the same memory, nothing was written over
+        assert_eq!(8000, ptrs.iter().collect::<HashSet<_>>().len());
+        for (i, p) in ptrs.iter().enumerate() {
+            assert_eq!(i as u64, unsafe { *(*p as *const u64) });
+        }
+        assert_eq!(0, arena.memory_usage() % 1024);
+        assert!(arena.memory_usage() >= 8000 * 8);
+    }
+}
@@ -0,0 +1,17 @@
+use std::cmp::Ordering;
+
+/// The order of the keys of a skip list. `Q` is the form the keys are
+/// looked up by, it is borrowed from them.
+pub trait Comparator<Q: ?Sized> {
+    fn compare(&self, a: &Q, b: &Q) -> Ordering;
+}
+
+/// Orders the keys by `Ord`, the default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OrdComparator;
+
+impl<Q: Ord + ?Sized> Comparator<Q> for OrdComparator {
+    fn compare(&self, a: &Q, b: &Q) -> Ordering {
+        a.cmp(b)
+    }
+}
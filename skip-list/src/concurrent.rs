@@ -0,0 +1,861 @@
+use std::alloc::{alloc, dealloc, handle_alloc_error, Layout};
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+use std::iter::Rev;
+use std::mem;
+use std::ops::Bound;
+use std::ops::RangeBounds;
+use std::ptr;
+use std::slice;
+use std::sync::atomic::AtomicPtr;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed};
+
+use crate::arena::Arena;
+use crate::arena::ArenaCopy;
+use crate::comparator::Comparator;
+use crate::comparator::OrdComparator;
+use crate::height_generator::GenHeight;
+use crate::height_generator::HeightGenerator;
+
+// the next pointers follow the node in the same allocation, `height_ + 1`
+// of them
+#[repr(C)]
+struct Node<K, V> {
+    key_: K,
+    value_: V,
+    height_: usize,
+    nexts_: [AtomicPtr<Node<K, V>>; 0],
+}
+
+impl<K, V> Node<K, V> {
+    fn layout(height: usize) -> Layout {
+        let (layout, _) = Layout::new::<Node<K, V>>()
+            .extend(Layout::array::<AtomicPtr<Node<K, V>>>(height + 1).unwrap())
+            .unwrap();
+        layout.pad_to_align()
+    }
+
+    /// Takes the memory from the heap.
+    fn allocate(key: K, value: V, height: usize) -> *mut Node<K, V> {
+        let layout = Self::layout(height);
+        let node = unsafe { alloc(layout) } as *mut Node<K, V>;
+        if node.is_null() {
+            handle_alloc_error(layout);
+        }
+        unsafe { Self::init(node, key, value, height) };
+        node
+    }
+
+    /// Takes the memory from `arena`, along with the bytes the key and the
+    /// value point to, which are copied right after the next pointers.
+    fn allocate_in_arena(key: &K, value: &V, height: usize, arena: &Arena) -> *mut Node<K, V>
+    where
+        K: ArenaCopy,
+        V: ArenaCopy,
+    {
+        let (key_bytes, value_bytes) = (key.arena_bytes(), value.arena_bytes());
+        let (layout, offset) = Self::layout(height)
+            .extend(Layout::array::<u8>(key_bytes.len() + value_bytes.len()).unwrap())
+            .unwrap();
+        let node = arena.allocate(layout) as *mut Node<K, V>;
+        unsafe {
+            let bytes = (node as *mut u8).add(offset);
+            ptr::copy_nonoverlapping(key_bytes.as_ptr(), bytes, key_bytes.len());
+            let value_at = bytes.add(key_bytes.len());
+            ptr::copy_nonoverlapping(value_bytes.as_ptr(), value_at, value_bytes.len());
+            Self::init(
+                node,
+                key.in_arena(slice::from_raw_parts(bytes, key_bytes.len())),
+                value.in_arena(slice::from_raw_parts(value_at, value_bytes.len())),
+                height,
+            );
+        }
+        node
+    }
+
+    unsafe fn init(node: *mut Node<K, V>, key: K, value: V, height: usize) {
+        ptr::write(
+            node,
+            Node {
+                key_: key,
+                value_: value,
+                height_: height,
+                nexts_: [],
+            },
+        );
+        let nexts = ptr::addr_of_mut!((*node).nexts_) as *mut AtomicPtr<Node<K, V>>;
+        for level in 0..=height {
+            ptr::write(nexts.add(level), AtomicPtr::default());
+        }
+    }
+
+    /// Drops the key and the value, the memory goes back to the heap unless
+    /// it is from an arena.
+    unsafe fn free(node: *mut Node<K, V>, in_arena: bool) {
+        let layout = Self::layout((*node).height_);
+        ptr::drop_in_place(node);
+        if !in_arena {
+            dealloc(node as *mut u8, layout);
+        }
+    }
+
+    /// Moves the key and the value out of a node from the heap, then frees
+    /// it.
+    unsafe fn into_entry(node: *mut Node<K, V>) -> (K, V) {
+        let layout = Self::layout((*node).height_);
+        let entry = (ptr::read(&(*node).key_), ptr::read(&(*node).value_));
+        dealloc(node as *mut u8, layout);
+        entry
+    }
+
+    fn memory_size(height: usize) -> usize {
+        Self::layout(height).size()
+    }
+
+    /// The next pointer at `level`, which is not above the height.
+    unsafe fn next<'a>(node: *mut Node<K, V>, level: usize) -> &'a AtomicPtr<Node<K, V>> {
+        &*(ptr::addr_of!((*node).nexts_) as *const AtomicPtr<Node<K, V>>).add(level)
+    }
+}
+
+/// A skip list any number of threads can insert into and read from at the
+/// same time, through a shared reference. Reads never block, inserts link a
+/// node level by level with compare-and-swap, starting from the bottom one,
+/// so a node is found as soon as it is on level 0.
+///
+/// Entries are never removed, the nodes are freed with the list, so a
+/// reader can't see one go away. A list made with `new_in_arena` takes the
+/// nodes from an `Arena`, the bytes the keys and the values point to next
+/// to them, and the arena frees them all in one go.
+pub struct ConcurrentSkipList<K, V, C = OrdComparator> {
+    // the tower of the head, it has no key
+    head_: Vec<AtomicPtr<Node<K, V>>>,
+    height_: AtomicUsize,
+    length_: AtomicUsize,
+    max_height_: usize,
+    memory_usage_: AtomicUsize,
+    arena_: Option<(Arena, AllocateInArena<K, V>)>,
+    height_generator: Box<dyn HeightGenerator + Send>,
+    cmp_: C,
+}
+
+// `Node::allocate_in_arena`, taken where the keys and the values are known
+// to be `ArenaCopy`
+type AllocateInArena<K, V> = fn(&K, &V, usize, &Arena) -> *mut Node<K, V>;
+
+unsafe impl<K: Send + Sync, V: Send + Sync, C: Send> Send for ConcurrentSkipList<K, V, C> {}
+unsafe impl<K: Send + Sync, V: Send + Sync, C: Sync> Sync for ConcurrentSkipList<K, V, C> {}
+
+impl<K, V> ConcurrentSkipList<K, V> {
+    pub fn new(max_height: usize) -> Self {
+        Self::new_with_height_generator(max_height, Box::new(GenHeight::new()))
+    }
+
+    pub fn new_with_height_generator(
+        max_height: usize,
+        height_generator: Box<dyn HeightGenerator + Send>,
+    ) -> Self {
+        Self::new_with(max_height, height_generator, OrdComparator, None)
+    }
+
+    pub fn new_in_arena(max_height: usize, arena: Arena) -> Self
+    where
+        K: ArenaCopy,
+        V: ArenaCopy,
+    {
+        Self::new_in_arena_with_height_generator(max_height, Box::new(GenHeight::new()), arena)
+    }
+
+    pub fn new_in_arena_with_height_generator(
+        max_height: usize,
+        height_generator: Box<dyn HeightGenerator + Send>,
+        arena: Arena,
+    ) -> Self
+    where
+        K: ArenaCopy,
+        V: ArenaCopy,
+    {
+        Self::new_with(
+            max_height,
+            height_generator,
+            OrdComparator,
+            Some((arena, Node::allocate_in_arena)),
+        )
+    }
+}
+
+impl<K, V, C> ConcurrentSkipList<K, V, C> {
+    pub fn new_with_comparator(max_height: usize, cmp: C) -> Self {
+        Self::new_with(max_height, Box::new(GenHeight::new()), cmp, None)
+    }
+
+    pub fn new_in_arena_with_comparator(
+        max_height: usize,
+        height_generator: Box<dyn HeightGenerator + Send>,
+        cmp: C,
+        arena: Arena,
+    ) -> Self
+    where
+        K: ArenaCopy,
+        V: ArenaCopy,
+    {
+        Self::new_with(
+            max_height,
+            height_generator,
+            cmp,
+            Some((arena, Node::allocate_in_arena)),
+        )
+    }
+
+    fn new_with(
+        max_height: usize,
+        height_generator: Box<dyn HeightGenerator + Send>,
+        cmp: C,
+        arena: Option<(Arena, AllocateInArena<K, V>)>,
+    ) -> Self {
+        ConcurrentSkipList {
+            head_: (0..=max_height).map(|_| AtomicPtr::default()).collect(),
+            height_: AtomicUsize::new(0),
+            length_: AtomicUsize::new(0),
+            max_height_: max_height,
+            memory_usage_: AtomicUsize::new(Self::head_memory_size(max_height)),
+            arena_: arena,
+            height_generator,
+            cmp_: cmp,
+        }
+    }
+
+    fn head_memory_size(max_height: usize) -> usize {
+        (max_height + 1) * mem::size_of::<AtomicPtr<Node<K, V>>>()
+    }
+
+    pub fn length(&self) -> usize {
+        self.length_.load(Relaxed)
+    }
+
+    pub fn height(&self) -> usize {
+        self.height_.load(Relaxed)
+    }
+
+    pub fn max_height(&self) -> usize {
+        self.max_height_
+    }
+
+    /// The bytes the nodes take, not counting what the keys and the values
+    /// point to. In an arena it is all of its blocks, which hold those too.
+    pub fn memory_usage(&self) -> usize {
+        let arena_usage = self.arena_.as_ref().map_or(0, |x| x.0.memory_usage());
+        self.memory_usage_.load(Relaxed) + arena_usage
+    }
+
+    /// The next pointer of `node` at `level`, `node` is the head when null.
+    fn next(&self, node: *mut Node<K, V>, level: usize) -> &AtomicPtr<Node<K, V>> {
+        if node.is_null() {
+            &self.head_[level]
+        } else {
+            unsafe { Node::next(node, level) }
+        }
+    }
+
+    pub fn iter(&self) -> ConcurrentSkipListIter<K, V, C> {
+        ConcurrentSkipListIter {
+            list_: self,
+            node_: ptr::null_mut(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.dispose();
+        for next in &self.head_ {
+            next.store(ptr::null_mut(), Relaxed);
+        }
+        self.height_.store(0, Relaxed);
+        self.length_.store(0, Relaxed);
+        self.memory_usage_
+            .store(Self::head_memory_size(self.max_height_), Relaxed);
+        if let Some((arena, _)) = self.arena_.as_mut() {
+            arena.reset();
+        }
+    }
+
+    fn dispose(&mut self) {
+        let in_arena = self.arena_.is_some();
+        let mut current = self.head_[0].load(Relaxed);
+        while !current.is_null() {
+            let next = unsafe { Node::next(current, 0) }.load(Relaxed);
+            unsafe { Node::free(current, in_arena) };
+            current = next;
+        }
+    }
+}
+
+impl<K, V, C: Comparator<K>> ConcurrentSkipList<K, V, C> {
+    /// Returns the last node whose key is less than `key` at `level`,
+    /// starting from `before`, and the node after it.
+    fn find_splice_for_level<Q: ?Sized>(
+        &self,
+        key: &Q,
+        before: *mut Node<K, V>,
+        level: usize,
+    ) -> (*mut Node<K, V>, *mut Node<K, V>)
+    where
+        K: Borrow<Q>,
+        C: Comparator<Q>,
+    {
+        let mut prev = before;
+        loop {
+            let next = self.next(prev, level).load(Acquire);
+            if next.is_null()
+                || self.cmp_.compare(unsafe { (*next).key_.borrow() }, key) != Ordering::Less
+            {
+                return (prev, next);
+            }
+            prev = next;
+        }
+    }
+
+    /// Returns the last node whose key is less than `key`, null for the
+    /// head, and the node after it.
+    fn find_splice<Q: ?Sized>(&self, key: &Q) -> (*mut Node<K, V>, *mut Node<K, V>)
+    where
+        K: Borrow<Q>,
+        C: Comparator<Q>,
+    {
+        let mut prev = ptr::null_mut();
+        let mut next = ptr::null_mut();
+        for level in (0..=self.height()).rev() {
+            let splice = self.find_splice_for_level(key, prev, level);
+            prev = splice.0;
+            next = splice.1;
+        }
+        (prev, next)
+    }
+
+    /// Returns the first node whose key is not less than `key`.
+    fn find_greater_or_equal<Q: ?Sized>(&self, key: &Q) -> *mut Node<K, V>
+    where
+        K: Borrow<Q>,
+        C: Comparator<Q>,
+    {
+        self.find_splice(key).1
+    }
+
+    /// Returns the first node whose key is greater than `key`.
+    fn find_greater<Q: ?Sized>(&self, key: &Q) -> *mut Node<K, V>
+    where
+        K: Borrow<Q>,
+        C: Comparator<Q>,
+    {
+        let next = self.find_greater_or_equal(key);
+        if !next.is_null() && self.is_equal(unsafe { (*next).key_.borrow() }, key) {
+            self.next(next, 0).load(Acquire)
+        } else {
+            next
+        }
+    }
+
+    /// Returns the last node before `node`, which is null for the end of the
+    /// list. Null is returned if there is none.
+    fn find_prev(&self, node: *mut Node<K, V>) -> *mut Node<K, V> {
+        if !node.is_null() {
+            return self.find_splice(unsafe { &(*node).key_ }).0;
+        }
+        let mut prev = ptr::null_mut();
+        for level in (0..=self.height()).rev() {
+            loop {
+                let next = self.next(prev, level).load(Acquire);
+                if next.is_null() {
+                    break;
+                }
+                prev = next;
+            }
+        }
+        prev
+    }
+
+    pub fn get<Q: ?Sized>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        C: Comparator<Q>,
+    {
+        match self.seek(key) {
+            Some((k, v)) if self.is_equal(k.borrow(), key) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Returns the first entry whose key is not less than `key`.
+    pub fn seek<Q: ?Sized>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        C: Comparator<Q>,
+    {
+        let node = self.find_greater_or_equal(key);
+        if node.is_null() {
+            None
+        } else {
+            unsafe { Some((&(*node).key_, &(*node).value_)) }
+        }
+    }
+
+    /// Returns the last entry whose key is not greater than `key`.
+    pub fn seek_for_prev<Q: ?Sized>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        C: Comparator<Q>,
+    {
+        let (prev, next) = self.find_splice(key);
+        let node = if !next.is_null() && self.is_equal(unsafe { (*next).key_.borrow() }, key) {
+            next
+        } else {
+            prev
+        };
+        if node.is_null() {
+            None
+        } else {
+            unsafe { Some((&(*node).key_, &(*node).value_)) }
+        }
+    }
+
+    /// Iterates the entries whose keys are in `range`, from either end.
+    pub fn range<Q: ?Sized, R>(&self, range: R) -> ConcurrentSkipListRange<K, V, C>
+    where
+        K: Borrow<Q>,
+        C: Comparator<Q>,
+        R: RangeBounds<Q>,
+    {
+        let front = match range.start_bound() {
+            Bound::Included(k) => self.find_greater_or_equal(k),
+            Bound::Excluded(k) => self.find_greater(k),
+            Bound::Unbounded => self.head_[0].load(Acquire),
+        };
+        let back = match range.end_bound() {
+            Bound::Included(k) => self.find_greater(k),
+            Bound::Excluded(k) => self.find_greater_or_equal(k),
+            Bound::Unbounded => ptr::null_mut(),
+        };
+        // an empty range may start past its end
+        let is_empty = !back.is_null()
+            && (front.is_null()
+                || self
+                    .cmp_
+                    .compare(unsafe { &(*front).key_ }, unsafe { &(*back).key_ })
+                    == Ordering::Greater);
+        ConcurrentSkipListRange {
+            list_: self,
+            front_: if is_empty { back } else { front },
+            back_: back,
+        }
+    }
+
+    /// Iterates the entries from the last one.
+    pub fn rev_iter(&self) -> Rev<ConcurrentSkipListRange<K, V, C>> {
+        self.range::<K, _>(..).rev()
+    }
+
+    /// The value of `key`, to change it in place. No one else can read the
+    /// list meanwhile.
+    pub fn get_mut<Q: ?Sized>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        C: Comparator<Q>,
+    {
+        let node = self.find_greater_or_equal(key);
+        if node.is_null() || !self.is_equal(unsafe { (*node).key_.borrow() }, key) {
+            None
+        } else {
+            unsafe { Some(&mut (*node).value_) }
+        }
+    }
+
+    fn is_equal<Q: ?Sized>(&self, a: &Q, b: &Q) -> bool
+    where
+        C: Comparator<Q>,
+    {
+        self.cmp_.compare(a, b) == Ordering::Equal
+    }
+
+    /// Inserts the entry, unless the key is in the list already, then
+    /// returns it back. Safe to call from several threads at once, it takes
+    /// no lock.
+    pub fn insert(&self, key: K, value: V) -> Result<(), (K, V)> {
+        let height = self.height_generator.gen_height(self.max_height_);
+
+        // the levels above the current height are only reached from the
+        // head
+        let mut list_height = self.height();
+        while height > list_height {
+            match self
+                .height_
+                .compare_exchange_weak(list_height, height, Relaxed, Relaxed)
+            {
+                Ok(_) => break,
+                Err(x) => list_height = x,
+            }
+        }
+        let list_height = ::std::cmp::max(list_height, height);
+
+        let mut prevs = vec![ptr::null_mut(); height + 1];
+        let mut nexts = vec![ptr::null_mut(); height + 1];
+        let mut prev = ptr::null_mut();
+        for level in (0..=list_height).rev() {
+            let (p, n) = self.find_splice_for_level(&key, prev, level);
+            if level <= height {
+                prevs[level] = p;
+                nexts[level] = n;
+            }
+            prev = p;
+        }
+
+        // the entry is kept to give it back, the node in an arena has copies
+        let (node, mut entry) = match &self.arena_ {
+            Some((arena, allocate)) => (allocate(&key, &value, height, arena), Some((key, value))),
+            None => (Node::allocate(key, value, height), None),
+        };
+        for level in 0..=height {
+            loop {
+                if level == 0 {
+                    let next = nexts[0];
+                    if !next.is_null()
+                        && self.is_equal(unsafe { &(*next).key_ }, unsafe { &(*node).key_ })
+                    {
+                        // the node is left to the arena if it is from one,
+                        // its copies free nothing
+                        return Err(match entry.take() {
+                            Some(entry) => entry,
+                            None => unsafe { Node::into_entry(node) },
+                        });
+                    }
+                }
+                unsafe { Node::next(node, level).store(nexts[level], Relaxed) };
+                // publishes the node, with its key and value
+                match self.next(prevs[level], level).compare_exchange(
+                    nexts[level],
+                    node,
+                    AcqRel,
+                    Acquire,
+                ) {
+                    Ok(_) => break,
+                    Err(_) => {
+                        // another insert got in between, the splice moved
+                        let (p, n) = self.find_splice_for_level(
+                            unsafe { &(*node).key_ },
+                            prevs[level],
+                            level,
+                        );
+                        prevs[level] = p;
+                        nexts[level] = n;
+                    }
+                }
+            }
+        }
+
+        self.length_.fetch_add(1, Relaxed);
+        if self.arena_.is_none() {
+            self.memory_usage_
+                .fetch_add(Node::<K, V>::memory_size(height), Relaxed);
+        }
+        Ok(())
+    }
+}
+
+impl<K: Clone, V: Clone, C: Comparator<K> + Clone> Clone for ConcurrentSkipList<K, V, C> {
+    fn clone(&self) -> Self {
+        let arena = self
+            .arena_
+            .as_ref()
+            .map(|(arena, allocate)| (Arena::new(arena.block_size()), *allocate));
+        let copied = ConcurrentSkipList::new_with(
+            self.max_height_,
+            Box::new(GenHeight::new()),
+            self.cmp_.clone(),
+            arena,
+        );
+        for (k, v) in self.iter() {
+            let _ = copied.insert(k.clone(), v.clone());
+        }
+        copied
+    }
+}
+
+impl<K, V, C> Debug for ConcurrentSkipList<K, V, C> {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(
+            f,
+            "ConcurrentSkipList {{ len = {}, height = {} }}",
+            self.length(),
+            self.height()
+        )
+    }
+}
+
+impl<K, V, C> Drop for ConcurrentSkipList<K, V, C> {
+    fn drop(&mut self) {
+        self.dispose();
+    }
+}
+
+/// Iterates the entries in key order, the ones inserted meanwhile may or
+/// may not be seen.
+pub struct ConcurrentSkipListIter<'a, K, V, C = OrdComparator> {
+    list_: &'a ConcurrentSkipList<K, V, C>,
+    node_: *mut Node<K, V>,
+}
+
+impl<'a, K, V, C> Iterator for ConcurrentSkipListIter<'a, K, V, C> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = self.list_.next(self.node_, 0).load(Acquire);
+        if next.is_null() {
+            return None;
+        }
+        self.node_ = next;
+        unsafe { Some((&(*next).key_, &(*next).value_)) }
+    }
+}
+
+/// The entries from `front_` up to `back_`, which is not included and is
+/// null for the end of the list. The ones inserted meanwhile may or may not
+/// be seen.
+pub struct ConcurrentSkipListRange<'a, K, V, C = OrdComparator> {
+    list_: &'a ConcurrentSkipList<K, V, C>,
+    front_: *mut Node<K, V>,
+    back_: *mut Node<K, V>,
+}
+
+impl<'a, K, V, C> Iterator for ConcurrentSkipListRange<'a, K, V, C> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.front_ == self.back_ {
+            return None;
+        }
+        let node = self.front_;
+        self.front_ = self.list_.next(node, 0).load(Acquire);
+        unsafe { Some((&(*node).key_, &(*node).value_)) }
+    }
+}
+
+impl<'a, K, V, C: Comparator<K>> DoubleEndedIterator for ConcurrentSkipListRange<'a, K, V, C> {
+    /// Each step searches the list again, there are no back links.
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.front_ == self.back_ {
+            return None;
+        }
+        self.back_ = self.list_.find_prev(self.back_);
+        let node = self.back_;
+        unsafe { Some((&(*node).key_, &(*node).value_)) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use std::sync::Barrier;
+    use std::thread;
+
+    use rand::prelude::*;
+
+    use crate::arena::test::Bytes;
+
+    use super::*;
+
+    #[test]
+    fn test_insert() {
+        let mut list = ConcurrentSkipList::new(10);
+        assert_eq!(Ok(()), list.insert(2, 4));
+        assert_eq!(Ok(()), list.insert(0, 1));
+        assert_eq!(Ok(()), list.insert(1, 3));
+        assert_eq!(Err((0, 2)), list.insert(0, 2));
+        assert_eq!(Some(&1), list.get(&0));
+        assert_eq!(Some(&3), list.get(&1));
+        assert_eq!(Some(&4), list.get(&2));
+        assert_eq!(None, list.get(&3));
+        assert_eq!(3, list.length());
+        assert_eq!(Some((&1, &3)), list.seek(&1));
+        assert_eq!(None, list.seek(&3));
+
+        *list.get_mut(&0).unwrap() = 2;
+        assert_eq!(Some(&2), list.get(&0));
+        assert_eq!(
+            vec![(0, 2), (1, 3), (2, 4)],
+            list.iter().map(|(k, v)| (*k, *v)).collect::<Vec<_>>()
+        );
+
+        let usage = list.memory_usage();
+        let copied = list.clone();
+        assert_eq!(3, copied.length());
+        list.clear();
+        assert_eq!(0, list.length());
+        assert_eq!(None, list.get(&0));
+        assert!(list.memory_usage() < usage);
+        assert_eq!(Some(&2), copied.get(&0));
+    }
+
+    #[test]
+    fn test_concurrent_insert() {
+        concurrent_insert(ConcurrentSkipList::new(12));
+        concurrent_insert(ConcurrentSkipList::new_in_arena(12, Arena::new(4096)));
+    }
+
+    fn concurrent_insert(list: ConcurrentSkipList<usize, usize>) {
+        let threads = 8;
+        let n = 2000;
+        let list = Arc::new(list);
+        let barrier = Arc::new(Barrier::new(threads + 1));
+
+        let writers: Vec<_> = (0..threads)
+            .map(|t| {
+                let list = list.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    let mut keys: Vec<usize> = (0..n).map(|i| i * threads + t).collect();
+                    keys.shuffle(&mut thread_rng());
+                    barrier.wait();
+                    for k in keys {
+                        list.insert(k, k + 1).unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        // the readers see the list sorted, whatever is in it
+        barrier.wait();
+        for _ in 0..10 {
+            let keys: Vec<usize> = list.iter().map(|(k, _)| *k).collect();
+            assert!(keys.windows(2).all(|x| x[0] < x[1]));
+        }
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        assert_eq!(threads * n, list.length());
+        let mut seen = HashSet::new();
+        for (k, v) in list.iter() {
+            assert_eq!(k + 1, *v);
+            seen.insert(*k);
+        }
+        assert_eq!(threads * n, seen.len());
+        for k in 0..threads * n {
+            assert_eq!(Some(&(k + 1)), list.get(&k));
+        }
+    }
+
+    #[test]
+    fn test_range() {
+        let list = ConcurrentSkipList::new(10);
+        for i in 0..10 {
+            list.insert(i * 2, i).unwrap();
+        }
+        assert_eq!(None, list.seek_for_prev(&-1));
+        assert_eq!(Some((&4, &2)), list.seek_for_prev(&4));
+        assert_eq!(Some((&4, &2)), list.seek_for_prev(&5));
+        assert_eq!(Some((&18, &9)), list.seek_for_prev(&30));
+
+        let keys =
+            |range: ConcurrentSkipListRange<i32, i32>| range.map(|(k, _)| *k).collect::<Vec<_>>();
+        assert_eq!(vec![4, 6, 8], keys(list.range(3..10)));
+        assert_eq!(vec![4, 6, 8, 10], keys(list.range(4..=10)));
+        assert_eq!(vec![0, 2], keys(list.range(..3)));
+        assert_eq!(
+            vec![6, 8],
+            keys(list.range((Bound::Excluded(4), Bound::Excluded(10))))
+        );
+        assert!(keys(list.range(7..6)).is_empty());
+        assert!(keys(list.range(19..)).is_empty());
+
+        let mut range = list.range(2..=8);
+        assert_eq!(Some((&8, &4)), range.next_back());
+        assert_eq!(Some((&2, &1)), range.next());
+        assert_eq!(vec![6, 4], range.rev().map(|(k, _)| *k).collect::<Vec<_>>());
+        assert_eq!(
+            (0..10).rev().map(|i| i * 2).collect::<Vec<_>>(),
+            list.rev_iter().map(|(k, _)| *k).collect::<Vec<_>>()
+        );
+    }
+
+    #[derive(Clone)]
+    struct Reverse;
+
+    impl Comparator<i32> for Reverse {
+        fn compare(&self, a: &i32, b: &i32) -> Ordering {
+            b.cmp(a)
+        }
+    }
+
+    #[test]
+    fn test_comparator() {
+        let list = ConcurrentSkipList::new_with_comparator(10, Reverse);
+        for i in 0..5 {
+            list.insert(i, i + 1).unwrap();
+        }
+        assert_eq!(Err((2, 0)), list.insert(2, 0));
+        assert_eq!(
+            vec![4, 3, 2, 1, 0],
+            list.iter().map(|(k, _)| *k).collect::<Vec<_>>()
+        );
+        assert_eq!(Some(&3), list.get(&2));
+        assert_eq!(Some((&2, &3)), list.seek(&2));
+        assert_eq!(None, list.seek(&-1));
+        assert_eq!(Some((&0, &1)), list.seek_for_prev(&-1));
+        assert_eq!(None, list.seek_for_prev(&9));
+        let keys: Vec<_> = list.range(3..=1).map(|(k, _)| *k).collect();
+        assert_eq!(vec![3, 2, 1], keys);
+        assert_eq!(
+            vec![0, 1, 2, 3, 4],
+            list.clone().rev_iter().map(|(k, _)| *k).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_arena() {
+        let value: &'static [u8] = b"value";
+        let mut list = ConcurrentSkipList::new_in_arena(10, Arena::new(1024));
+        let empty = list.memory_usage();
+        for k in 0..100 {
+            assert!(list.insert(k, Bytes(value)).is_ok());
+        }
+        // the entry given back is the one passed in
+        let other: &'static [u8] = b"other";
+        match list.insert(0, Bytes(other)) {
+            Err((0, v)) => assert_eq!(other.as_ptr(), v.0.as_ptr()),
+            x => panic!("{:?}", x),
+        }
+
+        // the value is copied next to the node, and counted with it
+        let v = list.get(&0).unwrap();
+        assert_eq!(value, v.0);
+        assert_ne!(value.as_ptr(), v.0.as_ptr());
+        let used = list.memory_usage() - empty;
+        assert_eq!(0, used % 1024);
+        assert!(used >= 100 * (Node::<usize, Bytes>::memory_size(0) + value.len()));
+        assert_eq!(
+            (0..100).collect::<Vec<_>>(),
+            list.iter().map(|(k, _)| *k).collect::<Vec<_>>()
+        );
+
+        let copied = list.clone();
+        list.clear();
+        assert_eq!(empty, list.memory_usage());
+        assert_eq!(None, list.get(&99));
+        assert_eq!(Some(&Bytes(value)), copied.get(&99));
+    }
+
+    #[test]
+    fn test_concurrent_duplicates() {
+        let list = Arc::new(ConcurrentSkipList::new(12));
+        let writers: Vec<_> = (0..8)
+            .map(|t| {
+                let list = list.clone();
+                thread::spawn(move || (0..1000).filter(|k| list.insert(*k, t).is_ok()).count())
+            })
+            .collect();
+        let inserted: usize = writers.into_iter().map(|x| x.join().unwrap()).sum();
+        assert_eq!(1000, inserted);
+        assert_eq!(1000, list.length());
+    }
+}
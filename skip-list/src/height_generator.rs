@@ -1,30 +1,104 @@
-use rand::prelude::ThreadRng;
-use rand::Rng;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
 
-pub trait HeightGenerator {
-    fn gen_height(&mut self, max: usize) -> usize;
+const DEFAULT_PROBABILITY: f64 = 0.5;
+// the increment of splitmix64
+const GOLDEN_GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// Called by the inserts of a concurrent skip list at once, so it takes
+/// `&self` and must not block.
+pub trait HeightGenerator: Sync {
+    fn gen_height(&self, max: usize) -> usize;
 }
 
+/// A node goes up each level with probability `p`, so every level has about
+/// `p` times the nodes of the one below it. A smaller `p` gives shorter
+/// towers and longer searches, LevelDB uses 1/4.
+///
+/// The random numbers are splitmix64, whose state is a counter, so each one
+/// is taken with an atomic add.
 pub struct GenHeight {
-    rng: ThreadRng,
+    state: AtomicU64,
+    // a random number below it goes up a level
+    threshold: u64,
+    p: f64,
 }
 
-unsafe impl Send for GenHeight {}
-
 impl GenHeight {
     pub fn new() -> Self {
+        Self::with_probability(DEFAULT_PROBABILITY)
+    }
+
+    pub fn with_probability(p: f64) -> Self {
+        Self::with_seed(p, rand::random())
+    }
+
+    /// The same seed and `p` give the same heights, so runs can be
+    /// reproduced.
+    pub fn with_seed(p: f64, seed: u64) -> Self {
+        assert!(p > 0.0 && p < 1.0, "the probability must be in (0, 1)");
         GenHeight {
-            rng: rand::thread_rng(),
+            state: AtomicU64::new(seed),
+            threshold: (p * u64::MAX as f64) as u64,
+            p,
         }
     }
+
+    pub fn probability(&self) -> f64 {
+        self.p
+    }
+
+    fn next_u64(&self) -> u64 {
+        let mut z = self
+            .state
+            .fetch_add(GOLDEN_GAMMA, Relaxed)
+            .wrapping_add(GOLDEN_GAMMA);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+impl Default for GenHeight {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl HeightGenerator for GenHeight {
-    fn gen_height(&mut self, max_height: usize) -> usize {
+    fn gen_height(&self, max_height: usize) -> usize {
         let mut l = 0;
-        while self.rng.gen_range::<usize, usize, usize>(0, 2) > 0 && l < max_height {
+        while l < max_height && self.next_u64() < self.threshold {
             l += 1;
         }
         l
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn heights(gen: &GenHeight, n: usize) -> Vec<usize> {
+        (0..n).map(|_| gen.gen_height(12)).collect()
+    }
+
+    #[test]
+    fn test_seed() {
+        let a = heights(&GenHeight::with_seed(0.25, 7), 100);
+        assert_eq!(a, heights(&GenHeight::with_seed(0.25, 7), 100));
+        assert_ne!(a, heights(&GenHeight::with_seed(0.25, 8), 100));
+        assert!(a.iter().all(|&h| h <= 12));
+    }
+
+    #[test]
+    fn test_probability() {
+        let n = 100_000;
+        for &p in &[0.5, 0.25] {
+            let gen = GenHeight::with_seed(p, 1);
+            let above = heights(&gen, n).iter().filter(|&&h| h > 0).count();
+            let ratio = above as f64 / n as f64;
+            assert!((ratio - p).abs() < 0.01, "p: {}, ratio: {}", p, ratio);
+        }
+    }
+}
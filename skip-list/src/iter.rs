@@ -1,10 +1,12 @@
+use crate::comparator::Comparator;
+use crate::comparator::OrdComparator;
 use crate::list::SkipList;
 use crate::node::SkipListNode;
 
 pub struct SkipListIter<'a, K, V>(Option<&'a SkipListNode<K, V>>);
 
 impl<'a, K, V> SkipListIter<'a, K, V> {
-    pub fn new(list: &'a SkipList<K, V>) -> Self {
+    pub fn new<C>(list: &'a SkipList<K, V, C>) -> Self {
         SkipListIter(list.head())
     }
 }
@@ -25,7 +27,7 @@ impl<'a, K, V> Iterator for SkipListIter<'a, K, V> {
 pub struct SkipListIterMut<'a, K, V>(Option<&'a mut SkipListNode<K, V>>);
 
 impl<'a, K, V> SkipListIterMut<'a, K, V> {
-    pub fn new(list: &'a mut SkipList<K, V>) -> Self {
+    pub fn new<C>(list: &'a mut SkipList<K, V, C>) -> Self {
         SkipListIterMut(list.head_mut())
     }
 }
@@ -46,6 +48,53 @@ impl<'a, K, V> Iterator for SkipListIterMut<'a, K, V> {
     }
 }
 
+/// The entries from `front_` up to `back_`, which is not included and is
+/// null for the end of the list.
+pub struct SkipListRange<'a, K, V, C = OrdComparator> {
+    list_: &'a SkipList<K, V, C>,
+    front_: *mut SkipListNode<K, V>,
+    back_: *mut SkipListNode<K, V>,
+}
+
+impl<'a, K, V, C> SkipListRange<'a, K, V, C> {
+    pub(crate) fn new(
+        list: &'a SkipList<K, V, C>,
+        front: *mut SkipListNode<K, V>,
+        back: *mut SkipListNode<K, V>,
+    ) -> Self {
+        SkipListRange {
+            list_: list,
+            front_: front,
+            back_: back,
+        }
+    }
+}
+
+impl<'a, K, V, C> Iterator for SkipListRange<'a, K, V, C> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.front_ == self.back_ {
+            return None;
+        }
+        let node = unsafe { &*self.front_ };
+        self.front_ = node.nexts()[0];
+        Some((&node.key_, &node.value_))
+    }
+}
+
+impl<'a, K, V, C: Comparator<K>> DoubleEndedIterator for SkipListRange<'a, K, V, C> {
+    /// Each step searches the list again, there are no back links.
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.front_ == self.back_ {
+            return None;
+        }
+        self.back_ = self.list_.find_prev(self.back_);
+        let node = unsafe { &*self.back_ };
+        Some((&node.key_, &node.value_))
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::list::SkipList;
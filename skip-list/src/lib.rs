@@ -1,12 +1,18 @@
 #![allow(dead_code)]
 
+mod arena;
+mod comparator;
+mod concurrent;
 mod height_generator;
 mod iter;
 mod list;
 mod node;
 mod util;
 
-pub use crate::height_generator::HeightGenerator;
-pub use crate::iter::{SkipListIter, SkipListIterMut};
+pub use crate::arena::{Arena, ArenaCopy};
+pub use crate::comparator::{Comparator, OrdComparator};
+pub use crate::concurrent::{ConcurrentSkipList, ConcurrentSkipListIter, ConcurrentSkipListRange};
+pub use crate::height_generator::{GenHeight, HeightGenerator};
+pub use crate::iter::{SkipListIter, SkipListIterMut, SkipListRange};
 pub use crate::list::SkipList;
 pub use crate::node::SkipListNode;
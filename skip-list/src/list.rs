@@ -1,82 +1,157 @@
 use std::borrow::Borrow;
+use std::cmp::Ordering;
 use std::fmt::{Display, Formatter, Result as FmtResult};
-use std::mem;
+use std::iter::Rev;
+use std::ops::Bound;
 use std::ops::Drop;
+use std::ops::RangeBounds;
+use std::ptr;
 
+use crate::arena::Arena;
+use crate::arena::ArenaCopy;
+use crate::comparator::Comparator;
+use crate::comparator::OrdComparator;
 use crate::height_generator::GenHeight;
 use crate::height_generator::HeightGenerator;
 use crate::iter::SkipListIter;
 use crate::iter::SkipListIterMut;
+use crate::iter::SkipListRange;
 use crate::node::SkipListNode;
 use std::fmt::Debug;
 
-pub struct SkipList<K, V> {
+/// A skip list, the keys are in the order of `C`. A list made with
+/// `new_in_arena` takes the nodes from an `Arena`, the bytes the keys and
+/// the values point to next to them, and the arena frees them all in one go.
+pub struct SkipList<K, V, C = OrdComparator> {
     head_: *mut SkipListNode<K, V>,
     length_: usize,
     height_: usize,
     max_height_: usize,
+    memory_usage_: usize,
+    arena_: Option<InArena<K, V>>,
     height_generator: Box<dyn HeightGenerator + Send>,
+    cmp_: C,
 }
 
-impl<K, V> SkipList<K, V> {
-    pub fn length(&self) -> usize {
-        self.length_
+// the arena of the nodes and how the entries are copied into it, taken
+// where the keys and the values are known to be `ArenaCopy`
+struct InArena<K, V> {
+    arena_: Arena,
+    allocate_: fn(&K, &V, usize, &Arena) -> *mut SkipListNode<K, V>,
+    copy_value_: fn(&Arena, &V) -> V,
+}
+
+impl<K, V> InArena<K, V> {
+    fn new(arena: Arena) -> Self
+    where
+        K: ArenaCopy,
+        V: ArenaCopy,
+    {
+        InArena {
+            arena_: arena,
+            allocate_: SkipListNode::allocate_in_arena,
+            copy_value_: Arena::copy,
+        }
     }
+}
 
-    pub fn height(&self) -> usize {
-        self.height_
+impl<K, V> SkipList<K, V> {
+    pub fn new(max_height: usize) -> Self {
+        Self::new_with_height_generator(max_height, Box::new(GenHeight::new()))
     }
 
-    pub fn max_height(&self) -> usize {
-        self.max_height_
+    pub fn new_with_height_generator(
+        max_height: usize,
+        height_generator: Box<dyn HeightGenerator + Send>,
+    ) -> Self {
+        SkipList::new_with(max_height, height_generator, OrdComparator, None)
     }
 
-    pub fn head(&self) -> Option<&SkipListNode<K, V>> {
-        SkipListNode::from_raw(self.head_)
+    pub fn new_in_arena(max_height: usize, arena: Arena) -> Self
+    where
+        K: ArenaCopy,
+        V: ArenaCopy,
+    {
+        Self::new_in_arena_with_height_generator(max_height, Box::new(GenHeight::new()), arena)
     }
 
-    pub fn head_mut(&self) -> Option<&mut SkipListNode<K, V>> {
-        SkipListNode::from_raw_mut(self.head_)
+    pub fn new_in_arena_with_height_generator(
+        max_height: usize,
+        height_generator: Box<dyn HeightGenerator + Send>,
+        arena: Arena,
+    ) -> Self
+    where
+        K: ArenaCopy,
+        V: ArenaCopy,
+    {
+        SkipList::new_with(
+            max_height,
+            height_generator,
+            OrdComparator,
+            Some(InArena::new(arena)),
+        )
     }
+}
 
-    pub fn new(max_height: usize) -> Self {
-        Self::new_with_height_generator(max_height, Box::new(GenHeight::new()))
+impl<K, V, C> SkipList<K, V, C> {
+    pub fn new_with_comparator(max_height: usize, cmp: C) -> Self {
+        Self::new_with(max_height, Box::new(GenHeight::new()), cmp, None)
     }
 
-    pub fn new_with_height_generator(
+    fn new_with(
         max_height: usize,
         height_generator: Box<dyn HeightGenerator + Send>,
+        cmp: C,
+        arena: Option<InArena<K, V>>,
     ) -> Self {
         SkipList {
             head_: SkipListNode::allocate_dummy(max_height),
             length_: 0,
             height_: 0,
             max_height_: max_height,
+            memory_usage_: SkipListNode::<K, V>::memory_size(max_height),
+            arena_: arena,
             height_generator,
+            cmp_: cmp,
         }
     }
 
-    fn dispose(&mut self) {
-        unsafe {
-            let mut current = self.head_;
-            let mut is_head = true;
+    pub fn length(&self) -> usize {
+        self.length_
+    }
 
-            while let Some(next) = (*current).next_mut(0) {
-                if !is_head {
-                    SkipListNode::free(current);
-                }
-                current = next;
-                is_head = false;
-            }
+    pub fn height(&self) -> usize {
+        self.height_
+    }
 
-            if !is_head {
-                SkipListNode::free(current);
-            }
+    pub fn max_height(&self) -> usize {
+        self.max_height_
+    }
 
-            // drop head nexts ptr
-            let nexts_ptr = &mut (*self.head_).nexts_ as *mut Vec<*mut SkipListNode<K, V>>;
-            nexts_ptr.drop_in_place();
+    /// The bytes the nodes take, not counting what the keys and the values
+    /// point to. In an arena it is all of its blocks, which hold those too.
+    pub fn memory_usage(&self) -> usize {
+        let arena_usage = self.arena_.as_ref().map_or(0, |x| x.arena_.memory_usage());
+        self.memory_usage_ + arena_usage
+    }
+
+    pub fn head(&self) -> Option<&SkipListNode<K, V>> {
+        SkipListNode::from_raw(self.head_)
+    }
+
+    pub fn head_mut(&self) -> Option<&mut SkipListNode<K, V>> {
+        SkipListNode::from_raw_mut(self.head_)
+    }
+
+    fn dispose(&mut self) {
+        let in_arena = self.arena_.is_some();
+        let mut current = unsafe { (*self.head_).nexts()[0] };
+        while !current.is_null() {
+            let next = unsafe { (*current).nexts()[0] };
+            SkipListNode::free(current, in_arena);
+            current = next;
         }
+        SkipListNode::free_dummy(self.head_);
     }
 
     pub fn clear(&mut self) {
@@ -84,19 +159,23 @@ impl<K, V> SkipList<K, V> {
         self.head_ = SkipListNode::allocate_dummy(self.max_height_);
         self.length_ = 0;
         self.height_ = 0;
+        self.memory_usage_ = SkipListNode::<K, V>::memory_size(self.max_height_);
+        if let Some(x) = self.arena_.as_mut() {
+            x.arena_.reset();
+        }
     }
 }
 
-impl<K: Ord, V> SkipList<K, V> {
+impl<K, V, C: Comparator<K>> SkipList<K, V, C> {
     pub fn get<Q: ?Sized>(&self, key: &Q) -> Option<&V>
     where
         K: Borrow<Q>,
-        Q: Ord,
+        C: Comparator<Q>,
     {
         let lower_bound = self.get_lower_bound(key);
 
         if let Some(next) = lower_bound.next(0) {
-            if next.key().borrow() == key {
+            if self.cmp_.compare(next.key().borrow(), key) == Ordering::Equal {
                 return Some(next.value());
             }
         }
@@ -104,15 +183,110 @@ impl<K: Ord, V> SkipList<K, V> {
         None
     }
 
+    /// Returns the first entry whose key is not less than `key`.
+    pub fn seek<Q: ?Sized>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        C: Comparator<Q>,
+    {
+        let lower_bound = self.get_lower_bound(key);
+
+        lower_bound.next(0).map(|next| (next.key(), next.value()))
+    }
+
+    /// Returns the last entry whose key is not greater than `key`.
+    pub fn seek_for_prev<Q: ?Sized>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        C: Comparator<Q>,
+    {
+        let lower_bound: &SkipListNode<K, V> = self.get_lower_bound(key);
+
+        if let Some(next) = lower_bound.next(0) {
+            if self.cmp_.compare(next.key().borrow(), key) == Ordering::Equal {
+                return Some((next.key(), next.value()));
+            }
+        }
+        if ptr::eq(lower_bound, self.head_) {
+            return None;
+        }
+        Some((lower_bound.key(), lower_bound.value()))
+    }
+
+    /// Iterates the entries whose keys are in `range`, from either end.
+    pub fn range<Q: ?Sized, R>(&self, range: R) -> SkipListRange<K, V, C>
+    where
+        K: Borrow<Q>,
+        C: Comparator<Q>,
+        R: RangeBounds<Q>,
+    {
+        let front = match range.start_bound() {
+            Bound::Included(k) => self.get_lower_bound(k).nexts()[0],
+            Bound::Excluded(k) => self.find_greater(k),
+            Bound::Unbounded => unsafe { (*self.head_).nexts()[0] },
+        };
+        let back = match range.end_bound() {
+            Bound::Included(k) => self.find_greater(k),
+            Bound::Excluded(k) => self.get_lower_bound(k).nexts()[0],
+            Bound::Unbounded => ptr::null_mut(),
+        };
+        // an empty range may start past its end
+        let is_empty = !back.is_null()
+            && (front.is_null()
+                || self
+                    .cmp_
+                    .compare(unsafe { &(*front).key_ }, unsafe { &(*back).key_ })
+                    == Ordering::Greater);
+        SkipListRange::new(self, if is_empty { back } else { front }, back)
+    }
+
+    /// Iterates the entries from the last one.
+    pub fn rev_iter(&self) -> Rev<SkipListRange<K, V, C>> {
+        self.range::<K, _>(..).rev()
+    }
+
+    /// Returns the first node whose key is greater than `key`, or null.
+    fn find_greater<Q: ?Sized>(&self, key: &Q) -> *mut SkipListNode<K, V>
+    where
+        K: Borrow<Q>,
+        C: Comparator<Q>,
+    {
+        let next = self.get_lower_bound(key).nexts()[0];
+        if !next.is_null()
+            && self.cmp_.compare(unsafe { (*next).key().borrow() }, key) == Ordering::Equal
+        {
+            unsafe { (*next).nexts()[0] }
+        } else {
+            next
+        }
+    }
+
+    /// Returns the last node before `node`, which is null for the end of the
+    /// list. The head is returned if there is none.
+    pub(crate) fn find_prev(&self, node: *mut SkipListNode<K, V>) -> *mut SkipListNode<K, V> {
+        if !node.is_null() {
+            return unsafe { self.get_lower_bound(&(*node).key_) as *mut _ };
+        }
+        let mut current_ptr = self.head_;
+        for i in (0..=self.height_).rev() {
+            unsafe {
+                while !(*current_ptr).nexts()[i].is_null() {
+                    current_ptr = (*current_ptr).nexts()[i];
+                }
+            }
+        }
+        current_ptr
+    }
+
     pub fn get_mut<Q: ?Sized>(&self, key: &Q) -> Option<&mut V>
     where
         K: Borrow<Q>,
-        Q: Ord,
+        C: Comparator<Q>,
     {
         let lower_bound = self.get_lower_bound(key);
 
         if let Some(next) = lower_bound.next_mut(0) {
-            if next.key().borrow() == key {
+            if self.cmp_.compare(next.key().borrow(), key) == Ordering::Equal {
                 return Some(next.value_mut());
             }
         }
@@ -126,24 +300,35 @@ impl<K: Ord, V> SkipList<K, V> {
         let (lower_bound, mut updates) = self.get_lower_bound_and_updates(&key);
 
         if let Some(next) = lower_bound.next_mut(0) {
-            if next.key() == &key {
+            if self.cmp_.compare(next.key(), &key) == Ordering::Equal {
+                let value = match &self.arena_ {
+                    Some(x) => (x.copy_value_)(&x.arena_, &value),
+                    None => value,
+                };
                 return Some(next.replace_value(value));
             }
         }
 
-        let node_ptr = SkipListNode::allocate(key, value, height);
+        let node_ptr = match &self.arena_ {
+            Some(x) => (x.allocate_)(&key, &value, height, &x.arena_),
+            None => SkipListNode::allocate(key, value, height),
+        };
 
         #[allow(clippy::needless_range_loop)]
         for i in 0..=height {
             let update = &mut updates[i];
             unsafe {
-                *((*node_ptr).nexts_.get_unchecked_mut(i)) = *(update.nexts_.get_unchecked_mut(i));
-                *(update.nexts_.get_unchecked_mut(i)) = node_ptr;
+                *((*node_ptr).nexts_mut().get_unchecked_mut(i)) =
+                    *(update.nexts_mut().get_unchecked_mut(i));
+                *(update.nexts_mut().get_unchecked_mut(i)) = node_ptr;
             }
         }
 
         self.height_ = ::std::cmp::max(self.height_, height);
         self.length_ += 1;
+        if self.arena_.is_none() {
+            self.memory_usage_ += SkipListNode::<K, V>::memory_size(height);
+        }
         None
     }
 
@@ -153,7 +338,7 @@ impl<K: Ord, V> SkipList<K, V> {
     ) -> (&mut SkipListNode<K, V>, Vec<&mut SkipListNode<K, V>>)
     where
         K: Borrow<Q>,
-        Q: Ord,
+        C: Comparator<Q>,
     {
         self.get_lower_bound_and_updates(key)
     }
@@ -164,7 +349,7 @@ impl<K: Ord, V> SkipList<K, V> {
     ) -> (&mut SkipListNode<K, V>, Vec<&mut SkipListNode<K, V>>)
     where
         K: Borrow<Q>,
-        Q: Ord,
+        C: Comparator<Q>,
     {
         let max_height = self.max_height_;
         let mut updates = Vec::with_capacity(max_height + 1);
@@ -184,7 +369,7 @@ impl<K: Ord, V> SkipList<K, V> {
 
             for i in (0..=self.height_).rev() {
                 while let Some(next) = (*current_ptr).next_mut(i) {
-                    if next.key().borrow() < key {
+                    if self.cmp_.compare(next.key().borrow(), key) == Ordering::Less {
                         current_ptr = next;
                     } else {
                         break;
@@ -201,14 +386,14 @@ impl<K: Ord, V> SkipList<K, V> {
     fn get_lower_bound<Q: ?Sized>(&self, key: &Q) -> &mut SkipListNode<K, V>
     where
         K: Borrow<Q>,
-        Q: Ord,
+        C: Comparator<Q>,
     {
         unsafe {
             let mut current_ptr = self.head_;
 
             for i in (0..=self.height_).rev() {
                 while let Some(next) = (*current_ptr).next_mut(i) {
-                    if next.key().borrow() < key {
+                    if self.cmp_.compare(next.key().borrow(), key) == Ordering::Less {
                         current_ptr = next;
                     } else {
                         break;
@@ -223,12 +408,12 @@ impl<K: Ord, V> SkipList<K, V> {
     pub fn remove<Q: ?Sized>(&mut self, key: &Q) -> Option<V>
     where
         K: Borrow<Q>,
-        Q: Ord,
+        C: Comparator<Q>,
     {
         let (lower_bound, mut updates) = self.get_lower_bound_and_updates(key);
 
         if let Some(next) = lower_bound.next_mut(0) {
-            if next.key().borrow() != key {
+            if self.cmp_.compare(next.key().borrow(), key) != Ordering::Equal {
                 return None;
             }
 
@@ -236,14 +421,19 @@ impl<K: Ord, V> SkipList<K, V> {
             for i in 0..=next.height() {
                 let update = &mut updates[i];
                 unsafe {
-                    *(update.nexts_.get_unchecked_mut(i)) = *(next.nexts_.get_unchecked_mut(i));
+                    *(update.nexts_mut().get_unchecked_mut(i)) =
+                        *(next.nexts_mut().get_unchecked_mut(i));
                 }
             }
 
-            let old_value = next.replace_value(unsafe { mem::uninitialized() });
-            SkipListNode::free(next);
+            let height = next.height();
+            let in_arena = self.arena_.is_some();
+            let old_value = SkipListNode::into_value(next, in_arena);
 
             self.length_ -= 1;
+            if !in_arena {
+                self.memory_usage_ -= SkipListNode::<K, V>::memory_size(height);
+            }
 
             return Some(old_value);
         }
@@ -251,7 +441,7 @@ impl<K: Ord, V> SkipList<K, V> {
     }
 }
 
-impl<K: Display, V: Display> Display for SkipList<K, V> {
+impl<K: Display, V: Display, C> Display for SkipList<K, V, C> {
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
         write!(f, "[")?;
         for (i, (k, v)) in self.iter().enumerate() {
@@ -264,7 +454,7 @@ impl<K: Display, V: Display> Display for SkipList<K, V> {
     }
 }
 
-impl<K, V> Debug for SkipList<K, V> {
+impl<K, V, C> Debug for SkipList<K, V, C> {
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
         write!(
             f,
@@ -275,9 +465,19 @@ impl<K, V> Debug for SkipList<K, V> {
     }
 }
 
-impl<K: Ord + Clone, V: Clone> Clone for SkipList<K, V> {
+impl<K: Clone, V: Clone, C: Comparator<K> + Clone> Clone for SkipList<K, V, C> {
     fn clone(&self) -> Self {
-        let mut copied: SkipList<K, V> = SkipList::new(self.max_height_);
+        let arena = self.arena_.as_ref().map(|x| InArena {
+            arena_: Arena::new(x.arena_.block_size()),
+            allocate_: x.allocate_,
+            copy_value_: x.copy_value_,
+        });
+        let mut copied = SkipList::new_with(
+            self.max_height_,
+            Box::new(GenHeight::new()),
+            self.cmp_.clone(),
+            arena,
+        );
         for (k, v) in self.iter() {
             copied.insert(k.clone(), v.clone());
         }
@@ -285,13 +485,13 @@ impl<K: Ord + Clone, V: Clone> Clone for SkipList<K, V> {
     }
 }
 
-impl<K, V> Drop for SkipList<K, V> {
+impl<K, V, C> Drop for SkipList<K, V, C> {
     fn drop(&mut self) {
         self.dispose();
     }
 }
 
-impl<K, V> SkipList<K, V> {
+impl<K, V, C> SkipList<K, V, C> {
     pub fn iter(&self) -> SkipListIter<K, V> {
         SkipListIter::new(self)
     }
@@ -304,10 +504,12 @@ impl<K, V> SkipList<K, V> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::arena::test::Bytes;
     use rand::prelude::*;
     use std::cmp::Ordering;
     use std::collections::HashSet;
     use std::fmt::Debug;
+    use std::mem;
 
     #[test]
     fn test_to_string() {
@@ -332,6 +534,89 @@ mod test {
         assert_eq!(3, list.length());
     }
 
+    #[test]
+    fn test_seek() {
+        let mut list = SkipList::new(10);
+        list.insert(1, 1);
+        list.insert(3, 3);
+        list.insert(5, 5);
+        assert_eq!(Some((&1, &1)), list.seek(&0));
+        assert_eq!(Some((&3, &3)), list.seek(&3));
+        assert_eq!(Some((&5, &5)), list.seek(&4));
+        assert_eq!(None, list.seek(&6));
+
+        assert_eq!(None, list.seek_for_prev(&0));
+        assert_eq!(Some((&1, &1)), list.seek_for_prev(&2));
+        assert_eq!(Some((&3, &3)), list.seek_for_prev(&3));
+        assert_eq!(Some((&5, &5)), list.seek_for_prev(&6));
+    }
+
+    #[test]
+    fn test_range() {
+        let mut list = SkipList::new(10);
+        for i in 0..10 {
+            list.insert(i * 2, i);
+        }
+        let keys = |range: SkipListRange<i32, i32>| range.map(|(k, _)| *k).collect::<Vec<_>>();
+        assert_eq!(vec![4, 6, 8], keys(list.range(3..10)));
+        assert_eq!(vec![4, 6, 8, 10], keys(list.range(4..=10)));
+        assert_eq!(vec![0, 2], keys(list.range(..3)));
+        assert_eq!(vec![16, 18], keys(list.range(15..)));
+        assert_eq!(
+            vec![6, 8],
+            keys(list.range((Bound::Excluded(4), Bound::Excluded(10))))
+        );
+        assert!(keys(list.range(5..5)).is_empty());
+        assert!(keys(list.range(7..6)).is_empty());
+        assert!(keys(list.range(19..)).is_empty());
+        assert_eq!(10, list.range::<i32, _>(..).count());
+
+        assert_eq!(
+            vec![8, 6, 4],
+            list.range(3..10).rev().map(|(k, _)| *k).collect::<Vec<_>>()
+        );
+        // from both ends, they meet in the middle
+        let mut range = list.range(2..=8);
+        assert_eq!(Some((&2, &1)), range.next());
+        assert_eq!(Some((&8, &4)), range.next_back());
+        assert_eq!(Some((&6, &3)), range.next_back());
+        assert_eq!(Some((&4, &2)), range.next());
+        assert_eq!(None, range.next());
+        assert_eq!(None, range.next_back());
+
+        assert_eq!(
+            (0..10).rev().map(|i| i * 2).collect::<Vec<_>>(),
+            list.rev_iter().map(|(k, _)| *k).collect::<Vec<_>>()
+        );
+        assert_eq!(None, SkipList::<i32, i32>::new(10).rev_iter().next());
+    }
+
+    struct Reverse;
+
+    impl Comparator<i32> for Reverse {
+        fn compare(&self, a: &i32, b: &i32) -> Ordering {
+            b.cmp(a)
+        }
+    }
+
+    #[test]
+    fn test_comparator() {
+        let mut list = SkipList::new_with_comparator(10, Reverse);
+        for i in 0..5 {
+            list.insert(i, i + 1);
+        }
+        assert_eq!("[(4, 5), (3, 4), (2, 3), (1, 2), (0, 1)]", list.to_string());
+        assert_eq!(Some(&3), list.get(&2));
+        assert_eq!(Some((&2, &3)), list.seek(&2));
+        assert_eq!(None, list.seek(&-1));
+        assert_eq!(Some((&0, &1)), list.seek_for_prev(&-1));
+        assert_eq!(None, list.seek_for_prev(&9));
+        let keys: Vec<_> = list.range(3..=1).map(|(k, _)| *k).collect();
+        assert_eq!(vec![3, 2, 1], keys);
+        assert_eq!(Some(2), list.remove(&1));
+        assert_eq!(None, list.get(&1));
+    }
+
     #[test]
     fn test_remove() {
         let mut list = SkipList::new(10);
@@ -409,6 +694,62 @@ mod test {
         assert_eq!(2, map.length());
     }
 
+    #[test]
+    fn test_memory_usage() {
+        let mut map: SkipList<u64, u64> = SkipList::new(10);
+        let empty = map.memory_usage();
+        assert!(empty > 0);
+        for i in 0..100 {
+            map.insert(i, i);
+        }
+        let full = map.memory_usage();
+        assert!(full - empty >= 100 * 3 * mem::size_of::<u64>());
+        // a replaced value takes no new node
+        map.insert(1, 2);
+        assert_eq!(full, map.memory_usage());
+        for i in 0..50 {
+            map.remove(&i);
+        }
+        assert!(map.memory_usage() < full);
+        map.clear();
+        assert_eq!(empty, map.memory_usage());
+    }
+
+    #[test]
+    fn test_arena() {
+        let value: &'static [u8] = b"value";
+        let mut map = SkipList::new_in_arena(10, Arena::new(1024));
+        let empty = map.memory_usage();
+        for i in 0..100u64 {
+            map.insert(i, Bytes(value));
+        }
+        // the value is copied next to the node, and counted with it
+        let v = map.get(&0).unwrap();
+        assert_eq!(value, v.0);
+        assert_ne!(value.as_ptr(), v.0.as_ptr());
+        let full = map.memory_usage();
+        assert_eq!(0, (full - empty) % 1024);
+        assert!(full - empty >= 100 * (SkipListNode::<u64, Bytes>::memory_size(0) + value.len()));
+
+        // a replaced value is copied too
+        let other: &'static [u8] = b"other";
+        assert_eq!(Some(Bytes(value)), map.insert(1, Bytes(other)));
+        assert_eq!(other, map.get(&1).unwrap().0);
+        assert_ne!(other.as_ptr(), map.get(&1).unwrap().0.as_ptr());
+        // a removed node stays in the arena
+        assert_eq!(Some(Bytes(other)), map.remove(&1));
+        assert_eq!(None, map.get(&1));
+        assert_eq!(99, map.length());
+        assert_eq!(full, map.memory_usage());
+
+        let copied = map.clone();
+        map.clear();
+        assert_eq!(empty, map.memory_usage());
+        assert_eq!(None, map.get(&99));
+        assert_eq!(Some(&Bytes(value)), copied.get(&99));
+        assert_eq!(99, copied.length());
+    }
+
     #[test]
     fn test_clone() {
         let n = 100;
@@ -1,11 +1,16 @@
+use crate::arena::Arena;
+use crate::arena::ArenaCopy;
 use crate::util::from_raw;
 use crate::util::from_raw_mut;
+use std::alloc::{dealloc, Layout};
 use std::mem;
 use std::ptr;
+use std::slice;
 
 #[derive(Debug)]
 pub struct SkipListNode<K, V> {
-    pub(crate) nexts_: Vec<*mut SkipListNode<K, V>>,
+    // a boxed slice, or in an arena right after the node
+    pub(crate) nexts_: *mut [*mut SkipListNode<K, V>],
     pub(crate) key_: K,
     pub(crate) value_: V,
 }
@@ -23,6 +28,14 @@ impl<K, V> SkipListNode<K, V> {
         &mut self.value_
     }
 
+    pub(crate) fn nexts(&self) -> &[*mut SkipListNode<K, V>] {
+        unsafe { &*self.nexts_ }
+    }
+
+    pub(crate) fn nexts_mut(&mut self) -> &mut [*mut SkipListNode<K, V>] {
+        unsafe { &mut *self.nexts_ }
+    }
+
     pub(crate) fn from_raw_mut<'a>(
         node_ptr: *mut SkipListNode<K, V>,
     ) -> Option<&'a mut SkipListNode<K, V>> {
@@ -45,28 +58,106 @@ impl<K, V> SkipListNode<K, V> {
 
     pub(crate) fn allocate(key: K, value: V, height: usize) -> *mut SkipListNode<K, V> {
         Box::into_raw(Box::new(SkipListNode {
-            nexts_: vec![ptr::null_mut(); height + 1],
+            nexts_: Box::into_raw(vec![ptr::null_mut(); height + 1].into_boxed_slice()),
             key_: key,
             value_: value,
         }))
     }
 
+    /// Takes the memory from `arena`: the node, its next pointers and the
+    /// bytes the key and the value point to, which are copied.
+    pub(crate) fn allocate_in_arena(
+        key: &K,
+        value: &V,
+        height: usize,
+        arena: &Arena,
+    ) -> *mut SkipListNode<K, V>
+    where
+        K: ArenaCopy,
+        V: ArenaCopy,
+    {
+        let (key_bytes, value_bytes) = (key.arena_bytes(), value.arena_bytes());
+        let (layout, nexts_offset) = Layout::new::<SkipListNode<K, V>>()
+            .extend(Layout::array::<*mut SkipListNode<K, V>>(height + 1).unwrap())
+            .unwrap();
+        let (layout, bytes_offset) = layout
+            .extend(Layout::array::<u8>(key_bytes.len() + value_bytes.len()).unwrap())
+            .unwrap();
+        let node = arena.allocate(layout);
+        unsafe {
+            let nexts = node.add(nexts_offset) as *mut *mut SkipListNode<K, V>;
+            for i in 0..=height {
+                ptr::write(nexts.add(i), ptr::null_mut());
+            }
+            let bytes = node.add(bytes_offset);
+            ptr::copy_nonoverlapping(key_bytes.as_ptr(), bytes, key_bytes.len());
+            let value_at = bytes.add(key_bytes.len());
+            ptr::copy_nonoverlapping(value_bytes.as_ptr(), value_at, value_bytes.len());
+
+            let node = node as *mut SkipListNode<K, V>;
+            ptr::write(
+                node,
+                SkipListNode {
+                    nexts_: ptr::slice_from_raw_parts_mut(nexts, height + 1),
+                    key_: key.in_arena(slice::from_raw_parts(bytes, key_bytes.len())),
+                    value_: value.in_arena(slice::from_raw_parts(value_at, value_bytes.len())),
+                },
+            );
+            node
+        }
+    }
+
+    /// The bytes a node of `height` takes, its key and value included but
+    /// not what they point to.
+    pub(crate) fn memory_size(height: usize) -> usize {
+        mem::size_of::<SkipListNode<K, V>>()
+            + (height + 1) * mem::size_of::<*mut SkipListNode<K, V>>()
+    }
+
     pub(crate) fn height(&self) -> usize {
-        self.nexts_.len() - 1
+        self.nexts().len() - 1
     }
 
-    pub(crate) fn free(node_ptr: *mut SkipListNode<K, V>) {
+    /// Drops the key and the value, the memory goes back to the heap unless
+    /// it is from an arena.
+    pub(crate) fn free(node_ptr: *mut SkipListNode<K, V>, in_arena: bool) {
         unsafe {
-            Box::from_raw(node_ptr);
+            ptr::drop_in_place(node_ptr);
+            if !in_arena {
+                Self::dealloc(node_ptr);
+            }
         }
     }
 
+    /// Moves the value out, and frees the node along with its key.
+    pub(crate) fn into_value(node_ptr: *mut SkipListNode<K, V>, in_arena: bool) -> V {
+        unsafe {
+            let value = ptr::read(&(*node_ptr).value_);
+            ptr::drop_in_place(&mut (*node_ptr).key_);
+            if !in_arena {
+                Self::dealloc(node_ptr);
+            }
+            value
+        }
+    }
+
+    /// Frees the dummy node of the head, whose key and value are not there.
+    pub(crate) fn free_dummy(node_ptr: *mut SkipListNode<K, V>) {
+        unsafe { Self::dealloc(node_ptr) };
+    }
+
+    /// Frees the memory of a node from the heap, and drops nothing.
+    unsafe fn dealloc(node_ptr: *mut SkipListNode<K, V>) {
+        drop(Box::from_raw((*node_ptr).nexts_));
+        dealloc(node_ptr as *mut u8, Layout::new::<SkipListNode<K, V>>());
+    }
+
     pub(crate) fn replace_value(&mut self, value: V) -> V {
         mem::replace(&mut self.value_, value)
     }
 
     pub fn next(&self, height: usize) -> Option<&SkipListNode<K, V>> {
-        self.nexts_.get(height).and_then(|ptr| {
+        self.nexts().get(height).and_then(|ptr| {
             if ptr.is_null() {
                 None
             } else {
@@ -76,7 +167,7 @@ impl<K, V> SkipListNode<K, V> {
     }
 
     pub fn next_mut(&mut self, height: usize) -> Option<&mut SkipListNode<K, V>> {
-        self.nexts_.get(height).and_then(|ptr| {
+        self.nexts().get(height).and_then(|ptr| {
             if ptr.is_null() {
                 None
             } else {
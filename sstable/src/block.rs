@@ -7,6 +7,7 @@ use crate::block_builder::BLOCK_CKSUM_LEN;
 use crate::block_builder::BLOCK_CTYPE_LEN;
 use crate::block_handle::BlockHandle;
 use crate::block_iter::BlockIter;
+use crate::block_iter::BlockIterState;
 use crate::error::MyResult;
 use crate::error::StatusCode;
 use crate::options::int_to_compress_type;
@@ -78,7 +79,15 @@ impl Block {
     }
 
     pub fn iter(&self) -> BlockIter {
-        BlockIter::new(&self.block, self.restarts_offset())
+        BlockIter::new(
+            &self.block,
+            self.restarts_offset(),
+            self.opt.comparator.as_ref(),
+        )
+    }
+
+    pub fn iter_with_state(&self, state: BlockIterState) -> BlockIter {
+        BlockIter::new_with_state(&self.block, state, self.opt.comparator.as_ref())
     }
 }
 
@@ -1,3 +1,4 @@
+use std::cmp::Ordering;
 use std::io::Seek;
 use std::io::SeekFrom;
 use std::io::Write;
@@ -12,7 +13,6 @@ use crate::error::MyResult;
 use crate::options::CompressType;
 use crate::options::Options;
 use crate::util::mask_crc;
-use crate::util::to_str;
 
 pub const BLOCK_CTYPE_LEN: usize = 1;
 pub const BLOCK_CKSUM_LEN: usize = 4;
@@ -63,13 +63,10 @@ impl BlockBuilder {
 
     pub fn add(&mut self, k: &[u8], v: &[u8]) {
         assert!(self.restart_count <= self.opt.block_restart_interval);
-        if !self.buffer.is_empty() && self.last_key.as_slice() > k {
-            println!(
-                "last: {}, key: {}",
-                to_str(self.last_key.as_slice()),
-                to_str(k)
-            );
-            assert!(self.buffer.is_empty() || self.last_key.as_slice() <= k);
+        if !self.buffer.is_empty()
+            && self.opt.comparator.compare(&self.last_key, k) == Ordering::Greater
+        {
+            panic!("out of order, last: {:?}, key: {:?}", self.last_key, k);
         }
 
         let mut shared = 0;
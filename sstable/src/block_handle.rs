@@ -1,10 +1,11 @@
 use integer_encoding::VarInt;
+use serde::{Deserialize, Serialize};
 
 /// Contains an offset and a length (or size); can be efficiently encoded in to varints. This is
 /// used typically as file-internal pointer in table (SSTable) files. For example, the index block
 /// in an SSTable is a block of (key = largest key in block) -> (value = encoded blockhandle of
 /// block).
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct BlockHandle {
     pub offset: usize,
     pub size: usize,
@@ -1,5 +1,8 @@
+use std::cmp::Ordering;
+
 use integer_encoding::{FixedInt, VarInt};
 
+use crate::comparator::Comparator;
 use crate::types::SsIterator;
 
 #[derive(Clone, Debug)]
@@ -35,17 +38,18 @@ impl BlockIterState {
 pub struct BlockIter<'a> {
     pub(crate) block: &'a [u8],
     pub(crate) state: BlockIterState,
+    cmp: &'a dyn Comparator,
 }
 
 impl<'a> BlockIter<'a> {
-    pub fn new(block: &'a [u8], restarts_offset: usize) -> Self {
+    pub fn new(block: &'a [u8], restarts_offset: usize, cmp: &'a dyn Comparator) -> Self {
         let state = BlockIterState::new(restarts_offset);
 
-        Self::new_with_state(block, state)
+        Self::new_with_state(block, state, cmp)
     }
 
-    pub fn new_with_state(block: &'a [u8], state: BlockIterState) -> Self {
-        Self { block, state }
+    pub fn new_with_state(block: &'a [u8], state: BlockIterState, cmp: &'a dyn Comparator) -> Self {
+        Self { block, state, cmp }
     }
 
     pub fn restart_count(&self) -> usize {
@@ -198,7 +202,7 @@ impl<'a> SsIterator for BlockIter<'a> {
         while left < right {
             let m = (left + right + 1) / 2;
             self.seek_to_restart_point(m);
-            if self.key() < to {
+            if self.cmp.compare(self.key(), to) == Ordering::Less {
                 left = m;
             } else {
                 right = m - 1;
@@ -210,13 +214,19 @@ impl<'a> SsIterator for BlockIter<'a> {
         self.state.next_offset = self.get_restart_point_offset(left);
 
         while self.advance() {
-            if self.key() >= to {
+            if self.cmp.compare(self.key(), to) != Ordering::Less {
                 break;
             }
         }
     }
 
     fn seek_to_last(&mut self) {
+        // an empty block has a restart point but no entry
+        if self.state.restarts_offset == 0 {
+            self.reset();
+            return;
+        }
+
         let restart_count = self.restart_count();
 
         if restart_count > 0 {
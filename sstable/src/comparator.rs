@@ -0,0 +1,44 @@
+use std::cmp::Ordering;
+
+use crate::util::find_short_succ;
+use crate::util::find_shortest_sep;
+
+/// The order of the keys of a table. A table records the name of the
+/// comparator it was built with, and can only be read with the same one.
+pub trait Comparator: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering;
+
+    /// Returns a key in `[a, b)`, preferably a short one, to separate the
+    /// blocks in the index. `a` itself is always right.
+    fn find_shortest_sep(&self, a: &[u8], _b: &[u8]) -> Vec<u8> {
+        a.to_vec()
+    }
+
+    /// Returns a key not less than `a`, preferably a short one.
+    fn find_short_succ(&self, a: &[u8]) -> Vec<u8> {
+        a.to_vec()
+    }
+}
+
+/// Orders the keys by their bytes, the default.
+pub struct BytewiseComparator;
+
+impl Comparator for BytewiseComparator {
+    fn name(&self) -> &str {
+        "sstable.BytewiseComparator"
+    }
+
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        a.cmp(b)
+    }
+
+    fn find_shortest_sep(&self, a: &[u8], b: &[u8]) -> Vec<u8> {
+        find_shortest_sep(a, b)
+    }
+
+    fn find_short_succ(&self, a: &[u8]) -> Vec<u8> {
+        find_short_succ(a)
+    }
+}
@@ -10,6 +10,7 @@ mod block;
 mod block_builder;
 mod block_iter;
 mod cache;
+mod comparator;
 mod footer;
 mod meta_block;
 mod options;
@@ -20,6 +21,7 @@ mod table_reader;
 mod types;
 mod writer;
 
+pub use crate::comparator::{BytewiseComparator, Comparator};
 pub use crate::error::{MyResult, Status, StatusCode};
 pub use crate::options::Options;
 pub use crate::table_builder::TableBuilder;
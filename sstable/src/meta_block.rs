@@ -9,6 +9,8 @@ use snap::Decoder;
 use snap::Encoder;
 
 use crate::block_handle::BlockHandle;
+use crate::comparator::BytewiseComparator;
+use crate::comparator::Comparator;
 use crate::reader;
 use crate::types::RandomAccess;
 use crate::MyResult;
@@ -18,14 +20,59 @@ pub struct MetaBlock {
     pub max_key: Vec<u8>,
     pub min_key: Vec<u8>,
     pub filter: ExportedCuckooFilter,
+    /// The name of the comparator the table is built with.
+    pub comparator: String,
+    /// Where the range deletion block is, `None` if the table has none.
+    pub range_deletion: Option<BlockHandle>,
+    /// Named values the user of the table records along with it.
+    pub properties: Vec<(String, Vec<u8>)>,
+}
+
+/// A meta block from before the properties.
+#[derive(Deserialize)]
+#[cfg_attr(test, derive(Serialize))]
+struct RangeDeletionMetaBlock {
+    max_key: Vec<u8>,
+    min_key: Vec<u8>,
+    filter: ExportedCuckooFilter,
+    comparator: String,
+    range_deletion: Option<BlockHandle>,
+}
+
+/// A meta block from before the range deletion block.
+#[derive(Deserialize)]
+struct ComparatorMetaBlock {
+    max_key: Vec<u8>,
+    min_key: Vec<u8>,
+    filter: ExportedCuckooFilter,
+    comparator: String,
+}
+
+/// A meta block from before the comparator was recorded, those tables are
+/// in byte order.
+#[derive(Deserialize)]
+struct LegacyMetaBlock {
+    max_key: Vec<u8>,
+    min_key: Vec<u8>,
+    filter: ExportedCuckooFilter,
 }
 
 impl MetaBlock {
-    pub fn new(max_key: Vec<u8>, min_key: Vec<u8>, filter: ExportedCuckooFilter) -> Self {
+    pub fn new(
+        max_key: Vec<u8>,
+        min_key: Vec<u8>,
+        filter: ExportedCuckooFilter,
+        comparator: String,
+        range_deletion: Option<BlockHandle>,
+        properties: Vec<(String, Vec<u8>)>,
+    ) -> Self {
         MetaBlock {
             max_key,
             min_key,
             filter,
+            comparator,
+            range_deletion,
+            properties,
         }
     }
 
@@ -34,10 +81,47 @@ impl MetaBlock {
         self.min_key = vec![];
         self.filter.values = vec![];
         self.filter.length = 0;
+        self.comparator.clear();
+        self.range_deletion = None;
+        self.properties.clear();
     }
 
     pub fn new_with_buffer<T: Into<Vec<u8>>>(buffer: T) -> MyResult<Self> {
-        Ok(deserialize(&buffer.into())?)
+        let buffer = buffer.into();
+        // the older layouts are prefixes of the newer ones, so the newest
+        // is tried first
+        if let Ok(meta_block) = deserialize(&buffer) {
+            return Ok(meta_block);
+        }
+        if let Ok(old) = deserialize::<RangeDeletionMetaBlock>(&buffer) {
+            return Ok(MetaBlock::new(
+                old.max_key,
+                old.min_key,
+                old.filter,
+                old.comparator,
+                old.range_deletion,
+                vec![],
+            ));
+        }
+        if let Ok(old) = deserialize::<ComparatorMetaBlock>(&buffer) {
+            return Ok(MetaBlock::new(
+                old.max_key,
+                old.min_key,
+                old.filter,
+                old.comparator,
+                None,
+                vec![],
+            ));
+        }
+        let legacy: LegacyMetaBlock = deserialize(&buffer)?;
+        Ok(MetaBlock::new(
+            legacy.max_key,
+            legacy.min_key,
+            legacy.filter,
+            BytewiseComparator.name().to_owned(),
+            None,
+            vec![],
+        ))
     }
 
     pub fn new_from_location(
@@ -60,3 +144,30 @@ impl MetaBlock {
         Ok(bh!(offset, size))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::hash_map::DefaultHasher;
+
+    use cuckoofilter::CuckooFilter;
+
+    use super::*;
+
+    #[test]
+    fn test_range_deletion_layout() -> MyResult<()> {
+        let mut filter = CuckooFilter::<DefaultHasher>::new();
+        filter.add(b"a")?;
+        let old = RangeDeletionMetaBlock {
+            max_key: b"b".to_vec(),
+            min_key: b"a".to_vec(),
+            filter: ExportedCuckooFilter::from(&filter),
+            comparator: BytewiseComparator.name().to_owned(),
+            range_deletion: Some(bh!(1, 2)),
+        };
+        let meta_block = MetaBlock::new_with_buffer(serialize(&old)?)?;
+        assert_eq!(b"b".to_vec(), meta_block.max_key);
+        assert_eq!(Some(bh!(1, 2)), meta_block.range_deletion);
+        assert!(meta_block.properties.is_empty());
+        Ok(())
+    }
+}
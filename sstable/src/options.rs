@@ -3,6 +3,8 @@ use std::sync::RwLock;
 
 use crate::block::Block;
 use crate::cache::Cache;
+use crate::comparator::BytewiseComparator;
+use crate::comparator::Comparator;
 
 const KB: usize = 1 << 10;
 const MB: usize = KB * KB;
@@ -31,6 +33,14 @@ pub struct Options {
     pub block_restart_interval: usize,
     pub block_cache: Arc<RwLock<Cache<Block>>>,
     pub compress_type: CompressType,
+    /// Maps a key to the part the filter is built on and `TableReader::find`
+    /// matches, the whole key by default.
+    pub filter_key: fn(&[u8]) -> &[u8],
+    pub comparator: Arc<dyn Comparator>,
+}
+
+fn whole_key(k: &[u8]) -> &[u8] {
+    k
 }
 
 impl Default for Options {
@@ -42,6 +52,8 @@ impl Default for Options {
                 BLOCK_CACHE_CAPACITY / BLOCK_MAX_SIZE,
             ))),
             compress_type: CompressType::Snappy,
+            filter_key: whole_key,
+            comparator: Arc::new(BytewiseComparator),
         }
     }
 }
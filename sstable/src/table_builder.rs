@@ -13,8 +13,6 @@ use crate::footer::Footer;
 use crate::footer::FULL_FOOTER_LENGTH;
 use crate::meta_block::MetaBlock;
 use crate::options::Options;
-use crate::util::find_short_succ;
-use crate::util::find_shortest_sep;
 
 pub struct TableBuilder {
     file: File,
@@ -24,9 +22,16 @@ pub struct TableBuilder {
     total_size_estimate_: usize,
     data_block: BlockBuilder,
     index_block: BlockBuilder,
+    range_deletion_block: BlockBuilder,
+    // the first key of the range deletion block, the bounds of a table
+    // with no entry
+    first_range_deletion: Option<Vec<u8>>,
+    // the bounds of a table with no entry set by the user, over the above
+    range_deletion_bounds: Option<(Vec<u8>, Vec<u8>)>,
     min_key: Option<Vec<u8>>,
     max_key: Option<Vec<u8>>,
     filter: CuckooFilter<DefaultHasher>,
+    properties: Vec<(String, Vec<u8>)>,
 }
 
 impl TableBuilder {
@@ -43,10 +48,14 @@ impl TableBuilder {
             offset: 0,
             total_size_estimate_: 0,
             data_block: BlockBuilder::new(opt.clone()),
-            index_block: BlockBuilder::new(opt),
+            index_block: BlockBuilder::new(opt.clone()),
+            range_deletion_block: BlockBuilder::new(opt),
+            first_range_deletion: None,
+            range_deletion_bounds: None,
             min_key: None,
             max_key: None,
             filter: CuckooFilter::new(),
+            properties: vec![],
         })
     }
 
@@ -74,7 +83,16 @@ impl TableBuilder {
             self.write_data_block(k)?;
         }
         self.data_block.add(k, v);
-        self.filter.add(k)?;
+        // keys sharing a filter key are adjacent, and a cuckoo filter only
+        // has room for a few copies of an item
+        let filter_key = self.opt.filter_key;
+        let is_dup = match &self.max_key {
+            Some(last_key) => filter_key(last_key) == filter_key(k),
+            None => false,
+        };
+        if !is_dup {
+            self.filter.add(filter_key(k))?;
+        }
         if self.min_key.is_none() {
             self.min_key = Some(k.to_vec());
         }
@@ -82,8 +100,38 @@ impl TableBuilder {
         Ok(())
     }
 
+    /// Adds an entry to the range deletion block, which is kept apart from
+    /// the entries and read whole. Its keys are added in order too, `k` is
+    /// where the range starts and `v` tells where it ends.
+    pub fn add_range_deletion(&mut self, k: &[u8], v: &[u8]) -> MyResult<()> {
+        self.total_size_estimate_ += k.len() + v.len();
+        self.range_deletion_block.add(k, v);
+        if self.first_range_deletion.is_none() {
+            self.first_range_deletion = Some(k.to_vec());
+        }
+        Ok(())
+    }
+
+    /// Sets the bounds of a table of range deletions only, which are the
+    /// key of its first range deletion otherwise. A table with entries is
+    /// bounded by its entries whatever is set.
+    pub fn set_range_deletion_bounds(&mut self, min_key: &[u8], max_key: &[u8]) {
+        self.range_deletion_bounds = Some((min_key.to_vec(), max_key.to_vec()));
+    }
+
+    /// Records a named value in the meta block, the reader of the table
+    /// gets it by `TableReader::property`. A later value of the name
+    /// replaces the earlier one.
+    pub fn set_property(&mut self, name: &str, value: &[u8]) {
+        self.properties.retain(|x| x.0 != name);
+        self.properties.push((name.to_owned(), value.to_vec()));
+    }
+
     fn write_data_block(&mut self, next_key: &[u8]) -> MyResult<()> {
-        let sep = find_shortest_sep(&self.data_block.last_key, next_key);
+        let sep = self
+            .opt
+            .comparator
+            .find_shortest_sep(&self.data_block.last_key, next_key);
 
         let bh = self.data_block.flush(&mut self.file, self.offset)?;
         self.offset = bh.offset + bh.size;
@@ -99,11 +147,41 @@ impl TableBuilder {
     }
 
     pub fn flush(&mut self) -> MyResult<()> {
-        self.write_data_block(&find_short_succ(&self.data_block.last_key))?;
+        // a table of range deletions only has no data block
+        if self.max_key.is_some() {
+            let succ = self
+                .opt
+                .comparator
+                .find_short_succ(&self.data_block.last_key);
+            self.write_data_block(&succ)?;
+        }
+        let range_deletion_bh = match &self.first_range_deletion {
+            Some(_) => {
+                let bh = self
+                    .range_deletion_block
+                    .flush(&mut self.file, self.offset)?;
+                self.offset = bh.offset + bh.size;
+                Some(bh)
+            }
+            None => None,
+        };
+        let (max_key, min_key) = match (&self.max_key, &self.min_key) {
+            (Some(max_key), Some(min_key)) => (max_key.clone(), min_key.clone()),
+            _ => match &self.range_deletion_bounds {
+                Some((min_key, max_key)) => (max_key.clone(), min_key.clone()),
+                None => {
+                    let k = self.first_range_deletion.clone().expect("max key");
+                    (k.clone(), k)
+                }
+            },
+        };
         let mut meta_block = MetaBlock::new(
-            self.max_key.clone().expect("max key"),
-            self.min_key.clone().expect("min key"),
+            max_key,
+            min_key,
             ExportedCuckooFilter::from(&self.filter),
+            self.opt.comparator.name().to_owned(),
+            range_deletion_bh,
+            self.properties.clone(),
         );
         let meta_bh = meta_block.flush(&mut self.file, self.offset)?;
         self.offset = meta_bh.offset + meta_bh.size;
@@ -1,3 +1,7 @@
+use std::ops::Deref;
+use std::ptr;
+use std::sync::Arc;
+
 use crate::block::Block;
 use crate::block_handle::BlockHandle;
 use crate::block_iter::BlockIter;
@@ -5,29 +9,68 @@ use crate::block_iter::BlockIterState;
 use crate::types::SsIterator;
 use crate::TableReader;
 
+/// The table of an iterator, borrowed or shared with it.
+enum TableRef<'a> {
+    Borrowed(&'a TableReader),
+    Shared(Arc<dyn AsRef<TableReader> + Send + Sync>),
+}
+
+impl<'a> Deref for TableRef<'a> {
+    type Target = TableReader;
+
+    fn deref(&self) -> &TableReader {
+        match self {
+            TableRef::Borrowed(table) => table,
+            TableRef::Shared(table) => table.as_ref().as_ref(),
+        }
+    }
+}
+
 pub struct TableIter<'a> {
-    table: &'a TableReader,
-    pub(crate) index_iter: BlockIter<'a>,
+    table: TableRef<'a>,
+    index_iter_state: BlockIterState,
     data_iter_state: BlockIterState,
     data_block: Option<Block>,
+    // the offset of `data_block` in the file
+    data_block_offset: usize,
 }
 
 impl<'a> TableIter<'a> {
     pub fn new(table: &'a TableReader) -> Self {
+        Self::with_table(TableRef::Borrowed(table))
+    }
+
+    fn with_table(table: TableRef<'a>) -> Self {
         Self {
+            index_iter_state: BlockIterState::new(table.index_block.restarts_offset()),
             table,
-            index_iter: table.index_block.iter(),
             data_iter_state: BlockIterState::new(0),
             data_block: None,
+            data_block_offset: 0,
         }
     }
 
+    pub(crate) fn is_over(&self, table: &TableReader) -> bool {
+        ptr::eq(self.table.deref(), table)
+    }
+
+    /// Runs `f` on the iterator of the index block, whose state is kept.
+    fn index_iter<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut BlockIter) -> R,
+    {
+        let mut iter = self
+            .table
+            .index_block
+            .iter_with_state(self.index_iter_state.clone());
+        let r = f(&mut iter);
+        self.index_iter_state = iter.state;
+        r
+    }
+
     fn data_iter(&self) -> Option<BlockIter> {
         match &self.data_block {
-            Some(ref v) => Some(BlockIter::new_with_state(
-                &v.block,
-                self.data_iter_state.clone(),
-            )),
+            Some(ref v) => Some(v.iter_with_state(self.data_iter_state.clone())),
             _ => None,
         }
     }
@@ -36,12 +79,19 @@ impl<'a> TableIter<'a> {
     fn print_info(&self) {
         println!("------------------------------------");
         println!("data_block: {}", self.data_block.is_some());
-        println!("index_iter: {:?}", self.index_iter.state);
+        println!("index_iter: {:?}", self.index_iter_state);
         println!("data_iter : {:?}", self.data_iter_state);
         println!("------------------------------------");
     }
 }
 
+impl TableIter<'static> {
+    /// An iterator which keeps the table alive, it needs no borrow.
+    pub fn new_shared(table: Arc<dyn AsRef<TableReader> + Send + Sync>) -> Self {
+        Self::with_table(TableRef::Shared(table))
+    }
+}
+
 impl<'a> SsIterator for TableIter<'a> {
     fn valid(&self) -> bool {
         let data_iter = self.data_iter();
@@ -57,15 +107,13 @@ impl<'a> SsIterator for TableIter<'a> {
             self.data_iter_state = data_iter.state.clone();
         }
 
-        if !self.index_iter.advance() {
-            return false;
-        }
-
-        if let Some((_k, v)) = self.index_iter.current_kv() {
+        let index = self.index_iter(|x| if x.advance() { x.current_kv() } else { None });
+        if let Some((_k, v)) = index {
             let (bh, _) = BlockHandle::decode(&v);
             match self.table.read_block(&bh) {
                 Ok(Some(block)) => {
                     self.data_iter_state = BlockIterState::new(block.restarts_offset());
+                    self.data_block_offset = bh.offset;
                     self.data_block = Some(block);
                     return self.advance();
                 }
@@ -89,17 +137,14 @@ impl<'a> SsIterator for TableIter<'a> {
             self.data_iter_state = data_iter.state.clone();
         }
 
-        if !self.index_iter.prev() {
-            self.reset();
-            return false;
-        }
-
-        if let Some((_k, v)) = self.index_iter.current_kv() {
+        let index = self.index_iter(|x| if x.prev() { x.current_kv() } else { None });
+        if let Some((_k, v)) = index {
             let (bh, _) = BlockHandle::decode(&v);
             if let Ok(Some(block)) = self.table.read_block(&bh) {
                 let mut iter = block.iter();
                 iter.seek_to_last();
                 self.data_iter_state = iter.state;
+                self.data_block_offset = bh.offset;
                 self.data_block = Some(block);
                 return true;
             }
@@ -118,32 +163,56 @@ impl<'a> SsIterator for TableIter<'a> {
     }
 
     fn reset(&mut self) {
-        self.index_iter.reset();
+        self.index_iter_state.reset();
         self.data_block = None;
         self.data_iter_state.reset();
     }
 
     fn seek(&mut self, key: &[u8]) {
+        // a seek into the block at hand, like the next one of a run of sorted
+        // keys, doesn't read it again
+        let current = self.data_block.take();
         self.reset();
-        self.index_iter.seek(key);
-        if let Some((_k, v)) = self.index_iter.current_kv() {
+        let index = self.index_iter(|x| {
+            x.seek(key);
+            x.current_kv()
+        });
+        if let Some((_k, v)) = index {
             let (bh, _) = BlockHandle::decode(&v);
-            if let Ok(Some(block)) = self.table.read_block(&bh) {
+            let block = match current {
+                Some(block) if self.data_block_offset == bh.offset => Some(block),
+                _ => self.table.read_block(&bh).ok().and_then(|x| x),
+            };
+            if let Some(block) = block {
                 let mut iter = block.iter();
                 iter.seek(key);
                 self.data_iter_state = iter.state;
+                self.data_block_offset = bh.offset;
                 self.data_block = Some(block);
             }
         }
+        // the key is past the last key of the block but not past its separator
+        if self.data_block.is_some() && !self.valid() {
+            self.advance();
+        }
     }
 
     fn seek_to_last(&mut self) {
         self.reset();
-        self.index_iter.seek_to_last();
-        self.index_iter.prev();
-        self.advance();
-
-        assert!(self.valid());
+        let index = self.index_iter(|x| {
+            x.seek_to_last();
+            x.current_kv()
+        });
+        if let Some((_k, v)) = index {
+            let (bh, _) = BlockHandle::decode(&v);
+            if let Ok(Some(block)) = self.table.read_block(&bh) {
+                let mut iter = block.iter();
+                iter.seek_to_last();
+                self.data_iter_state = iter.state;
+                self.data_block_offset = bh.offset;
+                self.data_block = Some(block);
+            }
+        }
     }
 }
 
@@ -321,4 +390,73 @@ mod test {
         assert_eq!(None, iter.current_kv());
         Ok(())
     }
+
+    #[test]
+    fn test_seek_to_last() -> MyResult<()> {
+        let path = Path::new("/tmp/test_table_seek_to_last");
+        let mut opt = Options::default();
+        opt.block_size = 200;
+        let mut t = TableBuilder::new(path, opt.clone())?;
+        let data = get_data();
+        for (k, v) in &data {
+            t.add(k.as_bytes(), v.as_bytes())?;
+        }
+        t.flush()?;
+        let t = TableReader::new(path, opt.clone())?;
+
+        let mut iter = TableIter::new(&t);
+        iter.seek_to_last();
+        assert_eq!(Some(data[N - 1].0.as_bytes().to_vec()), iter.current_k());
+        assert!(iter.prev());
+        assert_eq!(Some(data[N - 2].0.as_bytes().to_vec()), iter.current_k());
+        Ok(())
+    }
+
+    #[test]
+    fn test_seek_past_block() -> MyResult<()> {
+        let path = Path::new("/tmp/test_table_seek_past_block");
+        let mut opt = Options::default();
+        opt.block_size = 1;
+        let mut t = TableBuilder::new(path, opt.clone())?;
+        t.add(b"abc", b"1")?;
+        t.add(b"abz", b"2")?;
+        t.flush()?;
+        let t = TableReader::new(path, opt.clone())?;
+
+        // past the last key of the first block, not past its separator
+        let mut iter = TableIter::new(&t);
+        iter.seek(b"abd");
+        assert_eq!(Some(b"abz".to_vec()), iter.current_k());
+        iter.seek(b"b");
+        assert_eq!(None, iter.current_k());
+        Ok(())
+    }
+
+    #[test]
+    fn test_shared() -> MyResult<()> {
+        let path = Path::new("/tmp/test_table_shared");
+        let mut opt = Options::default();
+        opt.block_size = 20;
+        let mut t = TableBuilder::new(path, opt.clone())?;
+        let data = get_data();
+        for (k, v) in &data {
+            t.add(k.as_bytes(), v.as_bytes())?;
+        }
+        t.flush()?;
+
+        // the reader lives as long as the iterator
+        let mut iter = TableIter::new_shared(Arc::new(TableReader::new(path, opt.clone())?));
+        iter.seek(data[N / 2].0.as_bytes());
+        assert_eq!(Some(data[N / 2].0.as_bytes().to_vec()), iter.current_k());
+        assert!(iter.advance());
+        assert_eq!(
+            Some(data[N / 2 + 1].0.as_bytes().to_vec()),
+            iter.current_k()
+        );
+        iter.seek_to_last();
+        assert_eq!(Some(data[N - 1].0.as_bytes().to_vec()), iter.current_k());
+        assert!(iter.prev());
+        assert_eq!(Some(data[N - 2].0.as_bytes().to_vec()), iter.current_k());
+        Ok(())
+    }
 }
@@ -1,10 +1,11 @@
 use std::borrow::Borrow;
+use std::cmp::Ordering;
 use std::collections::hash_map::DefaultHasher;
 use std::fs::File;
 use std::path::Path;
-use std::rc::Rc;
 use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering::Relaxed;
+use std::sync::Arc;
 
 use cuckoofilter::CuckooFilter;
 use integer_encoding::FixedIntWriter;
@@ -13,6 +14,7 @@ use crate::block::Block;
 use crate::block_handle::BlockHandle;
 use crate::cache;
 use crate::error::MyResult;
+use crate::error::StatusCode;
 use crate::footer::Footer;
 use crate::footer::FULL_FOOTER_LENGTH;
 use crate::meta_block::MetaBlock;
@@ -23,7 +25,7 @@ use crate::types::SsIterator;
 use crate::util::write_unlock;
 
 pub struct TableReader {
-    file: Rc<Box<dyn RandomAccess>>,
+    file: Arc<Box<dyn RandomAccess + Send + Sync>>,
     file_size: usize,
     opt: Options,
 
@@ -36,6 +38,8 @@ pub struct TableReader {
     file_name_: String,
 
     seek_miss_count_: AtomicUsize,
+    range_deletions_: Vec<(Vec<u8>, Vec<u8>)>,
+    properties_: Vec<(String, Vec<u8>)>,
 
     filter_: CuckooFilter<DefaultHasher>,
 }
@@ -51,7 +55,29 @@ impl TableReader {
         }
         let footer = Footer::read(&f, size - FULL_FOOTER_LENGTH)?;
         let meta_block = MetaBlock::new_from_location(&f, &footer.meta_index())?.0;
+        if meta_block.comparator != opt.comparator.name() {
+            return err!(
+                StatusCode::InvalidData,
+                format!(
+                    "the table is built with {}, not with {}",
+                    meta_block.comparator,
+                    opt.comparator.name()
+                )
+            );
+        }
         let index_block = Block::new_from_location(&f, &footer.index(), opt.clone())?.0;
+        let range_deletions = match &meta_block.range_deletion {
+            Some(bh) => {
+                let block = Block::new_from_location(&f, bh, opt.clone())?.0;
+                let mut iter = block.iter();
+                let mut range_deletions = vec![];
+                while let Some(kv) = iter.next() {
+                    range_deletions.push(kv);
+                }
+                range_deletions
+            }
+            None => vec![],
+        };
         let metadata = f.metadata()?;
         let size_ = metadata.len() as usize;
         let file_name_ = path
@@ -62,7 +88,7 @@ impl TableReader {
             .expect("file name to str")
             .to_owned();
         Ok(TableReader {
-            file: Rc::new(Box::new(f)),
+            file: Arc::new(Box::new(f)),
             file_size: size,
             cache_id: write_unlock(&opt.block_cache).new_cache_id(),
             footer,
@@ -73,6 +99,8 @@ impl TableReader {
             size_,
             file_name_,
             seek_miss_count_: AtomicUsize::new(0),
+            range_deletions_: range_deletions,
+            properties_: meta_block.properties,
             filter_: meta_block.filter.into(),
         })
     }
@@ -101,6 +129,19 @@ impl TableReader {
         self.size_
     }
 
+    /// The entries of the range deletion block, in order.
+    pub fn range_deletions(&self) -> &[(Vec<u8>, Vec<u8>)] {
+        &self.range_deletions_
+    }
+
+    /// The value recorded by `TableBuilder::set_property`.
+    pub fn property(&self, name: &str) -> Option<&[u8]> {
+        self.properties_
+            .iter()
+            .find(|x| x.0 == name)
+            .map(|x| &x.1[..])
+    }
+
     pub fn file_name(&self) -> &String {
         &self.file_name_
     }
@@ -140,29 +181,78 @@ impl TableReader {
         K: ?Sized + Borrow<[u8]>,
     {
         let k = k.borrow();
-        if k < self.min_key() || k > self.max_key() {
+        let cmp = &self.opt.comparator;
+        if cmp.compare(k, self.min_key()) == Ordering::Less
+            || cmp.compare(k, self.max_key()) == Ordering::Greater
+        {
             return Ok(None);
         }
-        if !self.filter_.contains(k) {
+        if !self.filter_.contains((self.opt.filter_key)(k)) {
             self.incr_seek_miss_count();
             return Ok(None);
         }
         let mut iter = self.iter();
         iter.seek(k);
         if let Some(key) = iter.current_k() {
-            if &key[..] == k {
+            if cmp.compare(&key, k) == Ordering::Equal {
                 return Ok(iter.current_v());
             }
         }
         self.incr_seek_miss_count();
         Ok(None)
     }
+
+    /// Returns the first entry not less than `k` which has the same filter
+    /// key as `k`.
+    pub fn find<K>(&self, k: &K) -> MyResult<Option<(Vec<u8>, Vec<u8>)>>
+    where
+        K: ?Sized + Borrow<[u8]>,
+    {
+        self.find_in(&mut self.iter(), k)
+    }
+
+    /// Like `find`, through an iterator of the table. It keeps the block it
+    /// read last, so the finds of a run of sorted keys read each block once.
+    pub fn find_in<K>(&self, iter: &mut TableIter, k: &K) -> MyResult<Option<(Vec<u8>, Vec<u8>)>>
+    where
+        K: ?Sized + Borrow<[u8]>,
+    {
+        debug_assert!(iter.is_over(self));
+        let k = k.borrow();
+        let filter_key = self.opt.filter_key;
+        let fk = filter_key(k);
+        let cmp = &self.opt.comparator;
+        if cmp.compare(fk, filter_key(self.min_key())) == Ordering::Less
+            || cmp.compare(fk, filter_key(self.max_key())) == Ordering::Greater
+        {
+            return Ok(None);
+        }
+        if !self.filter_.contains(fk) {
+            self.incr_seek_miss_count();
+            return Ok(None);
+        }
+        iter.seek(k);
+        if let Some((key, v)) = iter.current_kv() {
+            if filter_key(&key) == fk {
+                return Ok(Some((key, v)));
+            }
+        }
+        self.incr_seek_miss_count();
+        Ok(None)
+    }
+}
+
+impl AsRef<TableReader> for TableReader {
+    fn as_ref(&self) -> &TableReader {
+        self
+    }
 }
 
 #[cfg(test)]
 mod test {
     use std::time;
 
+    use crate::comparator::Comparator;
     use crate::table_builder::TableBuilder;
     use crate::util::to_str;
 
@@ -257,4 +347,162 @@ mod test {
         assert!(first_cost > second_cost);
         Ok(())
     }
+
+    struct ReverseComparator;
+
+    impl Comparator for ReverseComparator {
+        fn name(&self) -> &str {
+            "test.ReverseComparator"
+        }
+
+        fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+            b.cmp(a)
+        }
+    }
+
+    #[test]
+    fn test_comparator() -> MyResult<()> {
+        let path = Path::new("/tmp/test_table_reader_comparator");
+        let mut opt = Options::default();
+        opt.block_size = 20;
+        opt.comparator = Arc::new(ReverseComparator);
+        let mut data = get_data();
+        data.sort_by(|a, b| b.0.cmp(&a.0));
+        let mut t = TableBuilder::new(path, opt.clone())?;
+        for (k, v) in &data {
+            t.add(k.as_bytes(), v.as_bytes())?;
+        }
+        t.flush()?;
+
+        let t = TableReader::new(path, opt.clone())?;
+        assert_eq!(data[0].0.as_bytes(), &t.min_key()[..]);
+        for (k, v) in &data {
+            assert_eq!(Some(v.as_bytes().to_vec()), t.get(k.as_bytes())?);
+        }
+        assert_eq!(None, t.get("zzz".as_bytes())?);
+        // the first key not after it is the greatest one not above it
+        let target = "prefix_key5z".as_bytes();
+        let mut iter = t.iter();
+        iter.seek(target);
+        assert!(&iter.current_k().unwrap()[..] < target);
+        assert!(iter.prev());
+        assert!(&iter.current_k().unwrap()[..] > target);
+        assert_eq!(data.len(), t.iter().count());
+
+        // the order is not the one the table is in
+        let status = TableReader::new(path, Options::default()).err().unwrap();
+        assert_eq!(StatusCode::InvalidData, status.code);
+        Ok(())
+    }
+
+    #[test]
+    fn test_find() -> MyResult<()> {
+        let path = Path::new("/tmp/test_table_reader_find");
+        let mut opt = Options::default();
+        opt.block_size = 20;
+        // the last byte is a version of the key
+        opt.filter_key = |k| &k[..k.len() - 1];
+        let mut t = TableBuilder::new(path, opt.clone())?;
+        for (k, v) in &[("a5", "a5"), ("a9", "a9"), ("b7", "b7"), ("c8", "c8")] {
+            t.add(k.as_bytes(), v.as_bytes())?;
+        }
+        t.flush()?;
+        let t = TableReader::new(path, opt.clone())?;
+        let found = |k: &str| {
+            t.find(k.as_bytes())
+                .unwrap()
+                .map(|(k, _)| String::from_utf8(k).unwrap())
+        };
+        assert_eq!(Some("a5".to_owned()), found("a0"));
+        assert_eq!(Some("a9".to_owned()), found("a6"));
+        assert_eq!(None, found("b8"));
+        assert_eq!(Some("b7".to_owned()), found("b0"));
+        assert_eq!(None, found("d0"));
+
+        // through one iterator, the keys in order or not
+        let mut iter = t.iter();
+        for k in &["a0", "a6", "a9", "b0", "b8", "c0", "d0", "a0", "c8"] {
+            assert_eq!(t.find(k.as_bytes())?, t.find_in(&mut iter, k.as_bytes())?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_range_deletions() -> MyResult<()> {
+        let path = Path::new("/tmp/test_table_reader_range_deletions");
+        let mut t = TableBuilder::new(path, Options::default())?;
+        t.add(b"b", b"b")?;
+        t.add_range_deletion(b"a", b"c")?;
+        t.add_range_deletion(b"d", b"f")?;
+        t.flush()?;
+        let t = TableReader::new(path, Options::default())?;
+        assert_eq!(b"b", &t.min_key()[..]);
+        assert_eq!(b"b", &t.max_key()[..]);
+        assert_eq!(
+            &[
+                (b"a".to_vec(), b"c".to_vec()),
+                (b"d".to_vec(), b"f".to_vec())
+            ],
+            t.range_deletions()
+        );
+        assert_eq!(1, t.iter().count());
+
+        // no entry, the table is bounded by its first range deletion
+        let mut t = TableBuilder::new(path, Options::default())?;
+        t.add_range_deletion(b"d", b"f")?;
+        t.flush()?;
+        let t = TableReader::new(path, Options::default())?;
+        assert_eq!(b"d", &t.min_key()[..]);
+        assert_eq!(b"d", &t.max_key()[..]);
+        assert_eq!(1, t.range_deletions().len());
+        assert_eq!(None, t.get(b"d")?);
+        assert_eq!(0, t.iter().count());
+        let mut iter = t.iter();
+        iter.seek_to_last();
+        assert!(!iter.valid());
+        iter.seek(b"a");
+        assert!(!iter.valid());
+
+        // bounds set for no entry, or for entries which bound the table
+        let mut t = TableBuilder::new(path, Options::default())?;
+        t.add_range_deletion(b"d", b"f")?;
+        t.set_range_deletion_bounds(b"e", b"g");
+        t.flush()?;
+        let t = TableReader::new(path, Options::default())?;
+        assert_eq!(b"e", &t.min_key()[..]);
+        assert_eq!(b"g", &t.max_key()[..]);
+        let mut t = TableBuilder::new(path, Options::default())?;
+        t.add(b"b", b"b")?;
+        t.add_range_deletion(b"d", b"f")?;
+        t.set_range_deletion_bounds(b"e", b"g");
+        t.flush()?;
+        let t = TableReader::new(path, Options::default())?;
+        assert_eq!(b"b", &t.min_key()[..]);
+        assert_eq!(b"b", &t.max_key()[..]);
+
+        // no range deletion
+        let mut t = TableBuilder::new(path, Options::default())?;
+        t.add(b"b", b"b")?;
+        t.flush()?;
+        assert!(TableReader::new(path, Options::default())?
+            .range_deletions()
+            .is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_properties() -> MyResult<()> {
+        let path = Path::new("/tmp/test_table_reader_properties");
+        let mut t = TableBuilder::new(path, Options::default())?;
+        t.add(b"a", b"a")?;
+        t.set_property("p", b"1");
+        t.set_property("q", b"2");
+        t.set_property("p", b"3");
+        t.flush()?;
+        let t = TableReader::new(path, Options::default())?;
+        assert_eq!(Some(&b"3"[..]), t.property("p"));
+        assert_eq!(Some(&b"2"[..]), t.property("q"));
+        assert_eq!(None, t.property("r"));
+        Ok(())
+    }
 }